@@ -0,0 +1,226 @@
+//! `#[derive(GtsLogEvent)]`, see `gts_logger::event`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Generics, Type};
+
+/// Implements `gts_logger::event::GtsLogEvent` for struct or enum.
+/// Every field must implement `GtsLogEvent`, type must be `Copy`.
+#[proc_macro_derive(GtsLogEvent)]
+pub fn derive_log_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = add_bounds(input.generics.clone());
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let event = quote!(::gts_logger::event);
+
+    let mut field_types = Vec::new();
+    let (schema, encode, decode) = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, construct) = bindings(&data.fields);
+            field_types.extend(data.fields.iter().map(|field| field.ty.clone()));
+            let encode_fields = encode_fields(&data.fields);
+            (
+                format!(
+                    "struct {}{}",
+                    type_name(&input),
+                    fields_schema(&data.fields)
+                ),
+                quote! {
+                    let #name #pattern = self;
+                    #encode_fields
+                },
+                quote! { Ok(#name #construct) },
+            )
+        }
+        Data::Enum(data) => {
+            let mut variants = Vec::new();
+            let mut encode_arms = Vec::new();
+            let mut decode_arms = Vec::new();
+            for (idx, variant) in data.variants.iter().enumerate() {
+                let vname = &variant.ident;
+                let idx = idx as u64;
+                let (pattern, construct) = bindings(&variant.fields);
+                let encode_fields = encode_fields(&variant.fields);
+                field_types.extend(variant.fields.iter().map(|field| field.ty.clone()));
+                variants.push(format!("{}{}", vname, fields_schema(&variant.fields)));
+                encode_arms.push(quote! {
+                    #name::#vname #pattern => {
+                        #event::write_varint(#idx, dst);
+                        #encode_fields
+                    }
+                });
+                decode_arms.push(quote! { #idx => Ok(#name::#vname #construct), });
+            }
+            let schema = if variants.is_empty() {
+                format!("enum {} {{}}", type_name(&input))
+            } else {
+                format!("enum {} {{ {} }}", type_name(&input), variants.join(", "))
+            };
+            let unknown = format!("unknown variant {{}} of {}", name);
+            (
+                schema,
+                quote! {
+                    match self {
+                        #(#encode_arms)*
+                    }
+                },
+                quote! {
+                    match #event::read_varint(src)? {
+                        #(#decode_arms)*
+                        idx => Err(::gts_logger::error::GtsLoggerError::DecodeError(
+                            format!(#unknown, idx),
+                        )),
+                    }
+                },
+            )
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new(
+                data.union_token.span(),
+                "GtsLogEvent can't be derived for union",
+            ))
+        }
+    };
+
+    let copy_check = quote_spanned! {name.span()=>
+        #[allow(dead_code)]
+        const _: () = {
+            fn assert_copy<T: ::core::marker::Copy>() {}
+            fn check #impl_generics () #where_clause {
+                assert_copy::<#name #ty_generics>();
+            }
+        };
+    };
+
+    Ok(quote! {
+        #copy_check
+
+        impl #impl_generics #event::GtsLogEvent for #name #ty_generics #where_clause {
+            const SCHEMA: &'static str = #schema;
+            const FINGERPRINT: u64 = {
+                let mut hash = #event::fnv1a(Self::SCHEMA.as_bytes());
+                #(hash = #event::mix_fingerprint(
+                    hash,
+                    <#field_types as #event::GtsLogEvent>::FINGERPRINT,
+                );)*
+                hash
+            };
+
+            fn schema(out: &mut ::std::vec::Vec<&'static str>) {
+                if out.contains(&Self::SCHEMA) {
+                    return;
+                }
+                out.push(Self::SCHEMA);
+                #(<#field_types as #event::GtsLogEvent>::schema(out);)*
+            }
+
+            #[allow(unused_variables)]
+            fn encode(&self, dst: &mut ::std::vec::Vec<u8>) {
+                #encode
+            }
+
+            #[allow(unused_variables)]
+            fn decode(
+                src: &mut &[u8],
+            ) -> ::core::result::Result<Self, ::gts_logger::error::GtsLoggerError> {
+                #decode
+            }
+        }
+    })
+}
+
+/// Adds `T: GtsLogEvent` bound to every type parameter.
+fn add_bounds(mut generics: Generics) -> Generics {
+    for param in generics.type_params_mut() {
+        param
+            .bounds
+            .push(parse_quote!(::gts_logger::event::GtsLogEvent));
+    }
+    generics
+}
+
+/// Pattern binding fields to `__fN` and expression constructing value from decoded fields.
+fn bindings(fields: &Fields) -> (TokenStream2, TokenStream2) {
+    let vars: Vec<_> = (0..fields.len())
+        .map(|idx| format_ident!("__f{}", idx))
+        .collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let decode = quote!(::gts_logger::event::GtsLogEvent > ::decode(src)?);
+    match fields {
+        Fields::Named(named) => {
+            let names: Vec<_> = named.named.iter().map(|field| &field.ident).collect();
+            (
+                quote!({ #(#names: #vars),* }),
+                quote!({ #(#names: <#types as #decode),* }),
+            )
+        }
+        Fields::Unnamed(_) => (quote!((#(#vars),*)), quote!((#(<#types as #decode),*))),
+        Fields::Unit => (quote!(), quote!()),
+    }
+}
+
+fn encode_fields(fields: &Fields) -> TokenStream2 {
+    let vars = (0..fields.len()).map(|idx| format_ident!("__f{}", idx));
+    quote! {
+        #(::gts_logger::event::GtsLogEvent::encode(#vars, dst);)*
+    }
+}
+
+fn type_name(input: &DeriveInput) -> String {
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    format!(
+        "{}{}",
+        input.ident,
+        normalize(ty_generics.to_token_stream())
+    )
+}
+
+fn fields_schema(fields: &Fields) -> String {
+    let field_schema = |ty: &Type| normalize(ty.to_token_stream());
+    match fields {
+        Fields::Named(named) => {
+            let items: Vec<_> = named
+                .named
+                .iter()
+                .map(|field| {
+                    format!(
+                        "{}: {}",
+                        field.ident.as_ref().unwrap(),
+                        field_schema(&field.ty)
+                    )
+                })
+                .collect();
+            format!(" {{ {} }}", items.join(", "))
+        }
+        Fields::Unnamed(unnamed) => {
+            let items: Vec<_> = unnamed
+                .unnamed
+                .iter()
+                .map(|field| field_schema(&field.ty))
+                .collect();
+            format!("({})", items.join(", "))
+        }
+        Fields::Unit => String::new(),
+    }
+}
+
+/// Token string without spacing of proc_macro2, e.g. `ArrayString<16>`.
+fn normalize(tokens: TokenStream2) -> String {
+    tokens
+        .to_string()
+        .split_whitespace()
+        .collect::<String>()
+        .replace(',', ", ")
+        .replace(';', "; ")
+        .replace(':', ": ")
+        .replace(": : ", "::")
+}
@@ -1,5 +1,6 @@
 use arrayvec::ArrayString;
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use gts_logger::level::{LevelThreshold, LogLevel, LogLevelFilter};
 use gts_logger::logbackend::dualthread::DualThreadLogBacked;
 use gts_logger::logclient::LogClient;
 use minstant::Instant;
@@ -51,8 +52,8 @@ fn bench_dualthread(c: &mut Criterion) {
             || (),
             |_| {
                 for _ in 0..1000 {
-                    let _res = log_client.log(event).unwrap();
-                    black_box(_res);
+                    let _res = log_client.log(event);
+                    black_box(_res).unwrap();
                 }
             },
             BatchSize::NumIterations(50),
@@ -64,8 +65,8 @@ fn bench_dualthread(c: &mut Criterion) {
             || (),
             |_| {
                 for _ in 0..1000 {
-                    let _res = log_client.log(event).unwrap();
-                    black_box(_res);
+                    let _res = log_client.log(event);
+                    black_box(_res).unwrap();
                 }
             },
             BatchSize::NumIterations(50),
@@ -77,8 +78,8 @@ fn bench_dualthread(c: &mut Criterion) {
             || (),
             |_| {
                 for _ in 0..1000 {
-                    let _res = log_client.log_same(event).unwrap();
-                    black_box(_res);
+                    let _res = log_client.log_same(event);
+                    black_box(_res).unwrap();
                 }
             },
             BatchSize::NumIterations(50),
@@ -90,9 +91,9 @@ fn bench_dualthread(c: &mut Criterion) {
             || (),
             |_| {
                 for _ in 0..1000 {
-                    let _res = log_client.log(event).unwrap();
+                    let _res = log_client.log(event);
                     black_box(Instant::now().as_unix_nanos(&anc));
-                    black_box(_res);
+                    black_box(_res).unwrap();
                 }
             },
             BatchSize::NumIterations(50),
@@ -111,8 +112,8 @@ fn bench_dualthread(c: &mut Criterion) {
         b.iter_batched(
             || (),
             |_| {
-                let _res = log_client.log(event).unwrap();
-                black_box(_res);
+                let _res = log_client.log(event);
+                black_box(_res).unwrap();
             },
             BatchSize::NumIterations(50000),
         );
@@ -124,8 +125,8 @@ fn bench_dualthread(c: &mut Criterion) {
             b.iter_batched(
                 || (),
                 |_| {
-                    let _res = log_client.log_same(event).unwrap();
-                    black_box(_res);
+                    let _res = log_client.log_same(event);
+                    black_box(_res).unwrap();
                 },
                 BatchSize::NumIterations(50000),
             );
@@ -138,9 +139,9 @@ fn bench_dualthread(c: &mut Criterion) {
             b.iter_batched(
                 || (),
                 |_| {
-                    let _res = log_client.log(event).unwrap();
+                    let _res = log_client.log(event);
                     black_box(Instant::now().as_unix_nanos(&anc));
-                    black_box(_res);
+                    black_box(_res).unwrap();
                 },
                 BatchSize::NumIterations(50000),
             );
@@ -148,6 +149,21 @@ fn bench_dualthread(c: &mut Criterion) {
     );
 
     group.finish();
+
+    static THRESHOLD: LevelThreshold = LevelThreshold::new(LogLevelFilter::Info);
+    let log_client = log_client.with_threshold(&THRESHOLD);
+    let mut group = c.benchmark_group("dualthread filtered");
+
+    group.bench_function("log below threshold (1000 call log per iter)", |b| {
+        b.iter(|| {
+            for _ in 0..1000 {
+                let _res = log_client.log_at(black_box(LogLevel::Debug), event);
+                black_box(_res).unwrap();
+            }
+        });
+    });
+
+    group.finish();
 }
 
 criterion_group!(benches, bench_dualthread);
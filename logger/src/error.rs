@@ -6,6 +6,15 @@ pub enum GtsLoggerError {
     #[error("common error (({0})")]
     CommonError(String),
 
+    #[error("timeout")]
+    Timeout,
+
+    #[error("decode error ({0})")]
+    DecodeError(String),
+
+    #[error("StdIoError error")]
+    StdIoError(#[from] std::io::Error),
+
     #[error("GtsTransportError")]
     TransportWouldBlock(#[from] GtsTransportError),
 }
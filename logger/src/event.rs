@@ -0,0 +1,376 @@
+//! Compact binary encoding of log event types, see [`GtsLogEvent`].
+//!
+//! `#[derive(GtsLogEvent)]` checks that type is `Copy`, generates its schema description
+//! (definition as written in source) and fingerprint (hash of schema and fingerprints of
+//! field types), so file with events could be decoded by other process or offline tool.
+//!
+//! Encoding (little-endian): integers and floats by their size, bool/char as u8/u32,
+//! `ArrayString<N>` as varint length + utf8 bytes, `[T; N]` as N values, enum as
+//! varint index of variant + its fields, struct as its fields in order.
+//!
+//! # Examples
+//!
+//! ```
+//! use arrayvec::ArrayString;
+//! use gts_logger::event::{decode_exact, GtsLogEvent};
+//!
+//! #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+//! struct Fill {
+//!     qty: u32,
+//!     venue: ArrayString<8>,
+//! }
+//!
+//! #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+//! enum OrderEvent {
+//!     Sent { id: u64 },
+//!     Filled(Fill),
+//!     Canceled,
+//! }
+//!
+//! let event = OrderEvent::Filled(Fill {
+//!     qty: 7,
+//!     venue: ArrayString::from("xnys").unwrap(),
+//! });
+//! let mut buf = Vec::new();
+//! event.encode(&mut buf);
+//! assert_eq!(buf.len(), 1 + 4 + 1 + 4);
+//! assert_eq!(decode_exact::<OrderEvent>(&buf)?, event);
+//!
+//! assert_eq!(
+//!     OrderEvent::SCHEMA,
+//!     "enum OrderEvent { Sent { id: u64 }, Filled(Fill), Canceled }"
+//! );
+//! assert_ne!(OrderEvent::FINGERPRINT, Fill::FINGERPRINT);
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+
+use crate::error::GtsLoggerError;
+use arrayvec::ArrayString;
+pub use gts_logger_derive::GtsLogEvent;
+pub use gts_transport::wire::fnv1a;
+
+/// Event type with stable binary encoding, usually implemented by derive.
+pub trait GtsLogEvent: Copy + Sized {
+    /// Definition of type, empty for builtin types.
+    const SCHEMA: &'static str;
+    /// Hash of schema of type and all nested types.
+    const FINGERPRINT: u64;
+
+    /// Appends schemas of type and nested types, each one once.
+    fn schema(_out: &mut Vec<&'static str>) {}
+
+    fn encode(&self, dst: &mut Vec<u8>);
+
+    /// Decodes value from the beginning of src and advances it.
+    fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError>;
+}
+
+/// Schema of type and all nested types, one definition per line.
+pub fn schema_of<T: GtsLogEvent>() -> String {
+    let mut out = Vec::new();
+    T::schema(&mut out);
+    out.join("\n")
+}
+
+/// Decodes value, which must take the whole src.
+pub fn decode_exact<T: GtsLogEvent>(mut src: &[u8]) -> Result<T, GtsLoggerError> {
+    let value = T::decode(&mut src)?;
+    if !src.is_empty() {
+        return Err(GtsLoggerError::DecodeError(format!(
+            "{} trailing bytes",
+            src.len()
+        )));
+    }
+    Ok(value)
+}
+
+pub const fn mix_fingerprint(hash: u64, value: u64) -> u64 {
+    (hash ^ value).wrapping_mul(0x100000001b3)
+}
+
+/// Splits len bytes off the beginning of src.
+pub fn take<'a>(src: &mut &'a [u8], len: usize) -> Result<&'a [u8], GtsLoggerError> {
+    if src.len() < len {
+        return Err(GtsLoggerError::DecodeError(format!(
+            "unexpected end of data, need {} bytes, have {}",
+            len,
+            src.len()
+        )));
+    }
+    let (head, tail) = src.split_at(len);
+    *src = tail;
+    Ok(head)
+}
+
+/// LEB128 unsigned varint.
+pub fn write_varint(mut value: u64, dst: &mut Vec<u8>) {
+    while value >= 0x80 {
+        dst.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+pub fn read_varint(src: &mut &[u8]) -> Result<u64, GtsLoggerError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(src, 1)?[0];
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(GtsLoggerError::DecodeError("varint overflow".to_string()))
+}
+
+macro_rules! impl_num {
+    ($($t:ty),*) => {
+        $(impl GtsLogEvent for $t {
+            const SCHEMA: &'static str = "";
+            const FINGERPRINT: u64 = fnv1a(stringify!($t).as_bytes());
+
+            fn encode(&self, dst: &mut Vec<u8>) {
+                dst.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+                let bytes = take(src, std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        })*
+    };
+}
+
+impl_num!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+// usize/isize are encoded as 64 bit to be independent of target.
+macro_rules! impl_size {
+    ($($t:ty as $as:ty),*) => {
+        $(impl GtsLogEvent for $t {
+            const SCHEMA: &'static str = "";
+            const FINGERPRINT: u64 = fnv1a(stringify!($as).as_bytes());
+
+            fn encode(&self, dst: &mut Vec<u8>) {
+                (*self as $as).encode(dst);
+            }
+
+            fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+                let value = <$as>::decode(src)?;
+                <$t>::try_from(value)
+                    .map_err(|err| GtsLoggerError::DecodeError(err.to_string()))
+            }
+        })*
+    };
+}
+
+impl_size!(usize as u64, isize as i64);
+
+impl GtsLogEvent for bool {
+    const SCHEMA: &'static str = "";
+    const FINGERPRINT: u64 = fnv1a(b"bool");
+
+    fn encode(&self, dst: &mut Vec<u8>) {
+        dst.push(*self as u8);
+    }
+
+    fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+        match take(src, 1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(GtsLoggerError::DecodeError(format!("bad bool {}", other))),
+        }
+    }
+}
+
+impl GtsLogEvent for char {
+    const SCHEMA: &'static str = "";
+    const FINGERPRINT: u64 = fnv1a(b"char");
+
+    fn encode(&self, dst: &mut Vec<u8>) {
+        (*self as u32).encode(dst);
+    }
+
+    fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+        let value = u32::decode(src)?;
+        char::from_u32(value)
+            .ok_or_else(|| GtsLoggerError::DecodeError(format!("bad char {}", value)))
+    }
+}
+
+impl GtsLogEvent for () {
+    const SCHEMA: &'static str = "";
+    const FINGERPRINT: u64 = fnv1a(b"()");
+
+    fn encode(&self, _dst: &mut Vec<u8>) {}
+
+    fn decode(_src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+        Ok(())
+    }
+}
+
+impl<const N: usize> GtsLogEvent for ArrayString<N> {
+    const SCHEMA: &'static str = "";
+    const FINGERPRINT: u64 = mix_fingerprint(fnv1a(b"ArrayString"), N as u64);
+
+    fn encode(&self, dst: &mut Vec<u8>) {
+        write_varint(self.len() as u64, dst);
+        dst.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+        let len = read_varint(src)? as usize;
+        if len > N {
+            return Err(GtsLoggerError::DecodeError(format!(
+                "string of {} bytes, capacity {}",
+                len, N
+            )));
+        }
+        let text = std::str::from_utf8(take(src, len)?)
+            .map_err(|err| GtsLoggerError::DecodeError(err.to_string()))?;
+        Ok(ArrayString::from(text).unwrap())
+    }
+}
+
+impl<T: GtsLogEvent, const N: usize> GtsLogEvent for [T; N] {
+    const SCHEMA: &'static str = "";
+    const FINGERPRINT: u64 = mix_fingerprint(T::FINGERPRINT, N as u64);
+
+    fn schema(out: &mut Vec<&'static str>) {
+        T::schema(out);
+    }
+
+    fn encode(&self, dst: &mut Vec<u8>) {
+        for value in self {
+            value.encode(dst);
+        }
+    }
+
+    fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+        let mut values = [None; N];
+        for value in &mut values {
+            *value = Some(T::decode(src)?);
+        }
+        Ok(values.map(Option::unwrap))
+    }
+}
+
+impl<T: GtsLogEvent> GtsLogEvent for Option<T> {
+    const SCHEMA: &'static str = "";
+    const FINGERPRINT: u64 = mix_fingerprint(fnv1a(b"Option"), T::FINGERPRINT);
+
+    fn schema(out: &mut Vec<&'static str>) {
+        T::schema(out);
+    }
+
+    fn encode(&self, dst: &mut Vec<u8>) {
+        self.is_some().encode(dst);
+        if let Some(value) = self {
+            value.encode(dst);
+        }
+    }
+
+    fn decode(src: &mut &[u8]) -> Result<Self, GtsLoggerError> {
+        Ok(match bool::decode(src)? {
+            true => Some(T::decode(src)?),
+            false => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::LogLevel;
+    use crate::logclient::LogEventTs;
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub struct LogOneStruct {
+        some_num: u64,
+        some_other_num: i32,
+        some_string: ArrayString<16>,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub struct LogTwoStruct(char, [u16; 2], Option<f64>);
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub enum LogEvent {
+        LogOneOne(LogOneStruct),
+        LogTwo(LogTwoStruct),
+        Flag { on: bool, id: usize },
+        Empty,
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let events = [
+            LogEvent::LogOneOne(LogOneStruct {
+                some_num: u64::MAX,
+                some_other_num: -7,
+                some_string: ArrayString::from("333").unwrap(),
+            }),
+            LogEvent::LogTwo(LogTwoStruct('ж', [1, 2], None)),
+            LogEvent::LogTwo(LogTwoStruct('a', [3, 4], Some(0.5))),
+            LogEvent::Flag { on: true, id: 300 },
+            LogEvent::Empty,
+        ];
+        let mut buf = Vec::new();
+        for (seqid, event) in events.iter().enumerate() {
+            LogEventTs::new(5, seqid as u32, LogLevel::Warn, *event).encode(&mut buf);
+        }
+        let mut src = buf.as_slice();
+        for (seqid, event) in events.iter().enumerate() {
+            let decoded = LogEventTs::<LogEvent>::decode(&mut src).unwrap();
+            assert_eq!(
+                decoded,
+                LogEventTs::new(5, seqid as u32, LogLevel::Warn, *event)
+            );
+        }
+        assert!(src.is_empty());
+        assert!(LogEvent::decode(&mut src).is_err());
+        assert!(decode_exact::<LogEvent>(&[4]).is_err());
+        assert!(decode_exact::<LogEvent>(&[3, 0]).is_err());
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(value, &mut buf);
+            let mut src = buf.as_slice();
+            assert_eq!(read_varint(&mut src).unwrap(), value);
+            assert!(src.is_empty());
+        }
+        assert!(read_varint(&mut [0xff; 11].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_schema() {
+        assert_eq!(
+            LogTwoStruct::SCHEMA,
+            "struct LogTwoStruct(char, [u16; 2], Option<f64>)"
+        );
+        assert_eq!(
+            schema_of::<LogEventTs<LogEvent>>()
+                .lines()
+                .collect::<Vec<_>>(),
+            [
+                "struct LogEventTs<T> { timestamp: u64, seqid: u32, level: LogLevel, data: T }",
+                "enum LogLevel { Error, Warn, Info, Debug, Trace }",
+                "enum LogEvent { LogOneOne(LogOneStruct), LogTwo(LogTwoStruct), \
+                 Flag { on: bool, id: usize }, Empty }",
+                "struct LogOneStruct { some_num: u64, some_other_num: i32, \
+                 some_string: ArrayString<16> }",
+                "struct LogTwoStruct(char, [u16; 2], Option<f64>)",
+            ]
+        );
+        assert_ne!(
+            LogEventTs::<LogEvent>::FINGERPRINT,
+            LogEventTs::<LogOneStruct>::FINGERPRINT
+        );
+        assert_ne!(
+            ArrayString::<8>::FINGERPRINT,
+            ArrayString::<16>::FINGERPRINT
+        );
+    }
+}
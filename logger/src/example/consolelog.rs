@@ -1,6 +1,8 @@
 use arrayvec::ArrayString;
+use gts_logger::error::GtsLoggerError;
 use gts_logger::logbackend::consolelogger::ConsoleThreadLogBacked;
 use gts_logger::logclient::LogClient;
+use gts_transport::thread::ThreadBuilder;
 use log::info;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -24,7 +26,7 @@ pub enum LogEvent {
     LogTwo(LogTwoStruct),
 }
 
-fn main() {
+fn main() -> Result<(), GtsLoggerError> {
     let anc = minstant::Anchor::new();
     env_logger::init();
 
@@ -45,10 +47,10 @@ fn main() {
 
     let log_client = LogClient::<_, LogEvent>::new(ConsoleThreadLogBacked::<50000, _>::new(Some(
         logthread_core,
-    )));
-    assert!(core_affinity::set_for_current(core_affinity::CoreId {
-        id: mainthread_core
-    }));
+    ))?);
+    ThreadBuilder::new()
+        .core(mainthread_core)
+        .apply_to_current()?;
 
     info!(">> println with logger:");
 
@@ -106,4 +108,5 @@ fn main() {
         ulogs_end_ts - ulogs_start_ts,
         (ulogs_end_ts - ulogs_start_ts) * 1000 / ulogs_iters
     );
+    Ok(())
 }
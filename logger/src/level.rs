@@ -0,0 +1,204 @@
+//! Levels of log events and filtering of them.
+//!
+//! Event is logged iff its level passes both filters:
+//!  * [`STATIC_MAX_LEVEL`] - compile time one, set by `max_level_*` / `release_max_level_*`
+//!    cargo features (the same as in `log` crate), so events below it are compiled out;
+//!  * [`LevelThreshold`] - runtime one, single relaxed atomic load. LogClient uses global
+//!    [`MAX_LEVEL`] by default (see [`set_max_level`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::level::{LevelThreshold, LogLevel, LogLevelFilter};
+//!
+//! static THRESHOLD: LevelThreshold = LevelThreshold::new(LogLevelFilter::Info);
+//!
+//! assert!(THRESHOLD.enabled(LogLevel::Warn));
+//! assert!(!THRESHOLD.enabled(LogLevel::Debug));
+//! THRESHOLD.set("debug".parse().unwrap());
+//! assert!(THRESHOLD.enabled(LogLevel::Debug));
+//! ```
+
+use crate::event::GtsLogEvent;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Level of event, Error is the most severe one.
+#[repr(u8)]
+#[derive(
+    Debug, Serialize, Deserialize, GtsLogEvent, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Maximal logged level, Off disables all events.
+#[repr(u8)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevelFilter {
+    Off = 0,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+const NAMES: [&str; 6] = ["OFF", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        NAMES[*self as usize]
+    }
+
+    pub fn to_filter(self) -> LogLevelFilter {
+        LogLevelFilter::from_u8(self as u8)
+    }
+}
+
+impl LogLevelFilter {
+    pub fn as_str(&self) -> &'static str {
+        NAMES[*self as usize]
+    }
+
+    /// True if events of level pass this filter.
+    #[inline(always)]
+    pub const fn enabled(self, level: LogLevel) -> bool {
+        level as u8 <= self as u8
+    }
+
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Off,
+            1 => Self::Error,
+            2 => Self::Warn,
+            3 => Self::Info,
+            4 => Self::Debug,
+            _ => Self::Trace,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl fmt::Display for LogLevelFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for LogLevelFilter {
+    type Err = String;
+
+    /// Case insensitive name of filter, e.g. "info".
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        NAMES
+            .iter()
+            .position(|known| known.eq_ignore_ascii_case(name.trim()))
+            .map(|pos| Self::from_u8(pos as u8))
+            .ok_or_else(|| format!("unknown log level {name}"))
+    }
+}
+
+/// Compile time max level, levels below it are compiled out.
+pub const STATIC_MAX_LEVEL: LogLevelFilter = static_max_level();
+
+// the most restrictive of enabled features wins.
+const fn static_max_level() -> LogLevelFilter {
+    let release = !cfg!(debug_assertions);
+    if cfg!(feature = "max_level_off") || release && cfg!(feature = "release_max_level_off") {
+        LogLevelFilter::Off
+    } else if cfg!(feature = "max_level_error")
+        || release && cfg!(feature = "release_max_level_error")
+    {
+        LogLevelFilter::Error
+    } else if cfg!(feature = "max_level_warn")
+        || release && cfg!(feature = "release_max_level_warn")
+    {
+        LogLevelFilter::Warn
+    } else if cfg!(feature = "max_level_info")
+        || release && cfg!(feature = "release_max_level_info")
+    {
+        LogLevelFilter::Info
+    } else if cfg!(feature = "max_level_debug")
+        || release && cfg!(feature = "release_max_level_debug")
+    {
+        LogLevelFilter::Debug
+    } else {
+        LogLevelFilter::Trace
+    }
+}
+
+/// Runtime adjustable max level, could be shared by clients as static.
+#[derive(Debug)]
+pub struct LevelThreshold(AtomicU8);
+
+impl LevelThreshold {
+    pub const fn new(filter: LogLevelFilter) -> Self {
+        Self(AtomicU8::new(filter as u8))
+    }
+
+    pub fn set(&self, filter: LogLevelFilter) {
+        self.0.store(filter as u8, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> LogLevelFilter {
+        LogLevelFilter::from_u8(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Checks both static and runtime filters.
+    #[inline(always)]
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        STATIC_MAX_LEVEL.enabled(level) && level as u8 <= self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Global threshold, default one of LogClient.
+pub static MAX_LEVEL: LevelThreshold = LevelThreshold::new(LogLevelFilter::Trace);
+
+pub fn set_max_level(filter: LogLevelFilter) {
+    MAX_LEVEL.set(filter);
+}
+
+pub fn max_level() -> LogLevelFilter {
+    MAX_LEVEL.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        assert!(LogLevel::Error < LogLevel::Trace);
+        assert!(LogLevelFilter::Warn.enabled(LogLevel::Error));
+        assert!(LogLevelFilter::Warn.enabled(LogLevel::Warn));
+        assert!(!LogLevelFilter::Warn.enabled(LogLevel::Info));
+        assert!(!LogLevelFilter::Off.enabled(LogLevel::Error));
+        assert_eq!(LogLevel::Debug.to_filter(), LogLevelFilter::Debug);
+
+        let threshold = LevelThreshold::new(LogLevelFilter::Off);
+        assert!(!threshold.enabled(LogLevel::Error));
+        threshold.set(LogLevelFilter::Trace);
+        assert_eq!(threshold.get(), LogLevelFilter::Trace);
+        assert!(threshold.enabled(LogLevel::Trace));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(format!("{:<5}|", LogLevel::Warn), "WARN |");
+        assert_eq!("Error".parse::<LogLevelFilter>(), Ok(LogLevelFilter::Error));
+        assert_eq!(" off\n".parse::<LogLevelFilter>(), Ok(LogLevelFilter::Off));
+        assert!("verbose".parse::<LogLevelFilter>().is_err());
+    }
+}
@@ -1,6 +1,13 @@
+extern crate self as gts_logger;
+
 pub mod error;
+pub mod event;
+pub mod level;
 pub mod logbackend;
 pub mod logclient;
+pub mod logfile;
+mod macros;
+pub mod record;
 
 #[cfg(test)]
 mod tests {
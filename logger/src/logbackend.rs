@@ -1,9 +1,21 @@
 use crate::error::GtsLoggerError;
+use std::time::Duration;
 pub mod consolelogger;
+pub mod crash;
 pub mod dualthread;
+pub mod encoder;
+pub mod hub;
 pub mod mock;
+pub mod rolling;
+pub mod shmem;
+pub mod stats;
 pub mod threadmock;
 
 pub trait LogBackend<T> {
     fn log(&self, event: T) -> Result<(), GtsLoggerError>;
+
+    /// Waits until events logged before the call are written to destination.
+    fn flush(&self, _timeout: Duration) -> Result<(), GtsLoggerError> {
+        Ok(())
+    }
 }
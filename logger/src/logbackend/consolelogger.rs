@@ -1,22 +1,29 @@
 use crate::error::GtsLoggerError;
+use crate::logbackend::crash;
+use crate::logbackend::dualthread::DROP_TIMEOUT;
+use crate::logbackend::stats::{wait_until, LogStats, ThreadCounters};
 use crate::logbackend::LogBackend;
 use crate::logclient::LogEventTs;
 use core::fmt::Debug;
 use gts_transport::error::GtsTransportError;
 use gts_transport::membackend::memchunk::MemChunkHolder;
 use gts_transport::sync::lfringspsc::{spsc_ring_pair, SpScRingData, SpScRingSender};
+use gts_transport::thread::{ThreadBuilder, ThreadHandle};
 use log::info;
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
 use std::time::Duration;
 
 pub struct ConsoleThreadLogBacked<const RSIZE: usize, T>
 where
     T: Copy + Send,
 {
-    run_flag: Arc<Mutex<bool>>,
-    join_handle: Option<std::thread::JoinHandle<()>>,
+    run_flag: Arc<AtomicBool>,
+    counters: Arc<ThreadCounters>,
+    /// Slot in registry of crash handlers.
+    crash_slot: Option<usize>,
+    join_handle: Cell<Option<ThreadHandle<()>>>,
     log_tx: UnsafeCell<SpScRingSender<RSIZE, T, MemChunkHolder<SpScRingData<RSIZE, T>>>>,
 }
 
@@ -24,48 +31,91 @@ impl<T, const RSIZE: usize> ConsoleThreadLogBacked<RSIZE, LogEventTs<T>>
 where
     T: Copy + Send + 'static + Debug,
 {
-    pub fn new(core_id: Option<usize>) -> Self {
-        let flag = Arc::new(Mutex::new(false));
-        // let queue = Arc::new(Mutex::new(VecDeque::<T>::new()));
+    /// Spawns log thread, pinned to core_id if any.
+    pub fn new(core_id: Option<usize>) -> Result<Self, GtsLoggerError> {
+        let flag = Arc::new(AtomicBool::new(true));
+        let counters = Arc::new(ThreadCounters::default());
 
         let flag_clone = flag.clone();
-        // let queue_clone = queue.clone();
+        let counters_clone = counters.clone();
         let (log_tx, mut log_rx) =
             spsc_ring_pair::<RSIZE, LogEventTs<T>, _>(MemChunkHolder::zeroed());
 
-        let join_handle = Some(std::thread::spawn(move || {
-            if let Some(core_id) = core_id {
-                assert!(core_affinity::set_for_current(core_affinity::CoreId {
-                    id: core_id
-                }));
-            }
+        let mut builder = ThreadBuilder::new().name("gts-consolelog");
+        if let Some(core_id) = core_id {
+            builder = builder.core(core_id);
+        }
+        let join_handle = builder.spawn(move || {
             let mut last_ts = None;
-            while !*flag_clone.lock().unwrap() {
-                match log_rx.try_recv() {
-                    Ok(res) => {
-                        let diff = last_ts.map(|val| res.timestamp - val);
-                        match diff {
-                            None => {
-                                info!("[LOG] @{} (-) {:?}", res.timestamp, res.data);
-                            }
-                            Some(diff) => {
-                                info!("[LOG] @{} (+{} ns) {:?}", res.timestamp, diff, res.data);
+            'run: loop {
+                // events sent before stop are in ring already.
+                let running = flag_clone.load(Ordering::Acquire);
+                loop {
+                    if counters_clone.is_aborted() {
+                        break 'run;
+                    }
+                    match log_rx.try_recv() {
+                        Ok(res) => {
+                            let diff = last_ts.map(|val| res.timestamp - val);
+                            match diff {
+                                None => {
+                                    info!("[{}] @{} (-) {:?}", res.level, res.timestamp, res.data);
+                                }
+                                Some(diff) => {
+                                    info!(
+                                        "[{}] @{} (+{} ns) {:?}",
+                                        res.level, res.timestamp, diff, res.data
+                                    );
+                                }
                             }
+                            last_ts = Some(res.timestamp);
+                            counters_clone.written.fetch_add(1, Ordering::Release);
                         }
-                        last_ts = Some(res.timestamp);
+                        Err(GtsTransportError::WouldBlock) => break,
+                        _ => unreachable!(),
                     }
-                    Err(GtsTransportError::WouldBlock) => {}
-                    _ => unreachable!(),
+                }
+                if !running {
+                    break;
                 }
                 std::thread::sleep(Duration::from_millis(10));
             }
-        }));
+            log::logger().flush();
+            counters_clone.finished.store(true, Ordering::Release);
+        })?;
 
-        ConsoleThreadLogBacked {
+        Ok(ConsoleThreadLogBacked {
             run_flag: flag,
-            join_handle,
+            crash_slot: crash::register(&counters),
+            counters,
+            join_handle: Cell::new(Some(join_handle)),
             log_tx: log_tx.into(),
+        })
+    }
+}
+
+impl<T, const RSIZE: usize> ConsoleThreadLogBacked<RSIZE, T>
+where
+    T: Copy + Send,
+{
+    /// Stops thread after it prints all logged events, see
+    /// [`DualThreadLogBacked::shutdown`](crate::logbackend::dualthread::DualThreadLogBacked::shutdown).
+    pub fn shutdown(&self, timeout: Duration) -> LogStats {
+        self.run_flag.store(false, Ordering::Release);
+        let handle = self.join_handle.take();
+        if wait_until(timeout, || self.counters.is_finished()) {
+            if let Some(handle) = handle {
+                handle.join().unwrap();
+            }
+        } else {
+            self.counters.abort();
         }
+        self.counters.final_stats()
+    }
+
+    /// Current counters, events in flight are neither written nor lost.
+    pub fn stats(&self) -> LogStats {
+        self.counters.stats()
     }
 }
 
@@ -74,8 +124,15 @@ where
     T: Copy + Send,
 {
     fn drop(&mut self) {
-        *self.run_flag.lock().unwrap() = true;
-        self.join_handle.take().unwrap().join().unwrap();
+        if self.run_flag.load(Ordering::Acquire) {
+            let stats = self.shutdown(DROP_TIMEOUT);
+            if stats.lost > 0 {
+                eprintln!("gts-logger: {} events lost", stats.lost);
+            }
+        }
+        if let Some(slot) = self.crash_slot.take() {
+            crash::unregister(slot);
+        }
     }
 }
 
@@ -84,22 +141,44 @@ where
     T: Copy + Send + Debug,
 {
     fn log(&self, event: T) -> Result<(), GtsLoggerError> {
+        if !self.run_flag.load(Ordering::Relaxed) {
+            self.counters.add_dropped();
+            return Err(GtsLoggerError::CommonError(
+                "backend is shut down".to_string(),
+            ));
+        }
         // SAFETY: Self is !Sync, only this function uses log_tx,
         // no reentrancy in this function.
         // but need verify reentrancy (by signal e.g.)
         // anyway refcell doesn't check signal-reentrancy either.
         let log_tx = unsafe { &mut *self.log_tx.get() };
-        log_tx.send(&event).unwrap();
+        if let Err(err) = log_tx.send(&event) {
+            self.counters.add_dropped();
+            return Err(err.into());
+        }
+        self.counters.add_sent();
         Ok(())
     }
+
+    fn flush(&self, timeout: Duration) -> Result<(), GtsLoggerError> {
+        let target = self.counters.request_flush();
+        if wait_until(timeout, || self.counters.flushed(target)) {
+            log::logger().flush();
+            Ok(())
+        } else {
+            Err(GtsLoggerError::Timeout)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::logbackend::consolelogger::ConsoleThreadLogBacked;
+    use crate::logbackend::stats::LogStats;
     use crate::logclient::LogClient;
     use arrayvec::ArrayString;
     use serde::{Deserialize, Serialize};
+    use std::time::Duration;
 
     #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
     pub struct LogOneStruct {
@@ -129,9 +208,20 @@ mod tests {
         });
 
         let log_client =
-            LogClient::<_, LogEvent>::new(ConsoleThreadLogBacked::<3000, _>::new(None));
+            LogClient::<_, LogEvent>::new(ConsoleThreadLogBacked::<3000, _>::new(None).unwrap());
 
         log_client.log(event).unwrap();
         log_client.log_same(event).unwrap();
+        log_client.flush(Duration::from_secs(5)).unwrap();
+
+        let stats = log_client.backend().shutdown(Duration::from_secs(5));
+        assert_eq!(
+            stats,
+            LogStats {
+                sent: 2,
+                written: 2,
+                lost: 0
+            }
+        );
     }
 }
@@ -0,0 +1,359 @@
+//! Flush of threaded backends on panic and fatal signals.
+//!
+//! Backends register their counters in a lock-free registry. On panic or signal
+//! the crashing thread asks every registered backend to write events sent so far
+//! and waits for them until timeout; the events are written by backend threads,
+//! so the handler itself does only atomics, `clock_gettime` and `nanosleep`, which
+//! are async-signal-safe. Then the previous handler of signal is called and stays chained,
+//! or, if the previous action is default, the signal is re-raised with it.
+//!
+//! Backend threads could be blocked by a lock held by the crashing thread (e.g. stdout),
+//! or the signal could hit the backend thread itself, timeout bounds the wait then.
+
+use crate::error::GtsLoggerError;
+use crate::logbackend::stats::ThreadCounters;
+use libc::{c_int, c_void, siginfo_t};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Max number of backends flushed on crash.
+pub const MAX_BACKENDS: usize = 64;
+
+/// Signals handled by default.
+pub const DEFAULT_SIGNALS: [c_int; 7] = [
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGILL,
+    libc::SIGABRT,
+    libc::SIGTERM,
+    libc::SIGINT,
+];
+
+const MAX_SIGNAL: usize = 65;
+
+static BACKENDS: [AtomicPtr<ThreadCounters>; MAX_BACKENDS] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_BACKENDS];
+/// Handlers reading BACKENDS, unregister waits for them before counters are freed.
+static IN_FLUSH: AtomicUsize = AtomicUsize::new(0);
+static TIMEOUT_NS: AtomicU64 = AtomicU64::new(1_000_000_000);
+static PANIC_HOOK: AtomicBool = AtomicBool::new(false);
+/// Actions of signals before install, leaked.
+static PREV_ACTIONS: [AtomicPtr<libc::sigaction>; MAX_SIGNAL] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_SIGNAL];
+
+/// Adds counters of backend to registry, returns its slot or None if registry is full.
+pub(crate) fn register(counters: &Arc<ThreadCounters>) -> Option<usize> {
+    let ptr = Arc::into_raw(counters.clone()) as *mut ThreadCounters;
+    for (idx, slot) in BACKENDS.iter().enumerate() {
+        if slot
+            .compare_exchange(null_mut(), ptr, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return Some(idx);
+        }
+    }
+    // SAFETY: ptr is from into_raw above and not stored.
+    drop(unsafe { Arc::from_raw(ptr) });
+    None
+}
+
+/// Removes counters from registry.
+pub(crate) fn unregister(slot: usize) {
+    let ptr = BACKENDS[slot].swap(null_mut(), Ordering::SeqCst);
+    if ptr.is_null() {
+        return;
+    }
+    // handler that loaded ptr before swap has incremented IN_FLUSH before load.
+    while IN_FLUSH.load(Ordering::SeqCst) > 0 {
+        std::thread::yield_now();
+    }
+    // SAFETY: ptr is from into_raw in register, nobody else can see it now.
+    drop(unsafe { Arc::from_raw(ptr) });
+}
+
+fn now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+fn sleep_ms() {
+    let ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 1_000_000,
+    };
+    unsafe { libc::nanosleep(&ts, null_mut()) };
+}
+
+/// Waits until registered backends write events sent before the call, returns false on timeout.
+/// Async-signal-safe.
+pub fn flush_all(timeout: Duration) -> bool {
+    IN_FLUSH.fetch_add(1, Ordering::SeqCst);
+    let mut targets = [0u64; MAX_BACKENDS];
+    for (slot, target) in BACKENDS.iter().zip(targets.iter_mut()) {
+        // SAFETY: counters are alive while IN_FLUSH > 0, see unregister.
+        if let Some(counters) = unsafe { slot.load(Ordering::SeqCst).as_ref() } {
+            *target = counters.request_flush();
+        }
+    }
+    let deadline = now_ns().saturating_add(timeout.as_nanos() as u64);
+    let flushed = loop {
+        let flushed = BACKENDS.iter().zip(targets).all(|(slot, target)| {
+            // SAFETY: as above.
+            unsafe { slot.load(Ordering::SeqCst).as_ref() }
+                .is_none_or(|counters| counters.flushed(target))
+        });
+        if flushed || now_ns() >= deadline {
+            break flushed;
+        }
+        sleep_ms();
+    };
+    IN_FLUSH.fetch_sub(1, Ordering::SeqCst);
+    flushed
+}
+
+extern "C" fn on_signal(sig: c_int, info: *mut siginfo_t, ctx: *mut c_void) {
+    flush_all(Duration::from_nanos(TIMEOUT_NS.load(Ordering::Relaxed)));
+    // SAFETY: previous action is leaked, so it lives forever.
+    let prev = unsafe { PREV_ACTIONS[sig as usize].load(Ordering::Acquire).as_ref() };
+    match prev.map(|prev| (prev.sa_sigaction, prev.sa_flags)) {
+        None | Some((libc::SIG_DFL, _)) => unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            // sig is blocked in handler, so it's delivered with default action on return.
+            libc::raise(sig);
+        },
+        Some((libc::SIG_IGN, _)) => {}
+        // SAFETY: handler is installed by sigaction with these flags, so it has this type.
+        Some((handler, flags)) if flags & libc::SA_SIGINFO != 0 => unsafe {
+            let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) =
+                std::mem::transmute(handler);
+            handler(sig, info, ctx);
+        },
+        Some((handler, _)) => unsafe {
+            let handler: extern "C" fn(c_int) = std::mem::transmute(handler);
+            handler(sig);
+        },
+    }
+}
+
+/// Options of crash handlers, panic hook and all DEFAULT_SIGNALS with 1 s timeout by default.
+#[derive(Debug, Clone)]
+pub struct CrashHandlerOptions {
+    timeout: Duration,
+    signals: Vec<c_int>,
+    panic_hook: bool,
+}
+
+impl Default for CrashHandlerOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrashHandlerOptions {
+    pub fn new() -> Self {
+        CrashHandlerOptions {
+            timeout: Duration::from_secs(1),
+            signals: DEFAULT_SIGNALS.to_vec(),
+            panic_hook: true,
+        }
+    }
+
+    /// Max time to wait for backends, shared by all handlers.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn signals(mut self, signals: &[c_int]) -> Self {
+        self.signals = signals.to_vec();
+        self
+    }
+
+    pub fn panic_hook(mut self, panic_hook: bool) -> Self {
+        self.panic_hook = panic_hook;
+        self
+    }
+
+    /// Installs handlers, signals and panic hook handled already are skipped.
+    /// Previous panic hook is called after flush.
+    pub fn install(self) -> Result<(), GtsLoggerError> {
+        TIMEOUT_NS.store(self.timeout.as_nanos() as u64, Ordering::Relaxed);
+        for &sig in &self.signals {
+            if sig <= 0 || sig as usize >= MAX_SIGNAL {
+                return Err(GtsLoggerError::CommonError(format!("bad signal {}", sig)));
+            }
+            if !PREV_ACTIONS[sig as usize].load(Ordering::Acquire).is_null() {
+                continue;
+            }
+            // SAFETY: sigaction is plain C struct.
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_signal as *const () as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            let mut prev: libc::sigaction = unsafe { std::mem::zeroed() };
+            unsafe { libc::sigemptyset(&mut action.sa_mask) };
+            // previous action is stored before handler could run.
+            if unsafe { libc::sigaction(sig, null_mut(), &mut prev) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            PREV_ACTIONS[sig as usize].store(Box::into_raw(Box::new(prev)), Ordering::Release);
+            if unsafe { libc::sigaction(sig, &action, null_mut()) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        if self.panic_hook && !PANIC_HOOK.swap(true, Ordering::SeqCst) {
+            let prev = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                flush_all(Duration::from_nanos(TIMEOUT_NS.load(Ordering::Relaxed)));
+                prev(info);
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logbackend::dualthread::DualThreadLogBacked;
+    use crate::logclient::{LogClient, LogEventTs};
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    const CHILD_ENV: &str = "GTS_CRASH_TEST_CHILD";
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("gts-crash-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn count_lines(path: &Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    /// Client, which backend is never dropped.
+    fn leaked_client(
+        path: &Path,
+    ) -> &'static LogClient<DualThreadLogBacked<64, LogEventTs<u64>>, u64> {
+        let file = std::fs::File::create(path).unwrap();
+        let log_client = Box::leak(Box::new(LogClient::<_, u64>::new(DualThreadLogBacked::<
+            64,
+            _,
+        >::new(file))));
+        for val in 0..10 {
+            log_client.log(val).unwrap();
+        }
+        log_client
+    }
+
+    /// Runs test in child process with CHILD_ENV set to path.
+    fn run_child(test: &str, path: &Path) -> std::process::ExitStatus {
+        Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", test, "--nocapture", "--test-threads=1"])
+            .env(CHILD_ENV, path)
+            .output()
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn test_flush_all() {
+        let path = temp_path("flush");
+        let file = std::fs::File::create(&path).unwrap();
+        let log_client = LogClient::<_, u64>::new(DualThreadLogBacked::<64, _>::new(file));
+        for val in 0..10 {
+            log_client.log(val).unwrap();
+        }
+        assert!(flush_all(Duration::from_secs(5)));
+        assert_eq!(count_lines(&path), 10);
+
+        drop(log_client);
+        assert!(flush_all(Duration::from_secs(5)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_signal_flush() {
+        if let Some(path) = std::env::var_os(CHILD_ENV) {
+            let _log_client = leaked_client(Path::new(&path));
+            CrashHandlerOptions::new()
+                .signals(&[libc::SIGTERM])
+                .panic_hook(false)
+                .timeout(Duration::from_secs(5))
+                .install()
+                .unwrap();
+            unsafe { libc::raise(libc::SIGTERM) };
+            unreachable!();
+        }
+        let path = temp_path("signal");
+        let status = run_child("logbackend::crash::tests::test_signal_flush", &path);
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert_eq!(count_lines(&path), 10);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    static CHAINED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn count_signal(_sig: c_int) {
+        CHAINED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_signal_chain() {
+        if let Some(path) = std::env::var_os(CHILD_ENV) {
+            let path = Path::new(&path);
+            let _log_client = leaked_client(path);
+            unsafe {
+                libc::signal(
+                    libc::SIGTERM,
+                    count_signal as *const () as libc::sighandler_t,
+                )
+            };
+            CrashHandlerOptions::new()
+                .signals(&[libc::SIGTERM])
+                .panic_hook(false)
+                .timeout(Duration::from_secs(5))
+                .install()
+                .unwrap();
+            // handler of application is called every time, process keeps running.
+            unsafe { libc::raise(libc::SIGTERM) };
+            assert_eq!(count_lines(path), 10);
+            unsafe { libc::raise(libc::SIGTERM) };
+            assert_eq!(CHAINED.load(Ordering::SeqCst), 2);
+            return;
+        }
+        let path = temp_path("chain");
+        let status = run_child("logbackend::crash::tests::test_signal_chain", &path);
+        assert!(status.success());
+        assert_eq!(count_lines(&path), 10);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_panic_flush() {
+        if let Some(path) = std::env::var_os(CHILD_ENV) {
+            let _log_client = leaked_client(Path::new(&path));
+            CrashHandlerOptions::new()
+                .signals(&[])
+                .timeout(Duration::from_secs(5))
+                .install()
+                .unwrap();
+            // panic with abort doesn't drop backend.
+            assert!(std::thread::spawn(|| panic!("crash")).join().is_err());
+            std::process::abort();
+        }
+        let path = temp_path("panic");
+        let status = run_child("logbackend::crash::tests::test_panic_flush", &path);
+        assert_eq!(status.signal(), Some(libc::SIGABRT));
+        assert_eq!(count_lines(&path), 10);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,8 @@
 use crate::error::GtsLoggerError;
+use crate::logbackend::crash;
+use crate::logbackend::encoder::{Encoder, JsonLinesEncoder};
+use crate::logbackend::rolling::RollingFileWriter;
+use crate::logbackend::stats::{wait_until, LogStats, ThreadCounters};
 use crate::logbackend::LogBackend;
 use crate::logclient::LogEventTs;
 use gts_transport::error::GtsTransportError;
@@ -6,54 +10,85 @@ use gts_transport::membackend::memchunk::MemChunkHolder;
 use gts_transport::sync::lfringspsc::{spsc_ring_pair, SpScRingData, SpScRingSender};
 use minstant::Instant;
 use serde::Serialize;
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
 use std::fmt::Debug;
 use std::io::Write;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, TryRecvError};
 use std::sync::Arc;
 use std::time::Duration;
 
+/// Backend with two threads: alpha moves events from ring to channel, beta encodes and
+/// writes them in batches (5000 events or every 5 s, and on flush / shutdown).
 pub struct DualThreadLogBacked<const RSIZE: usize, T>
 where
     T: Copy + Send,
 {
     // queue_rx: Receiver<T>,
     run_flag: Arc<AtomicBool>,
-    join_handle_alpha: Option<std::thread::JoinHandle<()>>,
-    join_handle_beta: Option<std::thread::JoinHandle<()>>,
+    counters: Arc<ThreadCounters>,
+    /// Slot in registry of crash handlers.
+    crash_slot: Option<usize>,
+    join_handle_alpha: Cell<Option<std::thread::JoinHandle<()>>>,
+    join_handle_beta: Cell<Option<std::thread::JoinHandle<()>>>,
     log_tx: UnsafeCell<SpScRingSender<RSIZE, T, MemChunkHolder<SpScRingData<RSIZE, T>>>>,
 }
 
+/// Timeout of shutdown on drop.
+pub const DROP_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl<T, const RSIZE: usize> DualThreadLogBacked<RSIZE, LogEventTs<T>>
 where
     T: Copy + Send + 'static + Debug + Serialize,
 {
+    /// Writes events to dest as JSON lines.
     pub fn new(dest: impl Write + Send + 'static) -> Self {
+        Self::with_encoder(dest, JsonLinesEncoder::new())
+    }
+}
+
+impl<T, const RSIZE: usize> DualThreadLogBacked<RSIZE, LogEventTs<T>>
+where
+    T: Copy + Send + 'static,
+{
+    /// Writes events to rolling file in format of encoder,
+    /// header of encoder is written at the beginning of every file.
+    pub fn with_rolling_file(
+        dest: RollingFileWriter,
+        mut encoder: impl Encoder<T> + 'static,
+    ) -> Result<Self, GtsLoggerError> {
+        let mut header = Vec::new();
+        encoder.begin(&mut header)?;
+        let dest = dest.with_header(header)?;
+        Ok(Self::with_encoder(dest, WithoutBegin(encoder)))
+    }
+
+    /// Writes events to dest in format of encoder.
+    pub fn with_encoder(
+        dest: impl Write + Send + 'static,
+        encoder: impl Encoder<T> + 'static,
+    ) -> Self {
         let running_flag_alpha = Arc::new(AtomicBool::new(true));
-        let running_flag_beta = Arc::new(AtomicBool::new(true));
-        // let queue = Arc::new(Mutex::new(VecDeque::<T>::new()));
+        let counters = Arc::new(ThreadCounters::default());
 
         let running_flag_alpha_clone = running_flag_alpha.clone();
-        let running_flag_beta_clone = running_flag_beta.clone();
-        // let queue_clone = queue.clone();
+        let counters_alpha = counters.clone();
+        let counters_beta = counters.clone();
         let (log_tx, mut log_rx) =
             spsc_ring_pair::<RSIZE, LogEventTs<T>, _>(MemChunkHolder::zeroed());
 
         let (queue_tx, queue_rx) = channel();
 
-        // let fname = fname.map(|fname| fname.to_string());
-
-        let join_handle_alpha = Some(std::thread::spawn(move || {
-            //let mut logs = Vec::with_capacity(3000);
-            while running_flag_alpha_clone.load(Ordering::Relaxed) {
+        let join_handle_alpha = std::thread::spawn(move || {
+            loop {
+                // events sent before stop are in ring already.
+                let running = running_flag_alpha_clone.load(Ordering::Acquire);
                 let mut counter = 0;
                 loop {
-                    //while logs.len() < logs.capacity() {
-                    match log_rx.try_recv() {
+                    match log_rx.try_recv_value() {
                         Ok(res) => {
-                            //queue_tx.send(*res).unwrap();
-                            queue_tx.send(*res).unwrap();
+                            // beta is gone after abort, event is lost.
+                            let _ = queue_tx.send(res);
                             counter += 1;
                         }
                         Err(GtsTransportError::WouldBlock) => {
@@ -65,68 +100,141 @@ where
                 if counter > 0 {
                     println!("READ {} items", counter);
                 }
+                if !running || counters_alpha.is_aborted() {
+                    break;
+                }
                 std::thread::sleep(Duration::from_millis(10));
             }
-            running_flag_beta.store(false, Ordering::Relaxed);
+            // beta sees disconnected channel after the last event.
+            drop(queue_tx);
             println!("logthread-alpha closed");
-        }));
+        });
 
-        let join_handle_beta = Some(std::thread::spawn(move || {
+        let join_handle_beta = std::thread::spawn(move || {
+            let counters = counters_beta;
             let mut last_send = minstant::Instant::now();
 
             let mut dest = dest;
-            // enum Fp {
-            //     File(File),
-            //     Sink(Sink),
-            // };
-            // let fp = match fname {
-            //     Some(name) => Fp::File(File::open(name).unwrap()),
-            //     None => Fp::Sink(std::io::sink()),
-            // };
-
-            let mut logs = Vec::with_capacity(3000);
-            while running_flag_beta_clone.load(Ordering::Relaxed) {
-                loop {
+            let mut encoder = encoder;
+            let mut buf = Vec::new();
+            if let Err(err) = encoder.begin(&mut buf) {
+                eprintln!("logthread-beta: encoder: {}", err);
+            }
+
+            let mut logs = Vec::with_capacity(5000);
+            while !counters.is_aborted() {
+                let closed = loop {
                     match queue_rx.try_recv() {
-                        Ok(res) => {
-                            logs.push(res);
-                        }
-                        Err(_) => {
-                            // either empty or closed, need to break
-                            break;
-                        }
+                        Ok(res) => logs.push(res),
+                        Err(TryRecvError::Empty) => break false,
+                        Err(TryRecvError::Disconnected) => break true,
                     }
-                }
-                if !logs.is_empty()
-                    && (logs.len() >= 5000 || last_send.elapsed() > Duration::from_millis(5000))
+                };
+                let flush = closed || counters.flush_requested();
+                if flush
+                    || logs.len() >= 5000
+                    || (!logs.is_empty() && last_send.elapsed() > Duration::from_millis(5000))
                 {
+                    let mut written = 0;
+                    let mut failed = 0;
                     for log in &logs {
-                        dest.write(&serde_json::to_vec(log).unwrap()).unwrap();
+                        match encoder.encode(log, &mut buf) {
+                            Ok(()) => written += 1,
+                            Err(err) => {
+                                eprintln!("logthread-beta: encoder: {}", err);
+                                failed += 1;
+                            }
+                        }
+                    }
+                    if counters.is_aborted() {
+                        break;
                     }
+                    let mut res = dest.write_all(&buf);
+                    if flush {
+                        res = res.and_then(|_| dest.flush());
+                    }
+                    if let Err(err) = res {
+                        eprintln!("logthread-beta: write: {}", err);
+                        failed += written;
+                        written = 0;
+                    }
+                    buf.clear();
+                    logs.clear();
+                    counters.failed.fetch_add(failed, Ordering::Release);
+                    counters.written.fetch_add(written, Ordering::Release);
                     last_send = Instant::now();
                 }
-                std::thread::sleep(Duration::from_millis(500));
+                if closed {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
             }
+            counters.finished.store(true, Ordering::Release);
             println!("logthread-beta closed");
-        }));
+        });
 
         DualThreadLogBacked {
             run_flag: running_flag_alpha,
-            join_handle_alpha,
-            join_handle_beta,
+            crash_slot: crash::register(&counters),
+            counters,
+            join_handle_alpha: Cell::new(Some(join_handle_alpha)),
+            join_handle_beta: Cell::new(Some(join_handle_beta)),
             log_tx: log_tx.into(),
         }
     }
 }
 
+impl<T, const RSIZE: usize> DualThreadLogBacked<RSIZE, T>
+where
+    T: Copy + Send,
+{
+    /// Stops threads after they write all logged events. Events logged after shutdown
+    /// are lost. If threads don't finish in timeout, they are told to stop writing and
+    /// detached, events not written yet are reported as lost. A write in progress at
+    /// timeout may still complete, [`Self::stats`] has exact counters after it.
+    pub fn shutdown(&self, timeout: Duration) -> LogStats {
+        self.run_flag.store(false, Ordering::Release);
+        let alpha = self.join_handle_alpha.take();
+        let beta = self.join_handle_beta.take();
+        if wait_until(timeout, || self.counters.is_finished()) {
+            for handle in [alpha, beta].into_iter().flatten() {
+                handle.join().unwrap();
+            }
+        } else {
+            self.counters.abort();
+        }
+        self.counters.final_stats()
+    }
+
+    /// Current counters, events in flight are neither written nor lost.
+    pub fn stats(&self) -> LogStats {
+        self.counters.stats()
+    }
+}
+
+/// Encoder, which header is written by sink.
+struct WithoutBegin<E>(E);
+
+impl<T, E: Encoder<T>> Encoder<T> for WithoutBegin<E> {
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        self.0.encode(event, dst)
+    }
+}
+
 impl<T, const RSIZE: usize> Drop for DualThreadLogBacked<RSIZE, T>
 where
     T: Copy + Send,
 {
     fn drop(&mut self) {
-        self.run_flag.store(false, Ordering::Relaxed);
-        self.join_handle_alpha.take().unwrap().join().unwrap();
-        self.join_handle_beta.take().unwrap().join().unwrap();
+        if self.run_flag.load(Ordering::Acquire) {
+            let stats = self.shutdown(DROP_TIMEOUT);
+            if stats.lost > 0 {
+                eprintln!("gts-logger: {} events lost", stats.lost);
+            }
+        }
+        if let Some(slot) = self.crash_slot.take() {
+            crash::unregister(slot);
+        }
     }
 }
 
@@ -135,12 +243,177 @@ where
     T: Copy + Send,
 {
     fn log(&self, event: T) -> Result<(), GtsLoggerError> {
+        if !self.run_flag.load(Ordering::Relaxed) {
+            self.counters.add_dropped();
+            return Err(GtsLoggerError::CommonError(
+                "backend is shut down".to_string(),
+            ));
+        }
         // SAFETY: Self is !Sync, only this function uses log_tx,
         // no reentrancy in this function.
         // but need verify reentrancy (by signal e.g.)
         // anyway refcell doesn't check signal-reentrancy either.
         let log_tx = unsafe { &mut *self.log_tx.get() };
-        log_tx.send(&event)?;
+        if let Err(err) = log_tx.send(&event) {
+            self.counters.add_dropped();
+            return Err(err.into());
+        }
+        self.counters.add_sent();
         Ok(())
     }
+
+    fn flush(&self, timeout: Duration) -> Result<(), GtsLoggerError> {
+        let target = self.counters.request_flush();
+        let done = wait_until(timeout, || self.counters.flushed(target));
+        if done && self.counters.done() >= target {
+            Ok(())
+        } else if done {
+            Err(GtsLoggerError::CommonError(
+                "backend is shut down".to_string(),
+            ))
+        } else {
+            Err(GtsLoggerError::Timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::LogLevel;
+    use crate::logbackend::encoder::TextEncoder;
+    use crate::logclient::LogClient;
+    use std::sync::Mutex;
+
+    /// Write to shared buffer, which could fail or stall.
+    #[derive(Clone, Default)]
+    struct SharedBuf {
+        data: Arc<Mutex<Vec<u8>>>,
+        fail: Arc<AtomicBool>,
+        stall: Arc<AtomicBool>,
+    }
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            while self.stall.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            if self.fail.load(Ordering::Relaxed) {
+                return Err(std::io::Error::other("disk is full"));
+            }
+            self.data.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn lines(&self) -> Vec<String> {
+            let data = self.data.lock().unwrap();
+            String::from_utf8_lossy(&data)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_flush_and_shutdown() {
+        let dest = SharedBuf::default();
+        let log_client = LogClient::<_, u64>::new(DualThreadLogBacked::<16, _>::new(dest.clone()));
+
+        for val in 0..10 {
+            log_client.log(val).unwrap();
+        }
+        log_client.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(dest.lines().len(), 10);
+        // flush without new events.
+        log_client.flush(Duration::from_secs(5)).unwrap();
+
+        // ring is smaller than burst, some events are dropped.
+        let mut dropped = 0;
+        for val in 10..100 {
+            if log_client.log_at(LogLevel::Warn, val).is_err() {
+                dropped += 1;
+            }
+        }
+        assert!(dropped > 0);
+
+        let stats = log_client.backend().shutdown(Duration::from_secs(5));
+        assert_eq!(stats.sent, 100 - dropped);
+        assert_eq!(stats.written, stats.sent);
+        assert_eq!(stats.lost, dropped);
+
+        // every event exactly once, in order.
+        let lines = dest.lines();
+        assert_eq!(lines.len() as u64, stats.written);
+        let values: Vec<u64> = lines
+            .iter()
+            .map(|line| serde_json::from_str::<LogEventTs<u64>>(line).unwrap().data)
+            .collect();
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert!(log_client.log(1).is_err());
+        // nothing pending, dropped event is not waited for.
+        log_client.flush(Duration::from_millis(10)).unwrap();
+        assert_eq!(log_client.backend().stats().lost, dropped + 1);
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let dest = SharedBuf::default();
+        let backend = DualThreadLogBacked::<16, _>::with_encoder(dest.clone(), TextEncoder::new());
+        let log_client = LogClient::<_, u64>::new(backend);
+
+        log_client.log(1).unwrap();
+        log_client.flush(Duration::from_secs(5)).unwrap();
+        dest.fail.store(true, Ordering::Relaxed);
+        log_client.log(2).unwrap();
+        log_client.log(3).unwrap();
+
+        let stats = log_client.backend().shutdown(Duration::from_secs(5));
+        assert_eq!(
+            stats,
+            LogStats {
+                sent: 3,
+                written: 1,
+                lost: 2
+            }
+        );
+        assert_eq!(dest.lines().len(), 1);
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let dest = SharedBuf::default();
+        let log_client = LogClient::<_, u64>::new(DualThreadLogBacked::<16, _>::new(dest.clone()));
+
+        dest.stall.store(true, Ordering::Relaxed);
+        log_client.log(1).unwrap();
+        // beta is stuck in write of the first event.
+        assert!(log_client.flush(Duration::from_millis(50)).is_err());
+        log_client.log(2).unwrap();
+
+        let backend = log_client.backend();
+        let stats = backend.shutdown(Duration::from_millis(50));
+        assert_eq!(
+            stats,
+            LogStats {
+                sent: 2,
+                written: 0,
+                lost: 2
+            }
+        );
+
+        // write in progress completes, nothing is written after it.
+        dest.stall.store(false, Ordering::Relaxed);
+        assert!(wait_until(Duration::from_secs(5), || backend
+            .counters
+            .is_finished()));
+        assert_eq!(backend.stats().written, 1);
+        assert_eq!(dest.lines().len(), 1);
+    }
 }
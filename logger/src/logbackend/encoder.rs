@@ -0,0 +1,380 @@
+//! Encoders of events for writing backends ([`DualThreadLogBacked`]).
+//!
+//! | encoder              | format                                             | T needs        |
+//! |----------------------|----------------------------------------------------|----------------|
+//! | [`JsonLinesEncoder`] | one JSON object per line                           | `Serialize`    |
+//! | [`LogfmtEncoder`]    | `ts=.. seqid=.. level=info data.field=..` per line | `Serialize`    |
+//! | [`TextEncoder`]      | `[INFO] @ts (+diff ns) {:?}` per line              | `Debug`        |
+//! | [`CsvEncoder`]       | `timestamp,seqid,level,data` with data as JSON     | `Serialize`    |
+//! | [`BincodeEncoder`]   | bincode records prefixed by u32 length             | `Serialize`    |
+//! | [`CborEncoder`]      | CBOR sequence (RFC 8742)                           | `Serialize`    |
+//! | [`LogFileEncoder`]   | [`crate::logfile`] format, read by `gts-logcat`    | `GtsLogEvent`  |
+//!
+//! bincode and CBOR encoders are enabled by default features `bincode` and `cbor`.
+//! bincode isn't self-describing, so data with tagged enums (`#[serde(tag = ..)]`) could be
+//! written, but not read back by serde.
+//!
+//! [`DualThreadLogBacked`]: crate::logbackend::dualthread::DualThreadLogBacked
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::level::LogLevel;
+//! use gts_logger::logbackend::encoder::{Encoder, LogfmtEncoder, TextEncoder};
+//! use gts_logger::logclient::LogEventTs;
+//!
+//! let mut buf = Vec::new();
+//! let mut encoder = TextEncoder::new();
+//! encoder.encode(&LogEventTs::new(100, 0, LogLevel::Info, 5u32), &mut buf)?;
+//! encoder.encode(&LogEventTs::new(130, 0, LogLevel::Warn, 6u32), &mut buf)?;
+//! assert_eq!(buf, b"[INFO] @100 (-) 5\n[WARN] @130 (+30 ns) 6\n");
+//!
+//! buf.clear();
+//! LogfmtEncoder::new().encode(&LogEventTs::new(100, 1, LogLevel::Debug, "a b"), &mut buf)?;
+//! assert_eq!(buf, b"ts=100 seqid=1 level=debug data=\"a b\"\n");
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+
+use crate::error::GtsLoggerError;
+use crate::event::GtsLogEvent;
+use crate::logclient::LogEventTs;
+use crate::logfile::{encode_record, LogFileHeader};
+use serde::Serialize;
+use std::fmt::Debug;
+use std::io::Write;
+use std::marker::PhantomData;
+
+/// Encodes events of log to bytes, called by backend thread only.
+pub trait Encoder<T>: Send {
+    /// Appends prefix of output (e.g. header), called once before the first event.
+    fn begin(&mut self, _dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        Ok(())
+    }
+
+    /// Appends encoded event.
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError>;
+}
+
+fn json_error(err: serde_json::Error) -> GtsLoggerError {
+    GtsLoggerError::CommonError(err.to_string())
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct JsonLinesEncoder;
+
+impl JsonLinesEncoder {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<T: Serialize> Encoder<T> for JsonLinesEncoder {
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        serde_json::to_writer(&mut *dst, event).map_err(json_error)?;
+        dst.push(b'\n');
+        Ok(())
+    }
+}
+
+/// logfmt line, nested fields of data are flattened to dotted keys.
+#[derive(Debug, Default, Clone)]
+pub struct LogfmtEncoder;
+
+impl LogfmtEncoder {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn write_value(key: &str, value: &serde_json::Value, dst: &mut Vec<u8>) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                for (name, field) in fields {
+                    Self::write_value(&format!("{}.{}", key, name), field, dst);
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for (idx, item) in items.iter().enumerate() {
+                    Self::write_value(&format!("{}.{}", key, idx), item, dst);
+                }
+            }
+            serde_json::Value::Null => {
+                let _ = write!(dst, " {}=", key);
+            }
+            serde_json::Value::String(text) => {
+                let needs_quotes = text.is_empty()
+                    || text
+                        .chars()
+                        .any(|ch| ch == ' ' || ch == '=' || ch == '"' || ch.is_control());
+                if needs_quotes {
+                    let _ = write!(dst, " {}={:?}", key, text);
+                } else {
+                    let _ = write!(dst, " {}={}", key, text);
+                }
+            }
+            other => {
+                let _ = write!(dst, " {}={}", key, other);
+            }
+        }
+    }
+}
+
+impl<T: Serialize> Encoder<T> for LogfmtEncoder {
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        let data = serde_json::to_value(&event.data).map_err(json_error)?;
+        write!(
+            dst,
+            "ts={} seqid={} level={}",
+            event.timestamp,
+            event.seqid,
+            event.level.as_str().to_ascii_lowercase()
+        )?;
+        Self::write_value("data", &data, dst);
+        dst.push(b'\n');
+        Ok(())
+    }
+}
+
+/// Human readable text, the same as [`ConsoleThreadLogBacked`](super::consolelogger::ConsoleThreadLogBacked) writes.
+#[derive(Debug, Default, Clone)]
+pub struct TextEncoder {
+    last_ts: Option<u64>,
+}
+
+impl TextEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: Debug> Encoder<T> for TextEncoder {
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        match self.last_ts {
+            None => write!(dst, "[{}] @{} (-) ", event.level, event.timestamp)?,
+            Some(last_ts) => write!(
+                dst,
+                "[{}] @{} (+{} ns) ",
+                event.level,
+                event.timestamp,
+                event.timestamp.saturating_sub(last_ts)
+            )?,
+        }
+        writeln!(dst, "{:?}", event.data)?;
+        self.last_ts = Some(event.timestamp);
+        Ok(())
+    }
+}
+
+/// CSV with header, data column is JSON of data (events of different variants
+/// have different fields).
+#[derive(Debug, Default, Clone)]
+pub struct CsvEncoder;
+
+impl CsvEncoder {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<T: Serialize> Encoder<T> for CsvEncoder {
+    fn begin(&mut self, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        dst.extend_from_slice(b"timestamp,seqid,level,data\n");
+        Ok(())
+    }
+
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        let data = serde_json::to_string(&event.data).map_err(json_error)?;
+        write!(dst, "{},{},{},", event.timestamp, event.seqid, event.level)?;
+        if data.contains([',', '"', '\n', '\r']) {
+            write!(dst, "\"{}\"", data.replace('"', "\"\""))?;
+        } else {
+            dst.extend_from_slice(data.as_bytes());
+        }
+        dst.push(b'\n');
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone)]
+pub struct BincodeEncoder;
+
+#[cfg(feature = "bincode")]
+impl BincodeEncoder {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl<T: Serialize> Encoder<T> for BincodeEncoder {
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        let start = dst.len();
+        dst.extend_from_slice(&[0; 4]);
+        bincode::serialize_into(&mut *dst, event)
+            .map_err(|err| GtsLoggerError::CommonError(err.to_string()))?;
+        let len = (dst.len() - start - 4) as u32;
+        dst[start..start + 4].copy_from_slice(&len.to_le_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Debug, Default, Clone)]
+pub struct CborEncoder;
+
+#[cfg(feature = "cbor")]
+impl CborEncoder {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl<T: Serialize> Encoder<T> for CborEncoder {
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        ciborium::into_writer(event, &mut *dst)
+            .map_err(|err| GtsLoggerError::CommonError(err.to_string()))
+    }
+}
+
+/// Binary log file, see [`crate::logfile`].
+pub struct LogFileEncoder<T> {
+    _owns_t: PhantomData<fn(T)>,
+}
+
+impl<T> LogFileEncoder<T> {
+    pub fn new() -> Self {
+        Self {
+            _owns_t: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for LogFileEncoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: GtsLogEvent> Encoder<T> for LogFileEncoder<T> {
+    fn begin(&mut self, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        dst.extend_from_slice(&LogFileHeader::of::<T>().encode());
+        Ok(())
+    }
+
+    fn encode(&mut self, event: &LogEventTs<T>, dst: &mut Vec<u8>) -> Result<(), GtsLoggerError> {
+        encode_record(event, dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::LogLevel;
+    use crate::logfile::LogFileReader;
+    use arrayvec::ArrayString;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub struct LogOneStruct {
+        some_num: u64,
+        some_string: ArrayString<16>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, GtsLogEvent)]
+    #[serde(tag = "t", content = "c")]
+    pub enum LogEvent {
+        LogOneOne(LogOneStruct),
+        Empty,
+    }
+
+    fn events() -> [LogEventTs<LogEvent>; 2] {
+        [
+            LogEventTs::new(
+                100,
+                0,
+                LogLevel::Info,
+                LogEvent::LogOneOne(LogOneStruct {
+                    some_num: 5,
+                    some_string: ArrayString::from("a,\"b\" c").unwrap(),
+                }),
+            ),
+            LogEventTs::new(150, 1, LogLevel::Error, LogEvent::Empty),
+        ]
+    }
+
+    fn encode_all(mut encoder: impl Encoder<LogEvent>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encoder.begin(&mut buf).unwrap();
+        for event in &events() {
+            encoder.encode(event, &mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn test_text_encoders() {
+        let json = String::from_utf8(encode_all(JsonLinesEncoder::new())).unwrap();
+        let lines: Vec<_> = json.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let decoded: LogEventTs<LogEvent> = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(decoded, events()[0]);
+
+        assert_eq!(
+            String::from_utf8(encode_all(LogfmtEncoder::new())).unwrap(),
+            "ts=100 seqid=0 level=info data.t=LogOneOne data.c.some_num=5 \
+             data.c.some_string=\"a,\\\"b\\\" c\"\n\
+             ts=150 seqid=1 level=error data.t=Empty\n"
+        );
+        assert_eq!(
+            String::from_utf8(encode_all(TextEncoder::new())).unwrap(),
+            "[INFO] @100 (-) LogOneOne(LogOneStruct { some_num: 5, some_string: \"a,\\\"b\\\" c\" })\n\
+             [ERROR] @150 (+50 ns) Empty\n"
+        );
+        assert_eq!(
+            String::from_utf8(encode_all(CsvEncoder::new())).unwrap(),
+            "timestamp,seqid,level,data\n\
+             100,0,INFO,\"{\"\"t\"\":\"\"LogOneOne\"\",\"\"c\"\":{\"\"some_num\"\":5,\
+             \"\"some_string\"\":\"\"a,\\\"\"b\\\"\" c\"\"}}\"\n\
+             150,1,ERROR,\"{\"\"t\"\":\"\"Empty\"\"}\"\n"
+        );
+    }
+
+    #[test]
+    fn test_binary_encoders() {
+        let file = encode_all(LogFileEncoder::new());
+        let mut reader = LogFileReader::new(file.as_slice()).unwrap();
+        for event in events() {
+            assert_eq!(reader.next_event::<LogEvent>().unwrap(), Some(event));
+        }
+        assert!(reader.next_raw().unwrap().is_none());
+
+        #[cfg(feature = "bincode")]
+        {
+            // tagged LogEvent can't be deserialized by bincode, it isn't self-describing.
+            let LogEvent::LogOneOne(data) = events()[0].data else {
+                unreachable!()
+            };
+            let event = LogEventTs::new(100, 3, LogLevel::Warn, data);
+            let mut buf = Vec::new();
+            BincodeEncoder::new().encode(&event, &mut buf).unwrap();
+            let len = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+            assert_eq!(buf.len(), 4 + len);
+            let decoded: LogEventTs<LogOneStruct> = bincode::deserialize(&buf[4..]).unwrap();
+            assert_eq!(decoded, event);
+            assert!(encode_all(BincodeEncoder::new()).len() > buf.len());
+        }
+
+        #[cfg(feature = "cbor")]
+        {
+            let buf = encode_all(CborEncoder::new());
+            let mut src = buf.as_slice();
+            for event in events() {
+                let decoded: LogEventTs<LogEvent> = ciborium::from_reader(&mut src).unwrap();
+                assert_eq!(decoded, event);
+            }
+            assert!(src.is_empty());
+        }
+    }
+}
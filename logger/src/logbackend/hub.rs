@@ -0,0 +1,602 @@
+//! Hub of per-thread backends, merged by one consumer thread to timestamp-ordered output.
+//!
+//! Every [`HubBackend`] has own SPSC ring, so producer threads never contend. Consumer thread
+//! drains all rings, holds events for merge delay (10 ms by default) and writes them ordered by
+//! timestamp. Event, which reaches ring later than merge delay after its timestamp (e.g. producer
+//! was preempted between timestamp and send), is written when it arrives, so it's out of order.
+//! Flush writes all pending events regardless of merge delay.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::logbackend::encoder::TextEncoder;
+//! use gts_logger::logbackend::hub::LogHubOptions;
+//! use std::time::Duration;
+//!
+//! let hub = LogHubOptions::new().open::<1024, u64>(std::io::sink(), TextEncoder::new());
+//! std::thread::scope(|scope| {
+//!     for val in 0..4 {
+//!         let hub = &hub;
+//!         scope.spawn(move || hub.client().log(val).unwrap());
+//!     }
+//! });
+//! let stats = hub.shutdown(Duration::from_secs(5));
+//! assert_eq!(stats.written, 4);
+//! ```
+
+use crate::error::GtsLoggerError;
+use crate::logbackend::crash;
+use crate::logbackend::dualthread::DROP_TIMEOUT;
+use crate::logbackend::encoder::Encoder;
+use crate::logbackend::stats::{wait_until, LogStats, ThreadCounters};
+use crate::logbackend::LogBackend;
+use crate::logclient::{LogClient, LogEventTs};
+use gts_transport::error::GtsTransportError;
+use gts_transport::membackend::memchunk::MemChunkHolder;
+use gts_transport::sync::lfringspsc::{
+    spsc_ring_pair, SpScRingData, SpScRingReceiver, SpScRingSender,
+};
+use std::cell::UnsafeCell;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+type Ring<const RSIZE: usize, T> = MemChunkHolder<SpScRingData<RSIZE, LogEventTs<T>>>;
+
+/// Options of [`LogHub`].
+#[derive(Debug, Clone)]
+pub struct LogHubOptions {
+    merge_delay: Duration,
+    poll: Duration,
+}
+
+impl Default for LogHubOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogHubOptions {
+    pub fn new() -> Self {
+        LogHubOptions {
+            merge_delay: Duration::from_millis(10),
+            poll: Duration::from_millis(1),
+        }
+    }
+
+    /// Time events are held to be ordered with events of other threads.
+    pub fn merge_delay(mut self, merge_delay: Duration) -> Self {
+        self.merge_delay = merge_delay;
+        self
+    }
+
+    /// Sleep of consumer thread between drains of rings.
+    pub fn poll(mut self, poll: Duration) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    /// Spawns consumer thread, which writes events to dest in format of encoder.
+    pub fn open<const RSIZE: usize, T>(
+        self,
+        dest: impl Write + Send + 'static,
+        encoder: impl Encoder<T> + 'static,
+    ) -> LogHub<RSIZE, T>
+    where
+        T: Copy + Send + 'static,
+    {
+        let shared = Arc::new(HubShared {
+            running: AtomicBool::new(true),
+            finished: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            next_id: AtomicU64::new(0),
+            sources: Mutex::new(Sources::default()),
+        });
+        let (msg_tx, msg_rx) = channel();
+        let consumer = Consumer {
+            shared: shared.clone(),
+            msg_rx,
+            sources: HashMap::new(),
+            pending: BinaryHeap::new(),
+            arrived: 0,
+            options: self,
+        };
+        let join_handle = std::thread::Builder::new()
+            .name("gts-loghub".to_string())
+            .spawn(move || consumer.run(dest, encoder))
+            .expect("spawn of gts-loghub thread");
+        LogHub {
+            shared,
+            msg_tx,
+            join_handle: Mutex::new(Some(join_handle)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sources {
+    live: Vec<Arc<ThreadCounters>>,
+    /// Final counters of closed backends.
+    closed: LogStats,
+}
+
+struct HubShared {
+    running: AtomicBool,
+    finished: AtomicBool,
+    /// Shutdown timed out, consumer must exit without writing anything more.
+    aborted: AtomicBool,
+    next_id: AtomicU64,
+    sources: Mutex<Sources>,
+}
+
+enum HubMsg<const RSIZE: usize, T: Copy> {
+    Open(
+        u64,
+        SpScRingReceiver<RSIZE, LogEventTs<T>, Ring<RSIZE, T>>,
+        Arc<ThreadCounters>,
+    ),
+    /// Backend is dropped, ring is removed when it's drained.
+    Close(u64),
+}
+
+/// Handle of hub, shared by producer threads, which take backends (or clients) from it.
+pub struct LogHub<const RSIZE: usize, T: Copy> {
+    shared: Arc<HubShared>,
+    msg_tx: Sender<HubMsg<RSIZE, T>>,
+    join_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<const RSIZE: usize, T: Copy> LogHub<RSIZE, T> {
+    /// Backend with new ring, for one producer thread.
+    pub fn backend(&self) -> HubBackend<RSIZE, T> {
+        let (log_tx, log_rx) = spsc_ring_pair::<RSIZE, LogEventTs<T>, _>(MemChunkHolder::zeroed());
+        let counters = Arc::new(ThreadCounters::default());
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared
+            .sources
+            .lock()
+            .unwrap()
+            .live
+            .push(counters.clone());
+        // consumer is gone after shutdown, backend fails on log then.
+        let _ = self.msg_tx.send(HubMsg::Open(id, log_rx, counters.clone()));
+        HubBackend {
+            id,
+            crash_slot: crash::register(&counters),
+            counters,
+            shared: self.shared.clone(),
+            msg_tx: self.msg_tx.clone(),
+            log_tx: log_tx.into(),
+        }
+    }
+
+    pub fn client(&self) -> LogClient<HubBackend<RSIZE, T>, T> {
+        LogClient::new(self.backend())
+    }
+
+    /// Waits until events logged by all backends before the call are written.
+    pub fn flush(&self, timeout: Duration) -> Result<(), GtsLoggerError> {
+        let live = self.shared.sources.lock().unwrap().live.clone();
+        let targets: Vec<u64> = live
+            .iter()
+            .map(|counters| counters.request_flush())
+            .collect();
+        let flushed = || {
+            live.iter()
+                .zip(&targets)
+                .all(|(counters, &target)| counters.flushed(target))
+        };
+        if wait_until(timeout, flushed) {
+            Ok(())
+        } else {
+            Err(GtsLoggerError::Timeout)
+        }
+    }
+
+    /// Stops consumer thread after it writes all logged events, see
+    /// [`DualThreadLogBacked::shutdown`](crate::logbackend::dualthread::DualThreadLogBacked::shutdown).
+    pub fn shutdown(&self, timeout: Duration) -> LogStats {
+        self.shared.running.store(false, Ordering::Release);
+        let handle = self.join_handle.lock().unwrap().take();
+        if wait_until(timeout, || self.shared.finished.load(Ordering::Acquire)) {
+            if let Some(handle) = handle {
+                handle.join().unwrap();
+            }
+        } else {
+            self.shared.aborted.store(true, Ordering::Release);
+        }
+        let sources = self.shared.sources.lock().unwrap();
+        sources
+            .live
+            .iter()
+            .fold(sources.closed, |sum, counters| sum + counters.final_stats())
+    }
+
+    /// Sum of counters of all backends, events in flight are neither written nor lost.
+    pub fn stats(&self) -> LogStats {
+        let sources = self.shared.sources.lock().unwrap();
+        sources
+            .live
+            .iter()
+            .fold(sources.closed, |sum, counters| sum + counters.stats())
+    }
+}
+
+impl<const RSIZE: usize, T: Copy> Drop for LogHub<RSIZE, T> {
+    fn drop(&mut self) {
+        if self.shared.running.load(Ordering::Acquire) {
+            let stats = self.shutdown(DROP_TIMEOUT);
+            if stats.lost > 0 {
+                eprintln!("gts-logger: {} events lost", stats.lost);
+            }
+        }
+    }
+}
+
+/// Backend of one producer thread, see [`LogHub::backend`].
+pub struct HubBackend<const RSIZE: usize, T: Copy> {
+    id: u64,
+    counters: Arc<ThreadCounters>,
+    /// Slot in registry of crash handlers.
+    crash_slot: Option<usize>,
+    shared: Arc<HubShared>,
+    msg_tx: Sender<HubMsg<RSIZE, T>>,
+    log_tx: UnsafeCell<SpScRingSender<RSIZE, LogEventTs<T>, Ring<RSIZE, T>>>,
+}
+
+impl<const RSIZE: usize, T: Copy> HubBackend<RSIZE, T> {
+    /// Counters of this backend.
+    pub fn stats(&self) -> LogStats {
+        self.counters.stats()
+    }
+}
+
+impl<const RSIZE: usize, T: Copy> Drop for HubBackend<RSIZE, T> {
+    fn drop(&mut self) {
+        let _ = self.msg_tx.send(HubMsg::Close(self.id));
+        if let Some(slot) = self.crash_slot.take() {
+            crash::unregister(slot);
+        }
+    }
+}
+
+impl<const RSIZE: usize, T: Copy> LogBackend<LogEventTs<T>> for HubBackend<RSIZE, T> {
+    fn log(&self, event: LogEventTs<T>) -> Result<(), GtsLoggerError> {
+        if !self.shared.running.load(Ordering::Relaxed) {
+            self.counters.add_dropped();
+            return Err(GtsLoggerError::CommonError("hub is shut down".to_string()));
+        }
+        // SAFETY: Self is !Sync, only this function uses log_tx,
+        // no reentrancy in this function.
+        let log_tx = unsafe { &mut *self.log_tx.get() };
+        if let Err(err) = log_tx.send(&event) {
+            self.counters.add_dropped();
+            return Err(err.into());
+        }
+        self.counters.add_sent();
+        Ok(())
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<(), GtsLoggerError> {
+        let target = self.counters.request_flush();
+        if !wait_until(timeout, || self.counters.flushed(target)) {
+            Err(GtsLoggerError::Timeout)
+        } else if self.counters.done() < target {
+            Err(GtsLoggerError::CommonError("hub is shut down".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+struct Source<const RSIZE: usize, T: Copy> {
+    log_rx: SpScRingReceiver<RSIZE, LogEventTs<T>, Ring<RSIZE, T>>,
+    counters: Arc<ThreadCounters>,
+    closed: bool,
+    /// Events in heap of consumer.
+    pending: u64,
+    written: u64,
+    failed: u64,
+}
+
+/// Event waiting for merge, heap is ordered by (timestamp, arrival) ascending.
+struct Pending<T> {
+    key: (u64, u64),
+    source: u64,
+    event: LogEventTs<T>,
+}
+
+impl<T> PartialEq for Pending<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<T> Eq for Pending<T> {}
+
+impl<T> PartialOrd for Pending<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Pending<T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other.key.cmp(&self.key)
+    }
+}
+
+struct Consumer<const RSIZE: usize, T: Copy> {
+    shared: Arc<HubShared>,
+    msg_rx: Receiver<HubMsg<RSIZE, T>>,
+    sources: HashMap<u64, Source<RSIZE, T>>,
+    pending: BinaryHeap<Pending<T>>,
+    arrived: u64,
+    options: LogHubOptions,
+}
+
+impl<const RSIZE: usize, T: Copy> Consumer<RSIZE, T> {
+    fn run(mut self, mut dest: impl Write, mut encoder: impl Encoder<T>) {
+        let anc = minstant::Anchor::new();
+        let mut buf = Vec::new();
+        if let Err(err) = encoder.begin(&mut buf) {
+            eprintln!("gts-loghub: encoder: {}", err);
+        }
+        while !self.shared.aborted.load(Ordering::Acquire) {
+            // events sent before stop are in rings already.
+            let running = self.shared.running.load(Ordering::Acquire);
+            self.receive();
+            let flush = !running
+                || self
+                    .sources
+                    .values()
+                    .any(|source| source.counters.flush_requested());
+            let cutoff = match flush {
+                true => u64::MAX,
+                false => minstant::Instant::now()
+                    .as_unix_nanos(&anc)
+                    .saturating_sub(self.options.merge_delay.as_nanos() as u64),
+            };
+            while self
+                .pending
+                .peek()
+                .is_some_and(|pending| pending.key.0 <= cutoff)
+            {
+                let pending = self.pending.pop().unwrap();
+                let source = self.sources.get_mut(&pending.source).unwrap();
+                source.pending -= 1;
+                match encoder.encode(&pending.event, &mut buf) {
+                    Ok(()) => source.written += 1,
+                    Err(err) => {
+                        eprintln!("gts-loghub: encoder: {}", err);
+                        source.failed += 1;
+                    }
+                }
+            }
+            if self.shared.aborted.load(Ordering::Acquire) {
+                break;
+            }
+            if !buf.is_empty() || flush {
+                let mut res = dest.write_all(&buf);
+                if flush {
+                    res = res.and_then(|_| dest.flush());
+                }
+                if let Err(err) = &res {
+                    eprintln!("gts-loghub: write: {}", err);
+                }
+                buf.clear();
+                for source in self.sources.values_mut() {
+                    if res.is_err() {
+                        source.failed += std::mem::take(&mut source.written);
+                    }
+                    let counters = &source.counters;
+                    counters.failed.fetch_add(source.failed, Ordering::Release);
+                    counters
+                        .written
+                        .fetch_add(source.written, Ordering::Release);
+                    source.written = 0;
+                    source.failed = 0;
+                }
+            }
+            self.remove_closed();
+            if !running {
+                break;
+            }
+            std::thread::sleep(self.options.poll);
+        }
+        for source in self.sources.values() {
+            source.counters.finished.store(true, Ordering::Release);
+        }
+        self.shared.finished.store(true, Ordering::Release);
+    }
+
+    /// Takes new and closed backends, then drains rings.
+    fn receive(&mut self) {
+        while let Ok(msg) = self.msg_rx.try_recv() {
+            match msg {
+                HubMsg::Open(id, log_rx, counters) => {
+                    let source = Source {
+                        log_rx,
+                        counters,
+                        closed: false,
+                        pending: 0,
+                        written: 0,
+                        failed: 0,
+                    };
+                    self.sources.insert(id, source);
+                }
+                HubMsg::Close(id) => {
+                    if let Some(source) = self.sources.get_mut(&id) {
+                        source.closed = true;
+                    }
+                }
+            }
+        }
+        for (&id, source) in &mut self.sources {
+            loop {
+                match source.log_rx.try_recv_value() {
+                    Ok(event) => {
+                        self.pending.push(Pending {
+                            key: (event.timestamp, self.arrived),
+                            source: id,
+                            event,
+                        });
+                        self.arrived += 1;
+                        source.pending += 1;
+                    }
+                    Err(GtsTransportError::WouldBlock) => break,
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Rings of closed backends are drained after close, so they're empty.
+    fn remove_closed(&mut self) {
+        let closed: Vec<u64> = self
+            .sources
+            .iter()
+            .filter(|(_, source)| source.closed && source.pending == 0)
+            .map(|(&id, _)| id)
+            .collect();
+        if closed.is_empty() {
+            return;
+        }
+        let mut sources = self.shared.sources.lock().unwrap();
+        for id in closed {
+            let source = self.sources.remove(&id).unwrap();
+            sources
+                .live
+                .retain(|counters| !Arc::ptr_eq(counters, &source.counters));
+            sources.closed = sources.closed + source.counters.final_stats();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logbackend::encoder::JsonLinesEncoder;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
+    pub struct Tick {
+        thread: u32,
+        seq: u32,
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn events(&self) -> Vec<LogEventTs<Tick>> {
+            let data = self.0.lock().unwrap();
+            String::from_utf8_lossy(&data)
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_merge_order() {
+        let dest = SharedBuf::default();
+        // no event is written before shutdown.
+        let hub = LogHubOptions::new()
+            .merge_delay(Duration::from_secs(60))
+            .open::<256, Tick>(dest.clone(), JsonLinesEncoder::new());
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                // client is created in one thread and moved to other.
+                let log_client = hub.client();
+                scope.spawn(move || {
+                    for seq in 0..100 {
+                        log_client.log(Tick { thread, seq }).unwrap();
+                        if seq % 10 == 0 {
+                            std::thread::yield_now();
+                        }
+                    }
+                });
+            }
+        });
+        assert!(dest.events().is_empty());
+
+        let stats = hub.shutdown(Duration::from_secs(5));
+        assert_eq!(
+            stats,
+            LogStats {
+                sent: 400,
+                written: 400,
+                lost: 0
+            }
+        );
+        let events = dest.events();
+        assert_eq!(events.len(), 400);
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        for thread in 0..4 {
+            let seqs: Vec<u32> = events
+                .iter()
+                .filter(|event| event.data.thread == thread)
+                .map(|event| event.data.seq)
+                .collect();
+            assert_eq!(seqs, (0..100).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_flush_and_close() {
+        let dest = SharedBuf::default();
+        let hub = LogHubOptions::new()
+            .merge_delay(Duration::from_secs(60))
+            .open::<16, Tick>(dest.clone(), JsonLinesEncoder::new());
+
+        let first = hub.client();
+        let second = hub.client();
+        first.log(Tick { thread: 0, seq: 0 }).unwrap();
+        second.log(Tick { thread: 1, seq: 0 }).unwrap();
+        // flush ignores merge delay.
+        first.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(dest.events().len(), 2);
+
+        second.log(Tick { thread: 1, seq: 1 }).unwrap();
+        drop(second);
+        hub.flush(Duration::from_secs(5)).unwrap();
+        assert_eq!(dest.events().len(), 3);
+        // closed backend is counted after removal too.
+        assert!(wait_until(Duration::from_secs(5), || {
+            hub.shared.sources.lock().unwrap().live.len() == 1
+        }));
+        assert_eq!(hub.stats().sent, 3);
+
+        let stats = hub.shutdown(Duration::from_secs(5));
+        assert_eq!(
+            stats,
+            LogStats {
+                sent: 3,
+                written: 3,
+                lost: 0
+            }
+        );
+        assert!(first.log(Tick { thread: 0, seq: 1 }).is_err());
+        assert!(hub.client().log(Tick { thread: 2, seq: 0 }).is_err());
+        assert_eq!(hub.stats().lost, 2);
+    }
+}
@@ -19,6 +19,12 @@ impl<T: Copy> MockLogBacked<T> {
     }
 }
 
+impl<T: Copy> Default for MockLogBacked<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Copy> LogBackend<T> for MockLogBacked<T> {
     fn log(&self, event: T) -> Result<(), GtsLoggerError> {
         self.queue.borrow_mut().push_back(event);
@@ -28,6 +34,7 @@ impl<T: Copy> LogBackend<T> for MockLogBacked<T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::level::{LevelThreshold, LogLevel, LogLevelFilter};
     use crate::logbackend::mock::MockLogBacked;
     use crate::logclient::LogClient;
     use arrayvec::ArrayString;
@@ -79,4 +86,30 @@ mod tests {
         let rr = log_client.backend().pop_front();
         assert!(rr.is_none());
     }
+
+    #[test]
+    fn filter_levels() {
+        static THRESHOLD: LevelThreshold = LevelThreshold::new(LogLevelFilter::Warn);
+        let event = LogEvent::LogTwo(LogTwoStruct {
+            some_string: ArrayString::from("lvl").unwrap(),
+        });
+        let log_client =
+            LogClient::<_, LogEvent>::new(MockLogBacked::new()).with_threshold(&THRESHOLD);
+
+        log_client.log(event).unwrap();
+        log_client.log_at(LogLevel::Debug, event).unwrap();
+        assert!(log_client.backend().pop_front().is_none());
+
+        log_client.log_at(LogLevel::Error, event).unwrap();
+        log_client.log_same_at(LogLevel::Warn, event).unwrap();
+        let rr = log_client.backend().pop_front();
+        assert!(matches!(rr, Some(ev) if ev.level == LogLevel::Error && ev.seqid == 0));
+        let rr = log_client.backend().pop_front();
+        assert!(matches!(rr, Some(ev) if ev.level == LogLevel::Warn && ev.seqid == 1));
+
+        THRESHOLD.set(LogLevelFilter::Debug);
+        log_client.log_at(LogLevel::Debug, event).unwrap();
+        let rr = log_client.backend().pop_front();
+        assert!(matches!(rr, Some(ev) if ev.level == LogLevel::Debug));
+    }
 }
@@ -0,0 +1,560 @@
+//! Rolling file sink: [`RollingFileWriter`] writes to one file, which is rotated when it
+//! reaches size limit or at midnight UTC. Rotated file is renamed to
+//! `<path>.<YYYYMMDD-HHMMSS.mmm>` (UTC time of rotation), compressed (gzip or zstd) and
+//! old generations are removed by background thread, so writer is blocked only by rename.
+//!
+//! Rotation happens only between `write` calls, so every batch written by backend
+//! ([`DualThreadLogBacked`]) lands in one file, file could exceed size limit by one batch.
+//! Every file starts with header (see [`RollingFileWriter::with_header`]), e.g. header
+//! of binary log file, use [`DualThreadLogBacked::with_rolling_file`] for it.
+//!
+//! [`DualThreadLogBacked`]: crate::logbackend::dualthread::DualThreadLogBacked
+//! [`DualThreadLogBacked::with_rolling_file`]: crate::logbackend::dualthread::DualThreadLogBacked::with_rolling_file
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::logbackend::rolling::{Compression, RollingOptions};
+//! use std::io::Write;
+//!
+//! let dir = std::env::temp_dir().join(format!("gts_rolling_doc_{}", std::process::id()));
+//! std::fs::create_dir_all(&dir)?;
+//! let mut writer = RollingOptions::new()
+//!     .max_size(10)
+//!     .daily(true)
+//!     .keep(2)
+//!     .compression(Compression::None)
+//!     .open(dir.join("app.log"))?;
+//! for _ in 0..4 {
+//!     writer.write_all(b"0123456789")?;
+//! }
+//! drop(writer);
+//! // current file and 2 rotated generations.
+//! assert_eq!(std::fs::read_dir(&dir)?.count(), 3);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+
+use crate::error::GtsLoggerError;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Compression of rotated files.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// `.gz`, needs `gzip` feature (default one).
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// `.zst`, needs `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Some("gz"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    fn compress(self, src: &Path, dst: &Path) -> io::Result<()> {
+        match self {
+            // rotated file is kept as is.
+            Compression::None => {
+                let _ = (src, dst);
+            }
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(
+                    File::create(dst)?,
+                    flate2::Compression::default(),
+                );
+                io::copy(&mut File::open(src)?, &mut encoder)?;
+                encoder.finish()?.sync_all()?;
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(File::create(dst)?, 0)?;
+                io::copy(&mut File::open(src)?, &mut encoder)?;
+                encoder.finish()?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rotation policy, by default file is never rotated and all generations are kept.
+#[derive(Debug, Default, Clone)]
+pub struct RollingOptions {
+    max_size: Option<u64>,
+    daily: bool,
+    keep: Option<usize>,
+    compression: Compression,
+}
+
+impl RollingOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rotates file, when it would exceed bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotates file at midnight UTC.
+    pub fn daily(mut self, daily: bool) -> Self {
+        self.daily = daily;
+        self
+    }
+
+    /// Number of rotated files to keep, older ones are removed.
+    pub fn keep(mut self, generations: usize) -> Self {
+        self.keep = Some(generations);
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Opens writer of path, existing non-empty file is rotated as previous generation.
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<RollingFileWriter, GtsLoggerError> {
+        RollingFileWriter::open(path.as_ref(), self.clone())
+    }
+}
+
+pub struct RollingFileWriter {
+    path: PathBuf,
+    options: RollingOptions,
+    file: File,
+    size: u64,
+    day: u64,
+    header: Vec<u8>,
+    rotations: u64,
+    jobs: Option<Sender<PathBuf>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// `YYYYMMDD-HHMMSS.mmm` of unix millis.
+fn format_utc(millis: u64) -> String {
+    let secs = millis / 1000;
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / DAY_SECS) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let time = secs % DAY_SECS;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        millis % 1000
+    )
+}
+
+/// Appends `.<extension>` to path.
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", extension));
+    PathBuf::from(name)
+}
+
+/// (stamp, counter) of rotated file name without prefix, e.g. `<stamp>-001.gz`,
+/// None for other files, including unfinished `.tmp`.
+fn generation_key(name: &str) -> Option<(String, u32)> {
+    const STAMP_LEN: usize = "YYYYMMDD-HHMMSS.mmm".len();
+    let stamp = name.get(..STAMP_LEN)?;
+    if !stamp.starts_with(|ch: char| ch.is_ascii_digit()) || name.ends_with(".tmp") {
+        return None;
+    }
+    let rest = &name[STAMP_LEN..];
+    let counter = rest.split('.').next().unwrap_or_default();
+    let counter = match counter.strip_prefix('-') {
+        Some(counter) => counter.parse().ok()?,
+        None if counter.is_empty() => 0,
+        None => return None,
+    };
+    Some((stamp.to_string(), counter))
+}
+
+impl RollingFileWriter {
+    fn open(path: &Path, options: RollingOptions) -> Result<Self, GtsLoggerError> {
+        let (jobs, rotated) = channel::<PathBuf>();
+        let worker_path = path.to_path_buf();
+        let worker_options = options.clone();
+        let worker = std::thread::Builder::new()
+            .name("gts-rolling".to_string())
+            .spawn(move || {
+                for file in rotated {
+                    if let Err(err) = Self::finish_rotated(&worker_path, &worker_options, &file) {
+                        eprintln!("gts-rolling: {}: {}", file.display(), err);
+                    }
+                }
+            })?;
+
+        let mut writer = RollingFileWriter {
+            path: path.to_path_buf(),
+            options,
+            file: Self::open_file(path)?,
+            size: 0,
+            day: now_millis() / 1000 / DAY_SECS,
+            header: Vec::new(),
+            rotations: 0,
+            jobs: Some(jobs),
+            worker: Some(worker),
+        };
+        if writer.file.metadata()?.len() > 0 {
+            writer.rotate()?;
+        }
+        Ok(writer)
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Header is written at the beginning of every file, including current one if it's empty.
+    pub fn with_header(mut self, header: Vec<u8>) -> Result<Self, GtsLoggerError> {
+        self.header = header;
+        if self.size == 0 {
+            self.write_header()?;
+        }
+        Ok(self)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.header)?;
+        self.size = self.header.len() as u64;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of rotations since open.
+    pub fn rotations(&self) -> u64 {
+        self.rotations
+    }
+
+    /// Rotates current file now, even if it's empty.
+    pub fn rotate(&mut self) -> Result<(), GtsLoggerError> {
+        Ok(self.rotate_file()?)
+    }
+
+    fn rotate_file(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = format_utc(now_millis());
+        let mut rotated = self.generation_path(&stamp, 0);
+        for counter in 1.. {
+            // generation could be compressed already.
+            let compressed = self
+                .options
+                .compression
+                .extension()
+                .is_some_and(|extension| with_extension(&rotated, extension).exists());
+            if !rotated.exists() && !compressed {
+                break;
+            }
+            rotated = self.generation_path(&stamp, counter);
+        }
+        std::fs::rename(&self.path, &rotated)?;
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        self.day = now_millis() / 1000 / DAY_SECS;
+        self.rotations += 1;
+        self.write_header()?;
+        if let Some(jobs) = &self.jobs {
+            // worker lives until drop of jobs.
+            let _ = jobs.send(rotated);
+        }
+        Ok(())
+    }
+
+    fn generation_path(&self, stamp: &str, counter: u32) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        match counter {
+            0 => name.push(format!(".{}", stamp)),
+            counter => name.push(format!(".{}-{:03}", stamp, counter)),
+        }
+        PathBuf::from(name)
+    }
+
+    fn needs_rotation(&self, len: usize) -> bool {
+        let has_events = self.size > self.header.len() as u64;
+        let too_big = self
+            .options
+            .max_size
+            .is_some_and(|max_size| self.size + len as u64 > max_size);
+        let next_day = self.options.daily && now_millis() / 1000 / DAY_SECS != self.day;
+        has_events && (too_big || next_day)
+    }
+
+    /// Compresses rotated file and removes old generations, called by worker thread.
+    fn finish_rotated(path: &Path, options: &RollingOptions, file: &Path) -> io::Result<()> {
+        if let Some(extension) = options.compression.extension() {
+            let compressed = with_extension(file, extension);
+            let tmp = with_extension(&compressed, "tmp");
+            options.compression.compress(file, &tmp)?;
+            std::fs::rename(&tmp, &compressed)?;
+            std::fs::remove_file(file)?;
+        }
+
+        let Some(keep) = options.keep else {
+            return Ok(());
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut prefix = path.file_name().unwrap_or_default().to_os_string();
+        prefix.push(".");
+        let prefix = prefix.to_string_lossy().into_owned();
+        let mut generations: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter_map(|name| {
+                let key = generation_key(name.strip_prefix(&prefix)?)?;
+                Some((key, name))
+            })
+            .collect();
+        // names aren't sorted by time, "<stamp>-001.gz" < "<stamp>.gz".
+        generations.sort();
+        let extra = generations.len().saturating_sub(keep);
+        for (_, name) in &generations[..extra] {
+            std::fs::remove_file(dir.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RollingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            self.rotate_file()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for RollingFileWriter {
+    /// Waits for compression of rotated files.
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "19700101-000000.000");
+        assert_eq!(format_utc(951_782_400_123), "20000229-000000.123");
+        assert_eq!(format_utc(1_792_330_161_874), "20261018-132921.874");
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let dir = test_dir("gts_rolling_size");
+        let path = dir.join("app.log");
+        std::fs::write(&path, b"previous run").unwrap();
+
+        let mut writer = RollingOptions::new()
+            .max_size(8)
+            .keep(2)
+            .open(&path)
+            .unwrap()
+            .with_header(b"H\n".to_vec())
+            .unwrap();
+        assert_eq!(writer.rotations(), 1);
+        // batch bigger than limit is written as is.
+        writer.write_all(b"0123456789").unwrap();
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"def").unwrap();
+        writer.write_all(b"ghi").unwrap();
+        assert_eq!(writer.rotations(), 3);
+        drop(writer);
+
+        let names = files(&dir);
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], "app.log");
+        assert_eq!(std::fs::read(&path).unwrap(), b"H\nghi");
+        // the oldest one ("previous run") is removed.
+        let rotated: Vec<_> = names[1..]
+            .iter()
+            .map(|name| std::fs::read(dir.join(name)).unwrap())
+            .collect();
+        assert_eq!(rotated, [&b"H\n0123456789"[..], b"H\nabcdef"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_daily_rotation() {
+        let dir = test_dir("gts_rolling_daily");
+        let path = dir.join("app.log");
+        let mut writer = RollingOptions::new().daily(true).open(&path).unwrap();
+        writer.write_all(b"today").unwrap();
+        assert_eq!(writer.rotations(), 0);
+        writer.day -= 1;
+        writer.write_all(b"tomorrow").unwrap();
+        assert_eq!(writer.rotations(), 1);
+        drop(writer);
+        assert_eq!(files(&dir).len(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"tomorrow");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn rotate_compressed(name: &str, compression: Compression) -> (PathBuf, PathBuf) {
+        let dir = test_dir(name);
+        let path = dir.join("app.log");
+        let mut writer = RollingOptions::new()
+            .compression(compression)
+            .open(&path)
+            .unwrap();
+        writer.write_all(b"compressed").unwrap();
+        writer.rotate().unwrap();
+        drop(writer);
+
+        let names = files(&dir);
+        assert_eq!(names.len(), 2);
+        let rotated = dir.join(&names[1]);
+        (dir, rotated)
+    }
+
+    #[test]
+    fn test_no_compression() {
+        let (dir, rotated) = rotate_compressed("gts_rolling_plain", Compression::None);
+        assert_eq!(std::fs::read(rotated).unwrap(), b"compressed");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        use std::io::Read;
+
+        let (dir, rotated) = rotate_compressed("gts_rolling_gzip", Compression::Gzip);
+        assert!(rotated.to_string_lossy().ends_with(".gz"));
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(rotated).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "compressed");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_generation_key() {
+        let stamp = "20261018-132921.874";
+        let key = |name: &str| generation_key(name).map(|(_, counter)| counter);
+        assert_eq!(key(stamp), Some(0));
+        assert_eq!(key(&format!("{stamp}.gz")), Some(0));
+        assert_eq!(key(&format!("{stamp}-001.gz")), Some(1));
+        assert_eq!(key(&format!("{stamp}-012")), Some(12));
+        assert_eq!(key(&format!("{stamp}-001.gz.tmp")), None);
+        assert_eq!(key(&format!("{stamp}x")), None);
+        assert_eq!(key("bak"), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_same_ms() {
+        let dir = test_dir("gts_rolling_same_ms");
+        let path = dir.join("app.log");
+        let stamp = "20261018-132921.874";
+        for name in ["20261018-132921.873.gz", "20261018-132921.874.gz"] {
+            std::fs::write(dir.join(format!("app.log.{name}")), b"old").unwrap();
+        }
+        std::fs::write(dir.join(format!("app.log.{stamp}-001.gz")), b"newer").unwrap();
+        let newest = dir.join(format!("app.log.{stamp}-002"));
+        std::fs::write(&newest, b"newest").unwrap();
+
+        let options = RollingOptions::new().keep(2).compression(Compression::Gzip);
+        RollingFileWriter::finish_rotated(&path, &options, &newest).unwrap();
+        assert_eq!(
+            files(&dir),
+            [
+                format!("app.log.{stamp}-001.gz"),
+                format!("app.log.{stamp}-002.gz")
+            ]
+        );
+
+        // rotations of the same ms don't overwrite compressed generations.
+        let mut writer = options.keep(10).open(&path).unwrap();
+        for _ in 0..3 {
+            writer.write_all(b"x").unwrap();
+            writer.rotate().unwrap();
+        }
+        drop(writer);
+        assert_eq!(files(&dir).len(), 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let (dir, rotated) = rotate_compressed("gts_rolling_zstd", Compression::Zstd);
+        assert!(rotated.to_string_lossy().ends_with(".zst"));
+        let text = zstd::decode_all(File::open(rotated).unwrap()).unwrap();
+        assert_eq!(text, b"compressed");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,535 @@
+//! Backend, which sends events to shared memory ring, drained by `gts-logd` in other process.
+//!
+//! Events are encoded by [`GtsLogEvent`] to fixed size slots of [`ShmemLogRing`], segment
+//! also holds schema of T, so reader writes binary log file (see [`crate::logfile`]) or
+//! decodes events without knowing T. Events sent before crash of process stay in segment,
+//! reader drains them after owner is dead.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::event::GtsLogEvent;
+//! use gts_logger::logbackend::shmem::{ShmemLogBackend, ShmemLogReader};
+//! use gts_logger::logclient::LogClient;
+//! use gts_logger::logfile::decode_record;
+//!
+//! #[derive(Debug, Copy, Clone, GtsLogEvent)]
+//! struct Tick {
+//!     price: f64,
+//! }
+//!
+//! let log_client = LogClient::<_, Tick>::new(ShmemLogBackend::create("doc_shmem_log")?);
+//! log_client.log(Tick { price: 1.5 })?;
+//!
+//! let mut reader = ShmemLogReader::connect("doc_shmem_log")?;
+//! assert_eq!(reader.header().root, "Tick");
+//! let record = reader.try_recv()?.unwrap();
+//! assert_eq!(decode_record(record)?.data, 1.5f64.to_le_bytes());
+//! reader.commit(1);
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+
+use crate::error::GtsLoggerError;
+use crate::event::GtsLogEvent;
+use crate::logbackend::stats::{wait_until, LogStats};
+use crate::logbackend::LogBackend;
+use crate::logclient::LogEventTs;
+use crate::logfile::LogFileHeader;
+use bytemuck::Zeroable;
+use gts_transport::arch::CachePadded;
+use gts_transport::error::GtsTransportError;
+use gts_transport::membackend::memholder::{MemHolder, MemHolderMut};
+use gts_transport::membackend::shmem::{ShmemHolder, ShmemOptions};
+use gts_transport::sync::lfringspsc::{SpScRingData, SpScRingReceiver, SpScRingSender};
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Slots in ring.
+pub const SHMEM_RING_SIZE: usize = 16384;
+/// Max size of encoded event.
+pub const SHMEM_SLOT_SIZE: usize = 252;
+pub const SHMEM_ROOT_SIZE: usize = 256;
+pub const SHMEM_SCHEMA_SIZE: usize = 16384;
+
+/// Encoded [`LogEventTs`], the same as record of log file.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct LogSlot {
+    len: u32,
+    data: [u8; SHMEM_SLOT_SIZE],
+}
+
+/// Segment of [`ShmemLogBackend`]: header written once by owner, counters and ring.
+#[repr(C)]
+pub struct ShmemLogRing {
+    /// Set by owner after header is written.
+    ready: AtomicU32,
+    root_len: u32,
+    schema_len: u32,
+    fingerprint: u64,
+    created: u64,
+    system_time: u64,
+    root: [u8; SHMEM_ROOT_SIZE],
+    schema: [u8; SHMEM_SCHEMA_SIZE],
+    /// Events dropped by owner (ring was full or event didn't fit slot)
+    /// or by reader (event was taken from ring, but not written).
+    dropped: CachePadded<AtomicU64>,
+    /// Events written by reader.
+    written: CachePadded<AtomicU64>,
+    ring: SpScRingData<SHMEM_RING_SIZE, LogSlot>,
+}
+
+unsafe impl Zeroable for ShmemLogRing {}
+
+/// Pointer to ring of segment, which is kept alive by owner of RingPtr.
+#[derive(Clone, Copy)]
+struct RingPtr(*mut SpScRingData<SHMEM_RING_SIZE, LogSlot>);
+
+unsafe impl Send for RingPtr {}
+
+impl RingPtr {
+    fn new(shmem: &ShmemHolder<ShmemLogRing>) -> Self {
+        // SAFETY: no reference is created, just pointer to field.
+        RingPtr(unsafe { std::ptr::addr_of_mut!((*shmem.get_mut_ptr()).ring) })
+    }
+}
+
+impl MemHolder<SpScRingData<SHMEM_RING_SIZE, LogSlot>> for RingPtr {
+    fn get_ptr(&self) -> *const SpScRingData<SHMEM_RING_SIZE, LogSlot> {
+        self.0
+    }
+}
+
+impl MemHolderMut<SpScRingData<SHMEM_RING_SIZE, LogSlot>> for RingPtr {
+    fn get_mut_ptr(&self) -> *mut SpScRingData<SHMEM_RING_SIZE, LogSlot> {
+        self.0
+    }
+}
+
+/// Counters of segment.
+fn counters(shmem: &ShmemHolder<ShmemLogRing>) -> (&AtomicU64, &AtomicU64) {
+    // SAFETY: atomics are only accessed through shared references.
+    unsafe {
+        let ptr = shmem.get_mut_ptr();
+        (&(*ptr).dropped, &(*ptr).written)
+    }
+}
+
+struct Sender {
+    log_tx: SpScRingSender<SHMEM_RING_SIZE, LogSlot, RingPtr>,
+    buf: Vec<u8>,
+}
+
+pub struct ShmemLogBackend<T> {
+    // drops before shmem, which holds memory of ring.
+    sender: UnsafeCell<Sender>,
+    sent: Cell<u64>,
+    /// Value of dropped counter without events dropped by reader.
+    own_dropped: Cell<u64>,
+    shmem: ShmemHolder<ShmemLogRing>,
+    _owns_t: PhantomData<T>,
+}
+
+impl<T: GtsLogEvent> ShmemLogBackend<T> {
+    /// Creates segment, segment of dead owner is replaced.
+    pub fn create(name: &str) -> Result<Self, GtsLoggerError> {
+        Self::create_with_options(&ShmemOptions::new(), name)
+    }
+
+    /// Segment of dead owner reused by [`CreateMode::ReuseIfCompatible`] keeps its header,
+    /// counters and events not read yet, it fails with `Incompatible` for events of other schema.
+    ///
+    /// [`CreateMode::ReuseIfCompatible`]: gts_transport::membackend::shmem::CreateMode::ReuseIfCompatible
+    pub fn create_with_options(options: &ShmemOptions, name: &str) -> Result<Self, GtsLoggerError> {
+        let header = LogFileHeader::of::<T>();
+        if header.root.len() > SHMEM_ROOT_SIZE || header.schema.len() > SHMEM_SCHEMA_SIZE {
+            return Err(GtsLoggerError::CommonError(format!(
+                "schema of {} doesn't fit shmem header",
+                header.root
+            )));
+        }
+        Self::from_segment(options.create::<ShmemLogRing>(name)?, header)
+    }
+
+    /// Writes header to new segment, reused segment keeps its header.
+    fn from_segment(
+        shmem: ShmemHolder<ShmemLogRing>,
+        header: LogFileHeader,
+    ) -> Result<Self, GtsLoggerError> {
+        let ptr = shmem.get_mut_ptr();
+        // SAFETY: ready is set only by owner, header is not changed after it.
+        let reused = unsafe { (*ptr).ready.load(Ordering::Acquire) != 0 };
+        let (log_tx, sent) = if reused {
+            let old = ShmemLogReader::read_header(&shmem)?;
+            if old.fingerprint != header.fingerprint || old.root != header.root {
+                return Err(GtsTransportError::Incompatible(format!(
+                    "shmem {} holds events of {}, not {}",
+                    shmem.name(),
+                    old.root,
+                    header.root
+                ))
+                .into());
+            }
+            // events of dead owner not written yet are counted as sent, flush waits for them.
+            let (_, written) = counters(&shmem);
+            // SAFETY: cursors are atomics, dead owner doesn't advance them.
+            let pending = unsafe {
+                let ring = &(*ptr).ring;
+                let write_done = ring.write_done_seqnum.load(Ordering::Acquire);
+                let read_done = ring.read_done_seqnum.load(Ordering::Acquire);
+                (write_done + SHMEM_RING_SIZE as u32 - read_done) % SHMEM_RING_SIZE as u32
+            };
+            (
+                SpScRingSender::resume(RingPtr::new(&shmem)),
+                written.load(Ordering::Acquire) + pending as u64,
+            )
+        } else {
+            // SAFETY: reader doesn't read header until ready is set.
+            unsafe {
+                (*ptr).root_len = header.root.len() as u32;
+                (*ptr).schema_len = header.schema.len() as u32;
+                (*ptr).fingerprint = header.fingerprint;
+                (*ptr).created = header.created;
+                (*ptr).system_time = header.system_time;
+                (&mut (*ptr).root)[..header.root.len()].copy_from_slice(header.root.as_bytes());
+                (&mut (*ptr).schema)[..header.schema.len()]
+                    .copy_from_slice(header.schema.as_bytes());
+                (*ptr).ready.store(1, Ordering::Release);
+            }
+            (SpScRingSender::new(RingPtr::new(&shmem)), 0)
+        };
+        let (dropped, _) = counters(&shmem);
+        let own_dropped = dropped.load(Ordering::Acquire);
+        Ok(ShmemLogBackend {
+            sender: UnsafeCell::new(Sender {
+                log_tx,
+                buf: Vec::with_capacity(SHMEM_SLOT_SIZE),
+            }),
+            sent: Cell::new(sent),
+            own_dropped: Cell::new(own_dropped),
+            shmem,
+            _owns_t: PhantomData,
+        })
+    }
+
+    /// Written events are counted by reader.
+    pub fn stats(&self) -> LogStats {
+        let (dropped, written) = counters(&self.shmem);
+        LogStats {
+            sent: self.sent.get(),
+            written: written.load(Ordering::Acquire),
+            lost: dropped.load(Ordering::Acquire),
+        }
+    }
+
+    fn drop_event(&self, err: GtsLoggerError) -> Result<(), GtsLoggerError> {
+        let (dropped, _) = counters(&self.shmem);
+        // reader increments it too.
+        dropped.fetch_add(1, Ordering::AcqRel);
+        self.own_dropped.set(self.own_dropped.get() + 1);
+        Err(err)
+    }
+}
+
+impl<T: GtsLogEvent> LogBackend<LogEventTs<T>> for ShmemLogBackend<T> {
+    fn log(&self, event: LogEventTs<T>) -> Result<(), GtsLoggerError> {
+        // SAFETY: Self is !Sync, only this function uses sender,
+        // no reentrancy in this function.
+        let sender = unsafe { &mut *self.sender.get() };
+        sender.buf.clear();
+        event.encode(&mut sender.buf);
+        let len = sender.buf.len();
+        if len > SHMEM_SLOT_SIZE {
+            return self.drop_event(GtsLoggerError::CommonError(format!(
+                "event of {} bytes doesn't fit slot",
+                len
+            )));
+        }
+        let mut slot = LogSlot {
+            len: len as u32,
+            data: [0; SHMEM_SLOT_SIZE],
+        };
+        slot.data[..len].copy_from_slice(&sender.buf);
+        if let Err(err) = sender.log_tx.send(&slot) {
+            return self.drop_event(err.into());
+        }
+        self.sent.set(self.sent.get() + 1);
+        Ok(())
+    }
+
+    /// Waits until reader writes (or drops) sent events, times out if there is no reader.
+    fn flush(&self, timeout: Duration) -> Result<(), GtsLoggerError> {
+        let target = self.sent.get();
+        let own_dropped = self.own_dropped.get();
+        let (dropped, written) = counters(&self.shmem);
+        if wait_until(timeout, || {
+            let reader_dropped = dropped.load(Ordering::Acquire) - own_dropped;
+            written.load(Ordering::Acquire) + reader_dropped >= target
+        }) {
+            Ok(())
+        } else {
+            Err(GtsLoggerError::Timeout)
+        }
+    }
+}
+
+/// Reader of segment of [`ShmemLogBackend`].
+pub struct ShmemLogReader {
+    // drops before shmem, which holds memory of ring.
+    log_rx: SpScRingReceiver<SHMEM_RING_SIZE, LogSlot, RingPtr>,
+    header: LogFileHeader,
+    shmem: ShmemHolder<ShmemLogRing>,
+}
+
+impl ShmemLogReader {
+    /// Connects to segment, waits until owner writes header.
+    pub fn connect(name: &str) -> Result<Self, GtsLoggerError> {
+        Self::connect_with_options(&ShmemOptions::new(), name)
+    }
+
+    pub fn connect_with_options(
+        options: &ShmemOptions,
+        name: &str,
+    ) -> Result<Self, GtsLoggerError> {
+        Self::from_shmem(options.connect_rw::<ShmemLogRing>(name)?)
+    }
+
+    fn from_shmem(shmem: ShmemHolder<ShmemLogRing>) -> Result<Self, GtsLoggerError> {
+        let header = Self::read_header(&shmem)?;
+        Ok(ShmemLogReader {
+            log_rx: SpScRingReceiver::new(RingPtr::new(&shmem)),
+            header,
+            shmem,
+        })
+    }
+
+    fn read_header(shmem: &ShmemHolder<ShmemLogRing>) -> Result<LogFileHeader, GtsLoggerError> {
+        let ptr = shmem.get_mut_ptr();
+        // SAFETY: header is not changed after ready is set.
+        unsafe {
+            if !wait_until(Duration::from_secs(1), || {
+                (*ptr).ready.load(Ordering::Acquire) != 0
+            }) {
+                return Err(GtsTransportError::Unitialized.into());
+            }
+            let root_len = ((*ptr).root_len as usize).min(SHMEM_ROOT_SIZE);
+            let schema_len = ((*ptr).schema_len as usize).min(SHMEM_SCHEMA_SIZE);
+            let text = |bytes: &[u8]| {
+                String::from_utf8(bytes.to_vec())
+                    .map_err(|err| GtsLoggerError::DecodeError(err.to_string()))
+            };
+            Ok(LogFileHeader {
+                root: text(&(&(*ptr).root)[..root_len])?,
+                schema: text(&(&(*ptr).schema)[..schema_len])?,
+                fingerprint: (*ptr).fingerprint,
+                created: (*ptr).created,
+                system_time: (*ptr).system_time,
+            })
+        }
+    }
+
+    /// Header of log file for events of segment.
+    pub fn header(&self) -> &LogFileHeader {
+        &self.header
+    }
+
+    pub fn name(&self) -> &str {
+        self.shmem.name()
+    }
+
+    /// Returns next record (see [`crate::logfile::decode_record`]) or None if ring is empty.
+    pub fn try_recv(&mut self) -> Result<Option<&[u8]>, GtsLoggerError> {
+        match self.log_rx.try_recv() {
+            Ok(slot) => {
+                let len = slot.len as usize;
+                if len > SHMEM_SLOT_SIZE {
+                    return Err(GtsLoggerError::DecodeError(format!(
+                        "bad slot length {}",
+                        len
+                    )));
+                }
+                Ok(Some(&slot.data[..len]))
+            }
+            Err(GtsTransportError::WouldBlock) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reports count events as written, flush of backend waits for it.
+    pub fn commit(&self, count: u64) {
+        let (_, written) = counters(&self.shmem);
+        written.fetch_add(count, Ordering::AcqRel);
+    }
+
+    /// Reports count events taken from ring as dropped, e.g. they failed to be written.
+    /// Flush of backend doesn't wait for them.
+    pub fn drop_events(&self, count: u64) {
+        let (dropped, _) = counters(&self.shmem);
+        dropped.fetch_add(count, Ordering::AcqRel);
+    }
+
+    /// Events dropped by owner or reported by [`Self::drop_events`].
+    pub fn dropped(&self) -> u64 {
+        let (dropped, _) = counters(&self.shmem);
+        dropped.load(Ordering::Acquire)
+    }
+
+    /// False if owner exited or crashed, events left in ring could still be read.
+    pub fn owner_alive(&self) -> bool {
+        self.shmem.owner_alive()
+    }
+
+    /// Reconnects to segment of restarted owner, returns Ok(false) if segment is the same.
+    /// Events left in old segment are lost, so drain it before.
+    pub fn reconnect(self) -> Result<(Self, bool), GtsLoggerError> {
+        // receiver points to old segment, it's rebuilt anyway.
+        let ShmemLogReader {
+            header, mut shmem, ..
+        } = self;
+        if !shmem.reconnect()? {
+            return Ok((
+                ShmemLogReader {
+                    log_rx: SpScRingReceiver::new(RingPtr::new(&shmem)),
+                    header,
+                    shmem,
+                },
+                false,
+            ));
+        }
+        Ok((Self::from_shmem(shmem)?, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::LogLevel;
+    use crate::logclient::LogClient;
+    use crate::logfile::decode_record;
+    use arrayvec::ArrayString;
+    use gts_transport::membackend::shmem::CreateMode;
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    #[allow(clippy::large_enum_variant)]
+    pub enum LogEvent {
+        Order { id: u64, venue: ArrayString<8> },
+        Note(ArrayString<240>),
+    }
+
+    #[test]
+    fn test_send_recv() {
+        let name = "test_shmem_log_send_recv";
+        let log_client = LogClient::<_, LogEvent>::new(ShmemLogBackend::create(name).unwrap());
+        let order = LogEvent::Order {
+            id: 7,
+            venue: ArrayString::from("xnys").unwrap(),
+        };
+        log_client.log_at(LogLevel::Warn, order).unwrap();
+        // 240 + varint length + tag doesn't fit with timestamp.
+        let note = LogEvent::Note(ArrayString::from(&"x".repeat(240)).unwrap());
+        assert!(log_client.log(note).is_err());
+        assert!(log_client.flush(Duration::from_millis(10)).is_err());
+
+        let mut reader = ShmemLogReader::connect(name).unwrap();
+        assert_eq!(reader.header().root, "LogEvent");
+        assert_eq!(reader.header().fingerprint, LogEvent::FINGERPRINT);
+        assert_eq!(reader.dropped(), 1);
+        assert!(reader.owner_alive());
+
+        let record = decode_record(reader.try_recv().unwrap().unwrap()).unwrap();
+        assert_eq!(record.level, LogLevel::Warn);
+        assert_eq!(
+            crate::event::decode_exact::<LogEvent>(record.data).unwrap(),
+            order
+        );
+        assert!(reader.try_recv().unwrap().is_none());
+        reader.commit(1);
+        log_client.flush(Duration::from_millis(10)).unwrap();
+        assert_eq!(
+            log_client.backend().stats(),
+            LogStats {
+                sent: 1,
+                written: 1,
+                lost: 1
+            }
+        );
+
+        // event which reader failed to write.
+        log_client.log(order).unwrap();
+        assert!(reader.try_recv().unwrap().is_some());
+        assert!(log_client.flush(Duration::from_millis(10)).is_err());
+        reader.drop_events(1);
+        log_client.flush(Duration::from_millis(10)).unwrap();
+        assert_eq!(
+            log_client.backend().stats(),
+            LogStats {
+                sent: 2,
+                written: 1,
+                lost: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let name = "test_shmem_log_reconnect";
+        let log_client = LogClient::<_, u64>::new(ShmemLogBackend::create(name).unwrap());
+        log_client.log(1).unwrap();
+        let reader = ShmemLogReader::connect(name).unwrap();
+        let (mut reader, replaced) = reader.reconnect().unwrap();
+        assert!(!replaced);
+
+        // events of exited owner are still in ring.
+        drop(log_client);
+        assert!(!reader.owner_alive());
+        let record = decode_record(reader.try_recv().unwrap().unwrap()).unwrap();
+        assert_eq!(record.data, 1u64.to_le_bytes());
+
+        let options = ShmemOptions::new().create_mode(CreateMode::FailIfExists);
+        let log_client =
+            LogClient::<_, u32>::new(ShmemLogBackend::create_with_options(&options, name).unwrap());
+        log_client.log(2).unwrap();
+        let (mut reader, replaced) = reader.reconnect().unwrap();
+        assert!(replaced);
+        assert_eq!(reader.header().root, "u32");
+        let record = decode_record(reader.try_recv().unwrap().unwrap()).unwrap();
+        assert_eq!(record.data, 2u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_reuse() {
+        let name = "test_shmem_log_reuse";
+        let event = |data: u64| LogEventTs::new(0, 0, LogLevel::Info, data);
+        let backend = ShmemLogBackend::<u64>::create(name).unwrap();
+        backend.log(event(1)).unwrap();
+        backend.log(event(2)).unwrap();
+        let mut reader = ShmemLogReader::connect(name).unwrap();
+        assert!(reader.try_recv().unwrap().is_some());
+        reader.commit(1);
+
+        // the same segment, as ReuseIfCompatible takes it after crash of owner.
+        let ShmemLogBackend { shmem, .. } = backend;
+        let backend =
+            ShmemLogBackend::<u64>::from_segment(shmem, LogFileHeader::of::<u64>()).unwrap();
+        assert_eq!(backend.stats().sent, 2);
+        backend.log(event(3)).unwrap();
+        for data in [2u64, 3] {
+            let record = decode_record(reader.try_recv().unwrap().unwrap()).unwrap();
+            assert_eq!(record.data, data.to_le_bytes());
+        }
+        assert!(backend.flush(Duration::from_millis(10)).is_err());
+        reader.commit(2);
+        backend.flush(Duration::from_millis(10)).unwrap();
+
+        let ShmemLogBackend { shmem, .. } = backend;
+        let res = ShmemLogBackend::<u32>::from_segment(shmem, LogFileHeader::of::<u32>());
+        assert!(matches!(
+            res,
+            Err(GtsLoggerError::TransportWouldBlock(
+                GtsTransportError::Incompatible(_)
+            ))
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+//! Counters of events of threaded backends, used by flush and shutdown.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Events of backend since its creation.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct LogStats {
+    /// Events accepted by backend.
+    pub sent: u64,
+    /// Events written to destination.
+    pub written: u64,
+    /// Events dropped because ring was full or backend was shut down, failed to be
+    /// encoded or written, and (after shutdown) not written before timeout.
+    /// If shutdown timed out, a batch being written at that moment may still reach
+    /// destination, so lost is an upper bound then.
+    pub lost: u64,
+}
+
+impl std::ops::Add for LogStats {
+    type Output = LogStats;
+
+    fn add(self, other: LogStats) -> LogStats {
+        LogStats {
+            sent: self.sent + other.sent,
+            written: self.written + other.written,
+            lost: self.lost + other.lost,
+        }
+    }
+}
+
+/// Counters updated by backend threads.
+#[derive(Debug, Default)]
+pub(crate) struct ThreadCounters {
+    /// Events sent to ring, updated by the only producer.
+    pub(crate) sent: AtomicU64,
+    /// Events dropped by producer, ring was full or backend was shut down.
+    pub(crate) dropped: AtomicU64,
+    pub(crate) written: AtomicU64,
+    pub(crate) failed: AtomicU64,
+    /// Events sent before the last flush request.
+    pub(crate) flush_target: AtomicU64,
+    /// Thread wrote everything and exited.
+    pub(crate) finished: AtomicBool,
+    /// Shutdown timed out, thread must exit without writing anything more.
+    pub(crate) aborted: AtomicBool,
+}
+
+impl ThreadCounters {
+    /// Written or failed events.
+    pub(crate) fn done(&self) -> u64 {
+        self.written.load(Ordering::Acquire) + self.failed.load(Ordering::Acquire)
+    }
+
+    /// Counts event sent by producer, store instead of rmw as producer is single.
+    pub(crate) fn add_sent(&self) {
+        let sent = self.sent.load(Ordering::Relaxed);
+        self.sent.store(sent + 1, Ordering::Release);
+    }
+
+    /// Current counters, events in flight are neither written nor lost.
+    pub(crate) fn stats(&self) -> LogStats {
+        LogStats {
+            sent: self.sent.load(Ordering::Acquire),
+            written: self.written.load(Ordering::Acquire),
+            lost: self.dropped.load(Ordering::Acquire) + self.failed.load(Ordering::Acquire),
+        }
+    }
+
+    /// Counters after shutdown, events in flight are lost.
+    pub(crate) fn final_stats(&self) -> LogStats {
+        let stats = self.stats();
+        LogStats {
+            lost: stats.lost + stats.sent.saturating_sub(self.done()),
+            ..stats
+        }
+    }
+
+    pub(crate) fn add_dropped(&self) {
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        self.dropped.store(dropped + 1, Ordering::Release);
+    }
+
+    /// Asks thread to write events sent so far, returns number of events to wait for.
+    pub(crate) fn request_flush(&self) -> u64 {
+        let target = self.sent.load(Ordering::Acquire);
+        self.flush_target.fetch_max(target, Ordering::AcqRel);
+        target
+    }
+
+    /// Events up to target are done, or thread has exited and never will do them.
+    pub(crate) fn flushed(&self, target: u64) -> bool {
+        self.done() >= target || self.is_finished()
+    }
+
+    pub(crate) fn flush_requested(&self) -> bool {
+        self.flush_target.load(Ordering::Acquire) > self.done()
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Tells thread, which is detached by shutdown, to stop writing.
+    pub(crate) fn abort(&self) {
+        self.aborted.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Acquire)
+    }
+}
+
+/// Polls cond until it's true or timeout expires.
+pub(crate) fn wait_until(timeout: Duration, mut cond: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if cond() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -38,9 +38,9 @@ where
         let join_handle = Some(std::thread::spawn(move || {
             let queue_tx = queue_tx;
             while !*flag_clone.lock().unwrap() {
-                match log_rx.try_recv() {
+                match log_rx.try_recv_value() {
                     Ok(res) => {
-                        queue_tx.send(*res).unwrap();
+                        queue_tx.send(res).unwrap();
                     }
                     Err(GtsTransportError::WouldBlock) => {}
                     _ => unreachable!(),
@@ -61,6 +61,16 @@ where
         self.queue_rx.try_recv().ok()
     }
 }
+
+impl<T, const RSIZE: usize> Default for MockThreadLogBacked<RSIZE, T>
+where
+    T: Copy + Send + 'static + Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T, const RSIZE: usize> Drop for MockThreadLogBacked<RSIZE, T>
 where
     T: Copy + Send,
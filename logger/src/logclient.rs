@@ -1,45 +1,77 @@
 use crate::error::GtsLoggerError;
+use crate::event::GtsLogEvent;
+use crate::level::{LevelThreshold, LogLevel, MAX_LEVEL};
 use crate::logbackend::LogBackend;
 use serde::{Deserialize, Serialize};
 use std::cell::Cell;
 use std::marker::PhantomData;
+use std::time::Duration;
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, GtsLogEvent, Copy, Clone, PartialEq)]
 pub struct LogEventTs<T> {
     pub timestamp: u64,
     pub seqid: u32,
+    pub level: LogLevel,
     pub data: T,
 }
 
 impl<T> LogEventTs<T> {
-    pub fn new(timestamp: u64, seqid: u32, data: T) -> Self {
+    pub fn new(timestamp: u64, seqid: u32, level: LogLevel, data: T) -> Self {
         LogEventTs {
             timestamp,
             seqid,
+            level,
             data,
         }
     }
 }
 
 /// LogClient is simple client with timestamp.
+/// Events below threshold (global [`MAX_LEVEL`] by default) are dropped before timestamp.
 pub struct LogClient<BackendT: LogBackend<LogEventTs<EventT>>, EventT> {
     backend: BackendT,
     _data: PhantomData<EventT>,
     anc: minstant::Anchor,
     last_ts: Cell<(u64, u32)>,
+    threshold: &'static LevelThreshold,
 }
 
 impl<BackendT: LogBackend<LogEventTs<EventT>>, EventT> LogClient<BackendT, EventT> {
     pub fn new(backend: BackendT) -> Self {
         Self {
-            backend: backend,
+            backend,
             _data: PhantomData {},
             anc: minstant::Anchor::new(),
             last_ts: (0, 0).into(),
+            threshold: &MAX_LEVEL,
         }
     }
 
+    /// Uses own threshold instead of global one, e.g. per subsystem.
+    pub fn with_threshold(mut self, threshold: &'static LevelThreshold) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    #[inline(always)]
+    pub fn enabled(&self, level: LogLevel) -> bool {
+        self.threshold.enabled(level)
+    }
+
+    /// Logs event at Info level with timestamp of previous event.
     pub fn log_same(&self, event: EventT) -> Result<(), GtsLoggerError> {
+        self.log_same_at(LogLevel::Info, event)
+    }
+
+    /// Logs event at Info level.
+    pub fn log(&self, event: EventT) -> Result<(), GtsLoggerError> {
+        self.log_at(LogLevel::Info, event)
+    }
+
+    pub fn log_same_at(&self, level: LogLevel, event: EventT) -> Result<(), GtsLoggerError> {
+        if !self.enabled(level) {
+            return Ok(());
+        }
         let (timestamp, mut seqid) = self.last_ts.get();
         seqid += 1;
         self.last_ts.set((timestamp, seqid));
@@ -47,11 +79,15 @@ impl<BackendT: LogBackend<LogEventTs<EventT>>, EventT> LogClient<BackendT, Event
         self.backend.log(LogEventTs {
             timestamp,
             seqid,
+            level,
             data: event,
         })
     }
 
-    pub fn log(&self, event: EventT) -> Result<(), GtsLoggerError> {
+    pub fn log_at(&self, level: LogLevel, event: EventT) -> Result<(), GtsLoggerError> {
+        if !self.enabled(level) {
+            return Ok(());
+        }
         let ts = minstant::Instant::now();
         let timestamp = ts.as_unix_nanos(&self.anc);
         self.last_ts.set((timestamp, 0));
@@ -59,10 +95,16 @@ impl<BackendT: LogBackend<LogEventTs<EventT>>, EventT> LogClient<BackendT, Event
         self.backend.log(LogEventTs {
             timestamp,
             seqid: 0,
+            level,
             data: event,
         })
     }
 
+    /// Waits until events logged before are written by backend.
+    pub fn flush(&self, timeout: Duration) -> Result<(), GtsLoggerError> {
+        self.backend.flush(timeout)
+    }
+
     pub fn backend(&self) -> &BackendT {
         &self.backend
     }
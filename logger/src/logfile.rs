@@ -0,0 +1,352 @@
+//! Binary log file of [`LogEventTs<T>`] with [`GtsLogEvent`] encoding,
+//! decoded by `gts-logcat` tool without knowing T (see [`dynamic`]).
+//!
+//! File: header `{magic: [u8; 8], version: u16, reserved: u16, root_len: u32,
+//! schema_len: u32, reserved: u32, fingerprint: u64, created: u64, system_time: u64}`
+//! (little-endian), root type name (`root_len` bytes) and schema of T (`schema_len` bytes),
+//! followed by records `{len: u32, event: [u8; len]}`. `created` is minstant unix nanos
+//! (the same clock as timestamps of events), `system_time` is system clock at the same moment.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::event::GtsLogEvent;
+//! use gts_logger::level::LogLevel;
+//! use gts_logger::logclient::LogEventTs;
+//! use gts_logger::logfile::{LogFileReader, LogFileWriter};
+//!
+//! #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+//! struct Tick {
+//!     price: f64,
+//! }
+//!
+//! let mut writer = LogFileWriter::<Tick, _>::new(Vec::new())?;
+//! writer.write(&LogEventTs::new(100, 0, LogLevel::Info, Tick { price: 1.5 }))?;
+//! let file = writer.into_inner()?;
+//!
+//! let mut reader = LogFileReader::new(file.as_slice())?;
+//! assert_eq!(reader.header().root, "Tick");
+//! let event = reader.next_event::<Tick>()?.unwrap();
+//! assert_eq!(event.data.price, 1.5);
+//! assert!(reader.next_event::<Tick>()?.is_none());
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+
+pub mod dynamic;
+
+use crate::error::GtsLoggerError;
+use crate::event::{schema_of, GtsLogEvent};
+use crate::level::LogLevel;
+use crate::logclient::LogEventTs;
+use std::io::{ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::time::SystemTime;
+
+pub const LOGFILE_MAGIC: [u8; 8] = *b"GTSLOG01";
+pub const LOGFILE_VERSION: u16 = 1;
+pub const LOGFILE_HEADER_SIZE: usize = 48;
+/// Max length of root type name and schema, longer ones are rejected by reader.
+pub const MAX_SCHEMA_SIZE: usize = 1 << 20;
+/// Max length of record, longer one is rejected by reader (e.g. corrupted length).
+pub const MAX_RECORD_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFileHeader {
+    /// Type name of event data, without module paths, e.g. `Option<Tick>`.
+    pub root: String,
+    /// [`schema_of`] event data.
+    pub schema: String,
+    pub fingerprint: u64,
+    pub created: u64,
+    pub system_time: u64,
+}
+
+impl LogFileHeader {
+    pub fn of<T: GtsLogEvent>() -> Self {
+        let anc = minstant::Anchor::new();
+        let system_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or(0);
+        LogFileHeader {
+            root: short_type_name(std::any::type_name::<T>()),
+            schema: schema_of::<T>(),
+            fingerprint: T::FINGERPRINT,
+            created: minstant::Instant::now().as_unix_nanos(&anc),
+            system_time,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut dst = vec![0u8; LOGFILE_HEADER_SIZE];
+        dst[0..8].copy_from_slice(&LOGFILE_MAGIC);
+        dst[8..10].copy_from_slice(&LOGFILE_VERSION.to_le_bytes());
+        dst[12..16].copy_from_slice(&(self.root.len() as u32).to_le_bytes());
+        dst[16..20].copy_from_slice(&(self.schema.len() as u32).to_le_bytes());
+        dst[24..32].copy_from_slice(&self.fingerprint.to_le_bytes());
+        dst[32..40].copy_from_slice(&self.created.to_le_bytes());
+        dst[40..48].copy_from_slice(&self.system_time.to_le_bytes());
+        dst.extend_from_slice(self.root.as_bytes());
+        dst.extend_from_slice(self.schema.as_bytes());
+        dst
+    }
+
+    pub fn read(src: &mut impl Read) -> Result<Self, GtsLoggerError> {
+        let mut raw = [0u8; LOGFILE_HEADER_SIZE];
+        src.read_exact(&mut raw)?;
+        let version = u16::from_le_bytes(raw[8..10].try_into().unwrap());
+        if raw[0..8] != LOGFILE_MAGIC || version != LOGFILE_VERSION {
+            return Err(GtsLoggerError::DecodeError(format!(
+                "bad log file magic / version {}",
+                version
+            )));
+        }
+        let u32_at = |pos: usize| u32::from_le_bytes(raw[pos..pos + 4].try_into().unwrap());
+        let u64_at = |pos: usize| u64::from_le_bytes(raw[pos..pos + 8].try_into().unwrap());
+        let mut read_string = |len: u32| -> Result<String, GtsLoggerError> {
+            if len as usize > MAX_SCHEMA_SIZE {
+                return Err(GtsLoggerError::DecodeError(format!(
+                    "log file header string of {} bytes",
+                    len
+                )));
+            }
+            let mut text = vec![0u8; len as usize];
+            src.read_exact(&mut text)?;
+            String::from_utf8(text).map_err(|err| GtsLoggerError::DecodeError(err.to_string()))
+        };
+        Ok(LogFileHeader {
+            root: read_string(u32_at(12))?,
+            schema: read_string(u32_at(16))?,
+            fingerprint: u64_at(24),
+            created: u64_at(32),
+            system_time: u64_at(40),
+        })
+    }
+}
+
+/// Strips module paths, e.g. `core::option::Option<app::Tick>` -> `Option<Tick>`.
+pub fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(pos) = rest.find("::") {
+        short.push_str(&rest[..pos]);
+        while short.ends_with(|ch: char| ch.is_alphanumeric() || ch == '_') {
+            short.pop();
+        }
+        rest = &rest[pos + 2..];
+    }
+    short.push_str(rest);
+    short
+}
+
+/// Appends length-prefixed record of event to dst.
+pub fn encode_record<T: GtsLogEvent>(event: &LogEventTs<T>, dst: &mut Vec<u8>) {
+    let start = dst.len();
+    dst.extend_from_slice(&[0; 4]);
+    event.encode(dst);
+    let len = (dst.len() - start - 4) as u32;
+    dst[start..start + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Decodes event of record (without length prefix), data stays encoded.
+pub fn decode_record(mut src: &[u8]) -> Result<LogEventTs<&[u8]>, GtsLoggerError> {
+    let timestamp = u64::decode(&mut src)?;
+    let seqid = u32::decode(&mut src)?;
+    let level = LogLevel::decode(&mut src)?;
+    Ok(LogEventTs::new(timestamp, seqid, level, src))
+}
+
+pub struct LogFileWriter<T: GtsLogEvent, W: Write> {
+    dest: W,
+    buf: Vec<u8>,
+    counter: u64,
+    _owns_t: PhantomData<T>,
+}
+
+impl<T: GtsLogEvent, W: Write> LogFileWriter<T, W> {
+    /// Writes file header to dest.
+    pub fn new(mut dest: W) -> Result<Self, GtsLoggerError> {
+        dest.write_all(&LogFileHeader::of::<T>().encode())?;
+        Ok(Self {
+            dest,
+            buf: Vec::with_capacity(64),
+            counter: 0,
+            _owns_t: PhantomData,
+        })
+    }
+
+    pub fn write(&mut self, event: &LogEventTs<T>) -> Result<(), GtsLoggerError> {
+        self.buf.clear();
+        encode_record(event, &mut self.buf);
+        self.dest.write_all(&self.buf)?;
+        self.counter += 1;
+        Ok(())
+    }
+
+    /// Number of written events.
+    pub fn count(&self) -> u64 {
+        self.counter
+    }
+
+    pub fn flush(&mut self) -> Result<(), GtsLoggerError> {
+        self.dest.flush()?;
+        Ok(())
+    }
+
+    /// Flushes and returns dest.
+    pub fn into_inner(mut self) -> Result<W, GtsLoggerError> {
+        self.flush()?;
+        Ok(self.dest)
+    }
+}
+
+pub struct LogFileReader<R: Read> {
+    src: R,
+    header: LogFileHeader,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> LogFileReader<R> {
+    /// Reads file header.
+    pub fn new(mut src: R) -> Result<Self, GtsLoggerError> {
+        let header = LogFileHeader::read(&mut src)?;
+        Ok(Self {
+            src,
+            header,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn header(&self) -> &LogFileHeader {
+        &self.header
+    }
+
+    /// Returns next event with encoded data or None at the end of file.
+    /// Record truncated by the end of file (e.g. crash of writer) or longer than
+    /// [`MAX_RECORD_SIZE`] is an error.
+    pub fn next_raw(&mut self) -> Result<Option<LogEventTs<&[u8]>>, GtsLoggerError> {
+        let mut len = [0u8; 4];
+        match self.src.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_RECORD_SIZE {
+            return Err(GtsLoggerError::DecodeError(format!(
+                "record of {} bytes",
+                len
+            )));
+        }
+        self.buf.resize(len, 0);
+        self.src.read_exact(&mut self.buf).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                GtsLoggerError::DecodeError("truncated record".to_string())
+            } else {
+                err.into()
+            }
+        })?;
+        decode_record(&self.buf).map(Some)
+    }
+
+    /// Returns next event or None at the end of file, T must match fingerprint of file.
+    pub fn next_event<T: GtsLogEvent>(&mut self) -> Result<Option<LogEventTs<T>>, GtsLoggerError> {
+        if self.header.fingerprint != T::FINGERPRINT {
+            return Err(GtsLoggerError::DecodeError(format!(
+                "log file fingerprint {:x} ({}) doesn't match {:x}",
+                self.header.fingerprint,
+                self.header.root,
+                T::FINGERPRINT
+            )));
+        }
+        let Some(raw) = self.next_raw()? else {
+            return Ok(None);
+        };
+        let data = crate::event::decode_exact(raw.data)?;
+        Ok(Some(LogEventTs::new(
+            raw.timestamp,
+            raw.seqid,
+            raw.level,
+            data,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrayvec::ArrayString;
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub enum LogEvent {
+        Order { id: u64, venue: ArrayString<8> },
+        Heartbeat,
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut writer = LogFileWriter::<LogEvent, _>::new(Vec::new()).unwrap();
+        let events = [
+            LogEventTs::new(10, 0, LogLevel::Info, LogEvent::Heartbeat),
+            LogEventTs::new(
+                20,
+                1,
+                LogLevel::Error,
+                LogEvent::Order {
+                    id: 5,
+                    venue: ArrayString::from("xnys").unwrap(),
+                },
+            ),
+        ];
+        for event in &events {
+            writer.write(event).unwrap();
+        }
+        assert_eq!(writer.count(), 2);
+        let file = writer.into_inner().unwrap();
+
+        let mut reader = LogFileReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.header().root, "LogEvent");
+        assert_eq!(reader.header().schema, LogEvent::SCHEMA);
+        assert_eq!(reader.next_event::<LogEvent>().unwrap(), Some(events[0]));
+        assert_eq!(reader.next_event::<LogEvent>().unwrap(), Some(events[1]));
+        assert!(reader.next_event::<LogEvent>().unwrap().is_none());
+
+        let mut reader = LogFileReader::new(file.as_slice()).unwrap();
+        assert!(reader.next_event::<u64>().is_err());
+
+        let mut reader = LogFileReader::new(&file[..file.len() - 1]).unwrap();
+        assert!(reader.next_raw().unwrap().is_some());
+        assert!(reader.next_raw().is_err());
+
+        assert!(LogFileReader::new(&file[1..]).is_err());
+
+        // corrupted lengths are rejected before allocation.
+        let header_len = LOGFILE_HEADER_SIZE + "LogEvent".len() + LogEvent::SCHEMA.len();
+        let mut corrupted = file.clone();
+        corrupted[header_len..header_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = LogFileReader::new(corrupted.as_slice()).unwrap();
+        assert!(matches!(
+            reader.next_raw(),
+            Err(GtsLoggerError::DecodeError(_))
+        ));
+        let mut corrupted = file.clone();
+        corrupted[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            LogFileReader::new(corrupted.as_slice()),
+            Err(GtsLoggerError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_short_type_name() {
+        assert_eq!(
+            short_type_name("core::option::Option<app::log::Tick>"),
+            "Option<Tick>"
+        );
+        assert_eq!(
+            short_type_name("[arrayvec::array_string::ArrayString<16>; 2]"),
+            "[ArrayString<16>; 2]"
+        );
+        assert_eq!(short_type_name("u64"), "u64");
+    }
+}
@@ -0,0 +1,676 @@
+//! Decoding of events by schema from log file header, without Rust type of events.
+//! Supports everything [`GtsLogEvent`] is implemented for in this crate and derived types.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::event::{schema_of, GtsLogEvent};
+//! use gts_logger::logfile::dynamic::Schema;
+//!
+//! #[derive(Debug, Copy, Clone, GtsLogEvent)]
+//! enum Side {
+//!     Buy { qty: u32 },
+//!     Sell(u32),
+//! }
+//!
+//! let schema = Schema::parse("Side", &schema_of::<Side>())?;
+//! let mut buf = Vec::new();
+//! Side::Buy { qty: 3 }.encode(&mut buf);
+//!
+//! let value = schema.decode(&mut buf.as_slice())?;
+//! assert_eq!(value.to_string(), "Buy { qty: 3 }");
+//! assert_eq!(value.variant(), Some("Buy"));
+//! assert_eq!(value.to_json().to_string(), r#"{"Buy":{"qty":3}}"#);
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+
+use crate::error::GtsLoggerError;
+use crate::event::{read_varint, take, GtsLogEvent};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Ty {
+    Unit,
+    Bool,
+    Char,
+    Unsigned(usize),
+    Signed(usize),
+    F32,
+    F64,
+    Str,
+    Array(Box<Ty>, Len),
+    Option(Box<Ty>),
+    /// Derived type or generic parameter.
+    Named(String, Vec<Ty>),
+    /// Value of const generic parameter.
+    Const(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Len {
+    Fixed(u64),
+    Param(String),
+}
+
+#[derive(Clone, Debug)]
+enum FieldsDef {
+    Unit,
+    Tuple(Vec<Ty>),
+    Named(Vec<(String, Ty)>),
+}
+
+#[derive(Clone, Debug)]
+enum Body {
+    Struct(FieldsDef),
+    Enum(Vec<(String, FieldsDef)>),
+}
+
+#[derive(Clone, Debug)]
+struct Def {
+    params: Vec<String>,
+    body: Body,
+}
+
+/// Decoded value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Char(char),
+    U64(u64),
+    I64(i64),
+    U128(u128),
+    I128(i128),
+    F32(f32),
+    F64(f64),
+    Str(String),
+    Seq(Vec<Value>),
+    Option(Option<Box<Value>>),
+    Struct {
+        name: String,
+        fields: Fields,
+    },
+    Variant {
+        name: String,
+        variant: String,
+        fields: Fields,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fields {
+    Unit,
+    Tuple(Vec<Value>),
+    Named(Vec<(String, Value)>),
+}
+
+/// Types of log file: root type and definitions of derived types.
+#[derive(Clone, Debug)]
+pub struct Schema {
+    root: Ty,
+    defs: HashMap<String, Def>,
+}
+
+fn error(msg: String) -> GtsLoggerError {
+    GtsLoggerError::DecodeError(msg)
+}
+
+impl Schema {
+    /// Parses root type name and [`schema_of`](crate::event::schema_of) it.
+    pub fn parse(root: &str, schema: &str) -> Result<Self, GtsLoggerError> {
+        let mut defs = HashMap::new();
+        for line in schema.lines().filter(|line| !line.trim().is_empty()) {
+            let mut parser = Parser::new(line)?;
+            let (name, def) = parser.def()?;
+            parser.end()?;
+            defs.insert(name, def);
+        }
+        let mut parser = Parser::new(root)?;
+        let root = parser.ty()?;
+        parser.end()?;
+        Ok(Schema { root, defs })
+    }
+
+    /// Decodes value of root type from the beginning of src and advances it.
+    pub fn decode(&self, src: &mut &[u8]) -> Result<Value, GtsLoggerError> {
+        self.decode_ty(&self.root, src)
+    }
+
+    fn decode_ty(&self, ty: &Ty, src: &mut &[u8]) -> Result<Value, GtsLoggerError> {
+        Ok(match ty {
+            Ty::Unit => Value::Unit,
+            Ty::Bool => Value::Bool(bool::decode(src)?),
+            Ty::Char => Value::Char(char::decode(src)?),
+            Ty::Unsigned(16) => Value::U128(u128::decode(src)?),
+            Ty::Signed(16) => Value::I128(i128::decode(src)?),
+            Ty::Unsigned(size) => {
+                let mut bytes = [0u8; 8];
+                bytes[..*size].copy_from_slice(take(src, *size)?);
+                Value::U64(u64::from_le_bytes(bytes))
+            }
+            Ty::Signed(size) => {
+                let mut bytes = [0u8; 8];
+                bytes[..*size].copy_from_slice(take(src, *size)?);
+                // sign extension
+                let shift = 64 - 8 * *size as u32;
+                Value::I64(i64::from_le_bytes(bytes) << shift >> shift)
+            }
+            Ty::F32 => Value::F32(f32::decode(src)?),
+            Ty::F64 => Value::F64(f64::decode(src)?),
+            Ty::Str => {
+                let len = read_varint(src)? as usize;
+                let text =
+                    std::str::from_utf8(take(src, len)?).map_err(|err| error(err.to_string()))?;
+                Value::Str(text.to_string())
+            }
+            Ty::Array(item, Len::Fixed(len)) => Value::Seq(
+                (0..*len)
+                    .map(|_| self.decode_ty(item, src))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Ty::Option(item) => Value::Option(match bool::decode(src)? {
+                true => Some(Box::new(self.decode_ty(item, src)?)),
+                false => None,
+            }),
+            Ty::Named(name, args) => {
+                let def = self
+                    .defs
+                    .get(name)
+                    .ok_or_else(|| error(format!("unknown type {}", name)))?;
+                if def.params.len() != args.len() {
+                    return Err(error(format!("bad number of parameters of {}", name)));
+                }
+                let env: Vec<_> = def.params.iter().cloned().zip(args.clone()).collect();
+                match &def.body {
+                    Body::Struct(fields) => Value::Struct {
+                        name: name.clone(),
+                        fields: self.decode_fields(fields, &env, src)?,
+                    },
+                    Body::Enum(variants) => {
+                        let idx = read_varint(src)?;
+                        let (variant, fields) = variants
+                            .get(idx as usize)
+                            .ok_or_else(|| error(format!("unknown variant {} of {}", idx, name)))?;
+                        Value::Variant {
+                            name: name.clone(),
+                            variant: variant.clone(),
+                            fields: self.decode_fields(fields, &env, src)?,
+                        }
+                    }
+                }
+            }
+            Ty::Array(_, Len::Param(param)) => {
+                return Err(error(format!("unbound parameter {}", param)))
+            }
+            Ty::Const(value) => return Err(error(format!("unexpected const {}", value))),
+        })
+    }
+
+    fn decode_fields(
+        &self,
+        fields: &FieldsDef,
+        env: &[(String, Ty)],
+        src: &mut &[u8],
+    ) -> Result<Fields, GtsLoggerError> {
+        Ok(match fields {
+            FieldsDef::Unit => Fields::Unit,
+            FieldsDef::Tuple(types) => Fields::Tuple(
+                types
+                    .iter()
+                    .map(|ty| self.decode_ty(&substitute(ty, env)?, src))
+                    .collect::<Result<_, _>>()?,
+            ),
+            FieldsDef::Named(types) => Fields::Named(
+                types
+                    .iter()
+                    .map(|(name, ty)| {
+                        Ok((name.clone(), self.decode_ty(&substitute(ty, env)?, src)?))
+                    })
+                    .collect::<Result<_, GtsLoggerError>>()?,
+            ),
+        })
+    }
+}
+
+/// Replaces generic parameters of definition by its arguments.
+fn substitute(ty: &Ty, env: &[(String, Ty)]) -> Result<Ty, GtsLoggerError> {
+    let lookup = |name: &str| {
+        env.iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, ty)| ty)
+    };
+    Ok(match ty {
+        Ty::Named(name, args) if args.is_empty() && lookup(name).is_some() => {
+            lookup(name).unwrap().clone()
+        }
+        Ty::Named(name, args) => Ty::Named(
+            name.clone(),
+            args.iter()
+                .map(|arg| substitute(arg, env))
+                .collect::<Result<_, _>>()?,
+        ),
+        Ty::Array(item, len) => {
+            let len = match len {
+                Len::Param(param) => match lookup(param) {
+                    Some(Ty::Const(value)) => Len::Fixed(*value),
+                    _ => return Err(error(format!("unbound parameter {}", param))),
+                },
+                fixed => fixed.clone(),
+            };
+            Ty::Array(Box::new(substitute(item, env)?), len)
+        }
+        Ty::Option(item) => Ty::Option(Box::new(substitute(item, env)?)),
+        other => other.clone(),
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(u64),
+    Punct(char),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Self, GtsLoggerError> {
+        let mut tokens = Vec::new();
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, ch)) = chars.next() {
+            if ch.is_whitespace() {
+                continue;
+            }
+            if ch.is_alphanumeric() || ch == '_' {
+                let mut end = start + ch.len_utf8();
+                while let Some((pos, ch)) =
+                    chars.next_if(|(_, ch)| ch.is_alphanumeric() || *ch == '_')
+                {
+                    end = pos + ch.len_utf8();
+                }
+                let word = &text[start..end];
+                tokens.push(match word.parse() {
+                    Ok(num) => Token::Num(num),
+                    Err(_) => Token::Ident(word.to_string()),
+                });
+            } else if "{}()[]<>,;:".contains(ch) {
+                tokens.push(Token::Punct(ch));
+            } else {
+                return Err(error(format!("unexpected {:?} in schema {:?}", ch, text)));
+            }
+        }
+        Ok(Parser { tokens, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, GtsLoggerError> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| error("unexpected end of schema".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_punct(&self, punct: char) -> bool {
+        self.peek() == Some(&Token::Punct(punct))
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), GtsLoggerError> {
+        match self.next()? {
+            Token::Punct(found) if found == punct => Ok(()),
+            other => Err(error(format!("expected {:?}, found {:?}", punct, other))),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, GtsLoggerError> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            other => Err(error(format!("expected identifier, found {:?}", other))),
+        }
+    }
+
+    fn end(&self) -> Result<(), GtsLoggerError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(error(format!("unexpected {:?} in schema", token))),
+        }
+    }
+
+    /// Comma separated items until closing punct.
+    fn list<T>(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<T, GtsLoggerError>,
+    ) -> Result<Vec<T>, GtsLoggerError> {
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(item(self)?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn def(&mut self) -> Result<(String, Def), GtsLoggerError> {
+        let kind = self.ident()?;
+        let name = self.ident()?;
+        let params = if self.eat('<') {
+            self.list('>', Self::ident)?
+        } else {
+            Vec::new()
+        };
+        let body = match kind.as_str() {
+            "struct" => Body::Struct(self.fields()?),
+            "enum" => {
+                self.expect('{')?;
+                Body::Enum(self.list('}', |parser| Ok((parser.ident()?, parser.fields()?)))?)
+            }
+            other => return Err(error(format!("unknown definition {}", other))),
+        };
+        Ok((name, Def { params, body }))
+    }
+
+    fn fields(&mut self) -> Result<FieldsDef, GtsLoggerError> {
+        if self.eat('{') {
+            Ok(FieldsDef::Named(self.list('}', |parser| {
+                let name = parser.ident()?;
+                parser.expect(':')?;
+                Ok((name, parser.ty()?))
+            })?))
+        } else if self.eat('(') {
+            Ok(FieldsDef::Tuple(self.list(')', Self::ty)?))
+        } else {
+            Ok(FieldsDef::Unit)
+        }
+    }
+
+    fn ty(&mut self) -> Result<Ty, GtsLoggerError> {
+        match self.next()? {
+            Token::Punct('(') => {
+                self.expect(')')?;
+                Ok(Ty::Unit)
+            }
+            Token::Punct('[') => {
+                let item = self.ty()?;
+                self.expect(';')?;
+                let len = match self.next()? {
+                    Token::Num(len) => Len::Fixed(len),
+                    Token::Ident(param) => Len::Param(param),
+                    other => return Err(error(format!("bad array length {:?}", other))),
+                };
+                self.expect(']')?;
+                Ok(Ty::Array(Box::new(item), len))
+            }
+            Token::Num(value) => Ok(Ty::Const(value)),
+            Token::Ident(mut name) => {
+                // path, only the last segment is used.
+                while self.is_punct(':') {
+                    self.expect(':')?;
+                    self.expect(':')?;
+                    name = self.ident()?;
+                }
+                let args = if self.eat('<') {
+                    self.list('>', Self::ty)?
+                } else {
+                    Vec::new()
+                };
+                Ok(match (name.as_str(), args.as_slice()) {
+                    ("bool", []) => Ty::Bool,
+                    ("char", []) => Ty::Char,
+                    ("u8", []) => Ty::Unsigned(1),
+                    ("u16", []) => Ty::Unsigned(2),
+                    ("u32", []) => Ty::Unsigned(4),
+                    ("u64" | "usize", []) => Ty::Unsigned(8),
+                    ("u128", []) => Ty::Unsigned(16),
+                    ("i8", []) => Ty::Signed(1),
+                    ("i16", []) => Ty::Signed(2),
+                    ("i32", []) => Ty::Signed(4),
+                    ("i64" | "isize", []) => Ty::Signed(8),
+                    ("i128", []) => Ty::Signed(16),
+                    ("f32", []) => Ty::F32,
+                    ("f64", []) => Ty::F64,
+                    ("ArrayString", [_]) => Ty::Str,
+                    ("Option", [item]) => Ty::Option(Box::new(item.clone())),
+                    _ => Ty::Named(name, args),
+                })
+            }
+            other => Err(error(format!("unexpected {:?} in type", other))),
+        }
+    }
+}
+
+impl Value {
+    /// Variant name, if value is enum.
+    pub fn variant(&self) -> Option<&str> {
+        match self {
+            Value::Variant { variant, .. } => Some(variant),
+            _ => None,
+        }
+    }
+
+    /// JSON in the same shape as serde derive writes it (externally tagged enums).
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Unit => serde_json::Value::Null,
+            Value::Bool(value) => json!(value),
+            Value::Char(value) => json!(value),
+            Value::U64(value) => json!(value),
+            Value::I64(value) => json!(value),
+            Value::U128(value) => u64::try_from(*value)
+                .map(|value| json!(value))
+                .unwrap_or_else(|_| json!(value.to_string())),
+            Value::I128(value) => i64::try_from(*value)
+                .map(|value| json!(value))
+                .unwrap_or_else(|_| json!(value.to_string())),
+            // shortest f32 representation, not widened one.
+            Value::F32(value) => json!(value.to_string().parse::<f64>().ok()),
+            Value::F64(value) => json!(value),
+            Value::Str(value) => json!(value),
+            Value::Seq(values) => values.iter().map(Value::to_json).collect(),
+            Value::Option(value) => value
+                .as_ref()
+                .map_or(serde_json::Value::Null, |value| value.to_json()),
+            Value::Struct { fields, .. } => fields.to_json(),
+            Value::Variant {
+                variant, fields, ..
+            } => match fields {
+                Fields::Unit => json!(variant),
+                fields => json!({ variant: fields.to_json() }),
+            },
+        }
+    }
+}
+
+impl Fields {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Fields::Unit => serde_json::Value::Null,
+            Fields::Tuple(values) if values.len() == 1 => values[0].to_json(),
+            Fields::Tuple(values) => values.iter().map(Value::to_json).collect(),
+            Fields::Named(values) => values
+                .iter()
+                .map(|(name, value)| (name.clone(), value.to_json()))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        }
+    }
+
+    fn fmt_with_name(&self, name: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(name)?;
+        match self {
+            Fields::Unit => Ok(()),
+            Fields::Tuple(values) => {
+                f.write_str("(")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str(")")
+            }
+            Fields::Named(values) => {
+                f.write_str(" { ")?;
+                for (idx, (name, value)) in values.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", name, value)?;
+                }
+                f.write_str(" }")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// The same as `{:?}` of original type with derived Debug.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Unit => f.write_str("()"),
+            Value::Bool(value) => write!(f, "{:?}", value),
+            Value::Char(value) => write!(f, "{:?}", value),
+            Value::U64(value) => write!(f, "{:?}", value),
+            Value::I64(value) => write!(f, "{:?}", value),
+            Value::U128(value) => write!(f, "{:?}", value),
+            Value::I128(value) => write!(f, "{:?}", value),
+            Value::F32(value) => write!(f, "{:?}", value),
+            Value::F64(value) => write!(f, "{:?}", value),
+            Value::Str(value) => write!(f, "{:?}", value),
+            Value::Seq(values) => {
+                f.write_str("[")?;
+                for (idx, value) in values.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Value::Option(None) => f.write_str("None"),
+            Value::Option(Some(value)) => write!(f, "Some({})", value),
+            Value::Struct { name, fields } => fields.fmt_with_name(name, f),
+            Value::Variant {
+                variant, fields, ..
+            } => fields.fmt_with_name(variant, f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::schema_of;
+    use crate::level::LogLevel;
+    use crate::logclient::LogEventTs;
+    use arrayvec::ArrayString;
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub struct LogOneStruct {
+        some_num: u64,
+        some_other_num: i16,
+        some_string: ArrayString<16>,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub struct Pair<T>(T, [u8; 2], Option<f32>, ());
+
+    #[derive(Debug, Copy, Clone, PartialEq, GtsLogEvent)]
+    pub enum LogEvent {
+        LogOneOne(LogOneStruct),
+        LogTwo(Pair<char>),
+        Level { level: LogLevel, big: i128 },
+        Empty,
+    }
+
+    fn roundtrip<T: GtsLogEvent + std::fmt::Debug>(value: T, root: &str) -> Value {
+        let schema = Schema::parse(root, &schema_of::<T>()).unwrap();
+        let mut buf = Vec::new();
+        value.encode(&mut buf);
+        let mut src = buf.as_slice();
+        let decoded = schema.decode(&mut src).unwrap();
+        assert!(src.is_empty());
+        assert_eq!(decoded.to_string(), format!("{:?}", value));
+        decoded
+    }
+
+    #[test]
+    fn test_decode() {
+        let value = roundtrip(
+            LogEvent::LogOneOne(LogOneStruct {
+                some_num: 5,
+                some_other_num: -7,
+                some_string: ArrayString::from("3\"3").unwrap(),
+            }),
+            "LogEvent",
+        );
+        assert_eq!(value.variant(), Some("LogOneOne"));
+        assert_eq!(
+            value.to_json().to_string(),
+            r#"{"LogOneOne":{"some_num":5,"some_other_num":-7,"some_string":"3\"3"}}"#
+        );
+
+        let value = roundtrip(
+            LogEvent::LogTwo(Pair('x', [1, 2], Some(0.1), ())),
+            "LogEvent",
+        );
+        assert_eq!(
+            value.to_json().to_string(),
+            r#"{"LogTwo":["x",[1,2],0.1,null]}"#
+        );
+
+        let value = roundtrip(
+            LogEvent::Level {
+                level: LogLevel::Warn,
+                big: i128::MIN,
+            },
+            "LogEvent",
+        );
+        assert_eq!(
+            value.to_json().to_string(),
+            format!(r#"{{"Level":{{"level":"Warn","big":"{}"}}}}"#, i128::MIN)
+        );
+        assert_eq!(
+            roundtrip(LogEvent::Empty, "LogEvent").to_json(),
+            json!("Empty")
+        );
+
+        roundtrip(
+            LogEventTs::new(1, 2, LogLevel::Trace, Pair(-1i8, [0, 255], None, ())),
+            "LogEventTs<Pair<i8>>",
+        );
+        roundtrip([Some(1u64), None], "[Option<u64>; 2]");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Schema::parse("LogEvent", "struct LogEvent { a: u8").is_err());
+        assert!(Schema::parse("LogEvent", "union LogEvent { a: u8 }").is_err());
+        assert!(Schema::parse("LogEvent", "struct LogEvent { a: &u8 }").is_err());
+
+        let schema = Schema::parse("Missing", &schema_of::<LogEvent>()).unwrap();
+        assert!(schema.decode(&mut [0u8; 64].as_slice()).is_err());
+        let schema = Schema::parse("LogEvent", &schema_of::<LogEvent>()).unwrap();
+        assert!(schema.decode(&mut [9u8].as_slice()).is_err());
+        assert!(schema.decode(&mut [0u8, 1].as_slice()).is_err());
+    }
+}
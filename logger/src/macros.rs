@@ -0,0 +1,71 @@
+//! Log macros, see [`crate::record`].
+
+/// Logs [`LogRecord`](crate::record::LogRecord) at level via LogClient, returns
+/// `Result<(), GtsLoggerError>`. Arguments are not evaluated, if level is filtered out.
+#[macro_export]
+macro_rules! gts_log {
+    ($client:expr, $level:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        static CALLSITE: $crate::record::Callsite = $crate::record::Callsite {
+            level: $level,
+            fmt: $fmt,
+            module_path: module_path!(),
+            file: file!(),
+            line: line!(),
+        };
+        let client = &$client;
+        if client.enabled(CALLSITE.level) {
+            const {
+                assert!(
+                    $crate::record::supported_fmt($fmt),
+                    "log format string supports only {{}}, {{:?}}, {{N}} and {{N:?}} placeholders"
+                )
+            };
+            // never executed, only checks format string and arguments at compile time.
+            if false {
+                let _ = format_args!($fmt $(, $arg)*);
+            }
+            let record = $crate::record::LogRecord::new(
+                &CALLSITE,
+                [$($crate::record::LogArg::from($arg)),*],
+            );
+            client.log_at(CALLSITE.level, record)
+        } else {
+            Ok(())
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! gts_error {
+    ($client:expr, $($rest:tt)+) => {
+        $crate::gts_log!($client, $crate::level::LogLevel::Error, $($rest)+)
+    };
+}
+
+#[macro_export]
+macro_rules! gts_warn {
+    ($client:expr, $($rest:tt)+) => {
+        $crate::gts_log!($client, $crate::level::LogLevel::Warn, $($rest)+)
+    };
+}
+
+#[macro_export]
+macro_rules! gts_info {
+    ($client:expr, $($rest:tt)+) => {
+        $crate::gts_log!($client, $crate::level::LogLevel::Info, $($rest)+)
+    };
+}
+
+#[macro_export]
+macro_rules! gts_debug {
+    ($client:expr, $($rest:tt)+) => {
+        $crate::gts_log!($client, $crate::level::LogLevel::Debug, $($rest)+)
+    };
+}
+
+#[macro_export]
+macro_rules! gts_trace {
+    ($client:expr, $($rest:tt)+) => {
+        $crate::gts_log!($client, $crate::level::LogLevel::Trace, $($rest)+)
+    };
+}
@@ -0,0 +1,324 @@
+//! Record of log macros ([`gts_info!`](crate::gts_info) etc.) with deferred formatting.
+//!
+//! Macro captures static [`Callsite`] (format string, level, file/line) and copies of
+//! arguments ([`LogArg`]) to fixed size [`LogRecord`], so producer doesn't format anything.
+//! Message is formatted by backend thread, when record is displayed.
+//!
+//! Format string is checked by compiler (as in `format!`), but only `{}`, `{:?}` and
+//! positional `{0}` / `{0:?}` placeholders are supported, at most [`MAX_ARGS`] arguments.
+//! Other format specs (`{:x}`, `{:.2}`, `{:>8}`) and captured identifiers (`{qty}`)
+//! are rejected at compile time (see [`supported_fmt`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_logger::gts_info;
+//! use gts_logger::logbackend::mock::MockLogBacked;
+//! use gts_logger::logclient::LogClient;
+//! use gts_logger::record::LogRecord;
+//!
+//! let log_client = LogClient::<_, LogRecord>::new(MockLogBacked::new());
+//! let (price, qty) = (101.5, 7u32);
+//! gts_info!(log_client, "order {} @ {} sent to {:?}", qty, price, "venue")?;
+//!
+//! let event = log_client.backend().pop_front().unwrap();
+//! assert_eq!(event.data.to_string(), "order 7 @ 101.5 sent to \"venue\"");
+//! # Ok::<(), gts_logger::error::GtsLoggerError>(())
+//! ```
+//!
+//! ```compile_fail
+//! # use gts_logger::gts_info;
+//! # use gts_logger::logbackend::mock::MockLogBacked;
+//! # use gts_logger::logclient::LogClient;
+//! # use gts_logger::record::LogRecord;
+//! # let log_client = LogClient::<_, LogRecord>::new(MockLogBacked::new());
+//! let qty = 7u32;
+//! gts_info!(log_client, "qty {qty}").unwrap();
+//! ```
+//!
+//! ```compile_fail
+//! # use gts_logger::gts_info;
+//! # use gts_logger::logbackend::mock::MockLogBacked;
+//! # use gts_logger::logclient::LogClient;
+//! # use gts_logger::record::LogRecord;
+//! # let log_client = LogClient::<_, LogRecord>::new(MockLogBacked::new());
+//! gts_info!(log_client, "price {:.2}", 101.5).unwrap();
+//! ```
+
+use crate::level::LogLevel;
+use arrayvec::ArrayString;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+pub const MAX_ARGS: usize = 8;
+
+/// Checks, that format string has only placeholders supported by [`LogRecord`]:
+/// `{}`, `{:?}`, `{N}` and `{N:?}`. Used by log macros at compile time.
+pub const fn supported_fmt(fmt: &str) -> bool {
+    let bytes = fmt.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        // escaped {{ or }}.
+        if (bytes[pos] == b'{' || bytes[pos] == b'}')
+            && pos + 1 < bytes.len()
+            && bytes[pos + 1] == bytes[pos]
+        {
+            pos += 2;
+            continue;
+        }
+        if bytes[pos] != b'{' {
+            pos += 1;
+            continue;
+        }
+        pos += 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        if pos + 1 < bytes.len() && bytes[pos] == b':' && bytes[pos + 1] == b'?' {
+            pos += 2;
+        }
+        if pos >= bytes.len() || bytes[pos] != b'}' {
+            return false;
+        }
+        pos += 1;
+    }
+    true
+}
+
+/// Static part of log macro call, its address identifies call.
+#[derive(Debug)]
+pub struct Callsite {
+    pub level: LogLevel,
+    pub fmt: &'static str,
+    pub module_path: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+}
+
+/// Copy of macro argument.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LogArg {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+    Char(char),
+    Str(&'static str),
+    Str16(ArrayString<16>),
+}
+
+macro_rules! impl_from_arg {
+    ($variant:ident, $as:ty, $($t:ty),*) => {
+        $(impl From<$t> for LogArg {
+            fn from(value: $t) -> Self {
+                LogArg::$variant(value as $as)
+            }
+        })*
+    };
+}
+
+impl_from_arg!(U64, u64, u8, u16, u32, u64, usize);
+impl_from_arg!(I64, i64, i8, i16, i32, i64, isize);
+impl_from_arg!(F64, f64, f32, f64);
+
+impl From<bool> for LogArg {
+    fn from(value: bool) -> Self {
+        LogArg::Bool(value)
+    }
+}
+
+impl From<char> for LogArg {
+    fn from(value: char) -> Self {
+        LogArg::Char(value)
+    }
+}
+
+impl From<&'static str> for LogArg {
+    fn from(value: &'static str) -> Self {
+        LogArg::Str(value)
+    }
+}
+
+impl From<ArrayString<16>> for LogArg {
+    fn from(value: ArrayString<16>) -> Self {
+        LogArg::Str16(value)
+    }
+}
+
+impl fmt::Display for LogArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogArg::U64(value) => value.fmt(f),
+            LogArg::I64(value) => value.fmt(f),
+            LogArg::F64(value) => value.fmt(f),
+            LogArg::Bool(value) => value.fmt(f),
+            LogArg::Char(value) => value.fmt(f),
+            LogArg::Str(value) => value.fmt(f),
+            LogArg::Str16(value) => value.fmt(f),
+        }
+    }
+}
+
+impl LogArg {
+    fn fmt_debug(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogArg::Char(value) => write!(f, "{value:?}"),
+            LogArg::Str(value) => write!(f, "{value:?}"),
+            LogArg::Str16(value) => write!(f, "{:?}", value.as_str()),
+            other => fmt::Display::fmt(other, f),
+        }
+    }
+}
+
+/// Fixed size record of log macro call.
+#[derive(Copy, Clone)]
+pub struct LogRecord {
+    callsite: &'static Callsite,
+    nargs: u8,
+    args: [LogArg; MAX_ARGS],
+}
+
+impl LogRecord {
+    pub fn new<const N: usize>(callsite: &'static Callsite, args: [LogArg; N]) -> Self {
+        const { assert!(N <= MAX_ARGS, "too many arguments of log macro") };
+        let mut all = [LogArg::U64(0); MAX_ARGS];
+        all[..N].copy_from_slice(&args);
+        Self {
+            callsite,
+            nargs: N as u8,
+            args: all,
+        }
+    }
+
+    pub fn callsite(&self) -> &'static Callsite {
+        self.callsite
+    }
+
+    pub fn args(&self) -> &[LogArg] {
+        &self.args[..self.nargs as usize]
+    }
+}
+
+impl fmt::Display for LogRecord {
+    /// Formats message, unsupported placeholder (see [`supported_fmt`]) or placeholder
+    /// without argument is written as is.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.callsite.fmt;
+        let mut next_arg = 0;
+        while let Some(pos) = rest.find(['{', '}']) {
+            f.write_str(&rest[..pos])?;
+            let tail = &rest[pos..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                f.write_str(&tail[..1])?;
+                rest = &tail[2..];
+                continue;
+            }
+            let Some(end) = tail.find('}').filter(|_| tail.starts_with('{')) else {
+                f.write_str(tail)?;
+                return Ok(());
+            };
+            let placeholder = &tail[1..end];
+            let (index, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+            let index = match index {
+                "" => {
+                    next_arg += 1;
+                    next_arg - 1
+                }
+                index => index.parse().unwrap_or(usize::MAX),
+            };
+            match (self.args().get(index), spec) {
+                (Some(arg), "?") => arg.fmt_debug(f)?,
+                (Some(arg), "") => fmt::Display::fmt(arg, f)?,
+                _ => f.write_str(&tail[..=end])?,
+            }
+            rest = &tail[end + 1..];
+        }
+        f.write_str(rest)
+    }
+}
+
+impl fmt::Debug for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} {}", self.callsite.file, self.callsite.line, self)
+    }
+}
+
+impl Serialize for LogRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut record = serializer.serialize_struct("LogRecord", 3)?;
+        record.serialize_field("msg", &self.to_string())?;
+        record.serialize_field("file", self.callsite.file)?;
+        record.serialize_field("line", &self.callsite.line)?;
+        record.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{LevelThreshold, LogLevelFilter};
+    use crate::logbackend::mock::MockLogBacked;
+    use crate::logclient::LogClient;
+    use std::cell::Cell;
+
+    static CALLSITE: Callsite = Callsite {
+        level: LogLevel::Info,
+        fmt: "{{a}} {} {:?} {1} {} {:x} {}",
+        module_path: module_path!(),
+        file: file!(),
+        line: line!(),
+    };
+
+    #[test]
+    fn test_format() {
+        let name = ArrayString::<16>::from("qwe").unwrap();
+        let record = LogRecord::new(
+            &CALLSITE,
+            [(-5i32).into(), name.into(), 'c'.into(), 2.5f32.into()],
+        );
+        assert_eq!(record.args().len(), 4);
+        assert_eq!(record.to_string(), "{a} -5 \"qwe\" qwe c {:x} {}");
+        assert!(format!("{:?}", record).ends_with(" {a} -5 \"qwe\" qwe c {:x} {}"));
+        assert!(serde_json::to_string(&record)
+            .unwrap()
+            .starts_with("{\"msg\":\"{a} -5 \\\"qwe\\\" qwe c {:x} {}\",\"file\":"));
+    }
+
+    #[test]
+    fn test_supported_fmt() {
+        for fmt in ["", "{{a}} }}", "{} {:?} {1} {0:?}", "{{{}}}"] {
+            assert!(supported_fmt(fmt), "{}", fmt);
+        }
+        for fmt in ["{:x}", "{:.2}", "{:>8}", "{qty}", "{0:#?}", "{:?x}", "a {"] {
+            assert!(!supported_fmt(fmt), "{}", fmt);
+        }
+    }
+
+    #[test]
+    fn test_macros() {
+        static THRESHOLD: LevelThreshold = LevelThreshold::new(LogLevelFilter::Info);
+        let log_client =
+            LogClient::<_, LogRecord>::new(MockLogBacked::new()).with_threshold(&THRESHOLD);
+        let evaluated = Cell::new(0u32);
+        let arg = || {
+            evaluated.set(evaluated.get() + 1);
+            evaluated.get()
+        };
+
+        crate::gts_debug!(log_client, "skipped {}", arg()).unwrap();
+        assert_eq!(evaluated.get(), 0);
+        assert!(log_client.backend().pop_front().is_none());
+
+        crate::gts_info!(log_client, "no args").unwrap();
+        crate::gts_error!(log_client, "n={} ok={}", arg(), true).unwrap();
+        let event = log_client.backend().pop_front().unwrap();
+        assert_eq!(event.level, LogLevel::Info);
+        assert_eq!(event.data.to_string(), "no args");
+        let event = log_client.backend().pop_front().unwrap();
+        assert_eq!(event.level, LogLevel::Error);
+        assert_eq!(event.data.to_string(), "n=1 ok=true");
+        assert_eq!(event.data.callsite().file, file!());
+        assert_eq!(evaluated.get(), 1);
+    }
+}
@@ -0,0 +1,126 @@
+//! Decodes binary log file (see `gts_logger::logfile`) to JSON lines or text.
+use gts_logger::error::GtsLoggerError;
+use gts_logger::logfile::dynamic::Schema;
+use gts_logger::logfile::LogFileReader;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const USAGE: &str = "usage: gts-logcat [--json | --text] [--from NANOS] [--to NANOS] \
+[--variant NAME]... [--schema] [FILE]
+
+Decodes binary log FILE (stdin by default) to JSON lines (default) or text.
+  --from, --to   unix nanos, events with from <= timestamp < to are printed
+  --variant      prints only events of variant NAME of event enum, could be repeated
+  --schema       prints header of file instead of events";
+
+#[derive(Default)]
+struct Args {
+    text: bool,
+    schema: bool,
+    from: Option<u64>,
+    to: Option<u64>,
+    variants: Vec<String>,
+    file: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs value", name));
+        match arg.as_str() {
+            "--json" => parsed.text = false,
+            "--text" => parsed.text = true,
+            "--schema" => parsed.schema = true,
+            "--from" | "--to" => {
+                let nanos = value(&arg)?
+                    .parse()
+                    .map_err(|err| format!("bad {}: {}", arg, err))?;
+                match arg.as_str() {
+                    "--from" => parsed.from = Some(nanos),
+                    _ => parsed.to = Some(nanos),
+                }
+            }
+            "--variant" => parsed.variants.push(value(&arg)?),
+            "-h" | "--help" => return Err(String::new()),
+            file if !file.starts_with('-') && parsed.file.is_none() => {
+                parsed.file = Some(file.to_string())
+            }
+            other => return Err(format!("unexpected argument {}", other)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn cat(args: &Args, src: impl Read, out: &mut impl Write) -> Result<(), GtsLoggerError> {
+    let mut reader = LogFileReader::new(src)?;
+    let header = reader.header().clone();
+    if args.schema {
+        writeln!(out, "root: {}", header.root)?;
+        writeln!(out, "fingerprint: {:x}", header.fingerprint)?;
+        writeln!(out, "created: {}", header.created)?;
+        writeln!(out, "system_time: {}", header.system_time)?;
+        writeln!(out, "{}", header.schema)?;
+        return Ok(());
+    }
+    let schema = Schema::parse(&header.root, &header.schema)?;
+
+    let mut last_ts = None;
+    while let Some(event) = reader.next_raw()? {
+        if args.from.is_some_and(|from| event.timestamp < from)
+            || args.to.is_some_and(|to| event.timestamp >= to)
+        {
+            continue;
+        }
+        let mut data = event.data;
+        let value = schema.decode(&mut data)?;
+        if !args.variants.is_empty()
+            && !value
+                .variant()
+                .is_some_and(|variant| args.variants.iter().any(|name| name == variant))
+        {
+            continue;
+        }
+        if args.text {
+            match last_ts.map(|val| event.timestamp.wrapping_sub(val) as i64) {
+                None => write!(out, "[{}] @{} (-) ", event.level, event.timestamp)?,
+                Some(diff) => write!(
+                    out,
+                    "[{}] @{} (+{} ns) ",
+                    event.level, event.timestamp, diff
+                )?,
+            }
+            writeln!(out, "{}", value)?;
+        } else {
+            let line = json!({
+                "timestamp": event.timestamp,
+                "seqid": event.seqid,
+                "level": event.level,
+                "data": value.to_json(),
+            });
+            writeln!(out, "{}", line)?;
+        }
+        last_ts = Some(event.timestamp);
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), GtsLoggerError> {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{}", err);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    match &args.file {
+        Some(file) => cat(&args, BufReader::new(File::open(file)?), &mut out)?,
+        None => cat(&args, BufReader::new(std::io::stdin().lock()), &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Drains shmem rings of `ShmemLogBackend` clients (see `gts_logger::logbackend::shmem`)
+//! to binary log files or JSON lines.
+use gts_logger::error::GtsLoggerError;
+use gts_logger::logbackend::shmem::ShmemLogReader;
+use gts_logger::logfile::dynamic::Schema;
+use gts_logger::logfile::{decode_record, LogFileHeader};
+use gts_transport::membackend::shmem::ShmemOptions;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: gts-logd [--json | --binary] [--dir DIR] [--namespace NS] \
+[--poll MICROS] [--until-exit] NAME...
+
+Drains shmem rings of clients NAME... to DIR/NAME.CREATED.glog (binary log file, default)
+or DIR/NAME.CREATED.jsonl (JSON lines as gts-logcat --json), CREATED is start time of client.
+  --dir          output directory, current by default
+  --namespace    namespace of segments
+  --poll         sleep when all rings are empty, 1000 us by default
+  --until-exit   exits when all clients exited and their rings are drained";
+
+/// Events written per client before other clients are polled.
+const BATCH: u64 = 4096;
+/// Interval of attempts to connect to absent or replaced segment.
+const RECONNECT: Duration = Duration::from_millis(100);
+
+struct Args {
+    json: bool,
+    dir: PathBuf,
+    namespace: Option<String>,
+    poll: Duration,
+    until_exit: bool,
+    names: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        json: false,
+        dir: PathBuf::from("."),
+        namespace: None,
+        poll: Duration::from_micros(1000),
+        until_exit: false,
+        names: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs value", name));
+        match arg.as_str() {
+            "--json" => parsed.json = true,
+            "--binary" => parsed.json = false,
+            "--dir" => parsed.dir = PathBuf::from(value(&arg)?),
+            "--namespace" => parsed.namespace = Some(value(&arg)?),
+            "--poll" => {
+                let micros = value(&arg)?
+                    .parse()
+                    .map_err(|err| format!("bad {}: {}", arg, err))?;
+                parsed.poll = Duration::from_micros(micros);
+            }
+            "--until-exit" => parsed.until_exit = true,
+            "-h" | "--help" => return Err(String::new()),
+            name if !name.starts_with('-') => parsed.names.push(name.to_string()),
+            other => return Err(format!("unexpected argument {}", other)),
+        }
+    }
+    if parsed.names.is_empty() {
+        return Err("no clients".to_string());
+    }
+    Ok(parsed)
+}
+
+/// Output file of one segment.
+struct Output {
+    dest: BufWriter<File>,
+    /// Decoder of events for JSON, None for binary file.
+    schema: Option<Schema>,
+}
+
+impl Output {
+    /// Appends to file, if daemon was restarted.
+    fn open(args: &Args, name: &str, header: &LogFileHeader) -> Result<Self, GtsLoggerError> {
+        let ext = if args.json { "jsonl" } else { "glog" };
+        let path = args
+            .dir
+            .join(format!("{}.{}.{}", name, header.created, ext));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let empty = file.metadata()?.len() == 0;
+        let mut dest = BufWriter::new(file);
+        let schema = if args.json {
+            Some(Schema::parse(&header.root, &header.schema)?)
+        } else {
+            if empty {
+                dest.write_all(&header.encode())?;
+            }
+            None
+        };
+        eprintln!("gts-logd: {} -> {}", name, path.display());
+        Ok(Output { dest, schema })
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<(), GtsLoggerError> {
+        let Some(schema) = &self.schema else {
+            self.dest.write_all(&(record.len() as u32).to_le_bytes())?;
+            self.dest.write_all(record)?;
+            return Ok(());
+        };
+        let event = decode_record(record)?;
+        let mut data = event.data;
+        let line = json!({
+            "timestamp": event.timestamp,
+            "seqid": event.seqid,
+            "level": event.level,
+            "data": schema.decode(&mut data)?.to_json(),
+        });
+        writeln!(self.dest, "{}", line)?;
+        Ok(())
+    }
+}
+
+struct Client {
+    name: String,
+    reader: Option<(ShmemLogReader, Output)>,
+    dropped: u64,
+    /// Owner exited and ring is drained.
+    exited: bool,
+    last_attempt: Option<Instant>,
+}
+
+impl Client {
+    fn new(name: String) -> Self {
+        Client {
+            name,
+            reader: None,
+            dropped: 0,
+            exited: false,
+            last_attempt: None,
+        }
+    }
+
+    fn attempt(&mut self) -> bool {
+        if self
+            .last_attempt
+            .is_some_and(|last| last.elapsed() < RECONNECT)
+        {
+            return false;
+        }
+        self.last_attempt = Some(Instant::now());
+        true
+    }
+
+    fn connect(&mut self, args: &Args, options: &ShmemOptions) -> Result<(), GtsLoggerError> {
+        if !self.attempt() {
+            return Ok(());
+        }
+        // absent segment is not an error, client is not started yet.
+        let Ok(reader) = ShmemLogReader::connect_with_options(options, &self.name) else {
+            return Ok(());
+        };
+        let output = Output::open(args, &self.name, reader.header())?;
+        self.dropped = 0;
+        self.exited = false;
+        self.reader = Some((reader, output));
+        Ok(())
+    }
+
+    /// Writes events of ring, returns number of written events.
+    fn poll(&mut self, args: &Args, options: &ShmemOptions) -> Result<u64, GtsLoggerError> {
+        let Some((reader, output)) = &mut self.reader else {
+            self.connect(args, options)?;
+            return Ok(0);
+        };
+        // events sent before exit are in ring, if owner is dead already.
+        let alive = reader.owner_alive();
+        let mut count = 0;
+        let mut failed = None;
+        while count < BATCH {
+            let Some(record) = reader.try_recv()? else {
+                break;
+            };
+            if let Err(err) = output.write(record) {
+                failed = Some(err);
+                break;
+            }
+            count += 1;
+        }
+        // records are taken from ring already, so they are committed before error is returned,
+        // failed one is reported as dropped, otherwise flush of client waits for it.
+        let flushed = if count > 0 {
+            let flushed = output.dest.flush();
+            reader.commit(count);
+            flushed
+        } else {
+            Ok(())
+        };
+        if let Some(err) = failed {
+            reader.drop_events(1);
+            self.dropped += 1;
+            return Err(err);
+        }
+        flushed?;
+        let dropped = reader.dropped();
+        if dropped > self.dropped {
+            eprintln!(
+                "gts-logd: {}: {} events dropped by client",
+                self.name,
+                dropped - self.dropped
+            );
+            self.dropped = dropped;
+        }
+        if count == 0 && !alive {
+            self.exited = true;
+            if self.attempt() {
+                let (reader, output) = self.reader.take().unwrap();
+                match reader.reconnect() {
+                    Ok((reader, false)) => self.reader = Some((reader, output)),
+                    Ok((reader, true)) => {
+                        let output = Output::open(args, &self.name, reader.header())?;
+                        self.exited = false;
+                        self.dropped = 0;
+                        self.reader = Some((reader, output));
+                    }
+                    // segment is removed on clean exit of owner, connect by name later.
+                    Err(_) => {}
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+fn run(args: &Args) -> Result<(), GtsLoggerError> {
+    let mut options = ShmemOptions::new();
+    if let Some(namespace) = &args.namespace {
+        options = options.namespace(namespace);
+    }
+    let mut clients: Vec<Client> = args.names.iter().cloned().map(Client::new).collect();
+    loop {
+        let mut idle = true;
+        for client in &mut clients {
+            match client.poll(args, &options) {
+                Ok(count) => idle &= count == 0,
+                Err(err) => {
+                    eprintln!("gts-logd: {}: {}", client.name, err);
+                    client.reader = None;
+                }
+            }
+        }
+        if args.until_exit && clients.iter().all(|client| client.exited) {
+            return Ok(());
+        }
+        if idle {
+            std::thread::sleep(args.poll);
+        }
+    }
+}
+
+fn main() -> Result<(), GtsLoggerError> {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{}", err);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    run(&args)
+}
@@ -1,7 +1,10 @@
+use bytemuck::NoUninit;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use gts_transport::error::GtsTransportError;
+use gts_transport::membackend::memchunk::MemChunkHolder;
 use gts_transport::membackend::shmem::ShmemHolder;
-use gts_transport::sync::lfspmc::{SpMcReceiver, SpMcSender};
+use gts_transport::sync::lfspmc::{spmc_pair, SpMcReceiver, SpMcSender};
+use gts_transport::sync::racycopy::{racy_load, racy_store};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::TryRecvError;
 use std::sync::Arc;
@@ -99,6 +102,9 @@ struct TestData {
     timestamp: u64,
 }
 
+// SAFETY: no padding.
+unsafe impl NoUninit for TestData {}
+
 #[repr(C)]
 #[derive(Copy, Debug, Clone)]
 struct TestDataBigT<const TSIZE: usize> {
@@ -106,6 +112,9 @@ struct TestDataBigT<const TSIZE: usize> {
     trash: [u64; TSIZE],
 }
 
+// SAFETY: repr(C) of u64 fields, no padding.
+unsafe impl<const TSIZE: usize> NoUninit for TestDataBigT<TSIZE> {}
+
 impl<const TSIZE: usize> Default for TestDataBigT<TSIZE> {
     fn default() -> Self {
         TestDataBigT {
@@ -118,6 +127,7 @@ impl<const TSIZE: usize> Default for TestDataBigT<TSIZE> {
 #[repr(C)]
 //#[derive(Copy, Debug, Clone)]
 #[derive(Copy, Debug, Clone, Default)]
+#[allow(dead_code)]
 struct TestDataBig2 {
     timestamp: u64,
     timestamp2: u64,
@@ -130,6 +140,9 @@ struct TestDataBig2 {
     // timestamp9: u64,
 }
 
+// SAFETY: repr(C) of u64 fields, no padding.
+unsafe impl NoUninit for TestDataBig2 {}
+
 fn bench_shmem(c: &mut Criterion) {
     // The first call will take some time for calibartion
     let test_shmem1 = "crit_tx1";
@@ -338,12 +351,89 @@ fn bench_shmem_big(c: &mut Criterion) {
     server.join().expect("join failed");
 }
 
+fn bench_racycopy_t<T: NoUninit + Default>(c: &mut Criterion, name: &str) {
+    let src = T::default();
+    let mut shared = std::mem::MaybeUninit::<T>::uninit();
+    let mut dst = std::mem::MaybeUninit::<T>::uninit();
+
+    // plain copy, as lfspmc did before atomic words copy.
+    let mut group = c.benchmark_group(format!("racycopy {name}"));
+    group.bench_function("plain", |b| {
+        b.iter(|| unsafe {
+            std::ptr::copy_nonoverlapping(black_box(&src), shared.as_mut_ptr(), 1);
+            std::ptr::copy_nonoverlapping(black_box(shared.as_ptr()), dst.as_mut_ptr(), 1);
+            black_box(&dst);
+        })
+    });
+    group.bench_function("atomic words", |b| {
+        b.iter(|| unsafe {
+            racy_store(black_box(&src), shared.as_mut_ptr());
+            racy_load(black_box(shared.as_ptr()), dst.as_mut_ptr());
+            black_box(&dst);
+        })
+    });
+    group.finish();
+}
+
+/// Seqlock of lfspmc before atomic words copy.
+#[repr(C)]
+struct PlainSpMcData<T> {
+    begin: AtomicU32,
+    data: std::mem::MaybeUninit<T>,
+    end: AtomicU32,
+}
+
+fn plain_spmc_send<T: Copy>(pdata: *mut PlainSpMcData<T>, seqnum: u32, value: &T) {
+    unsafe {
+        (*pdata).begin.store(seqnum, Ordering::Release);
+        std::ptr::copy_nonoverlapping(value, (*pdata).data.as_mut_ptr(), 1);
+        (*pdata).end.store(seqnum, Ordering::Release);
+    }
+}
+
+fn bench_spmc_ping_t<T: NoUninit + Default>(c: &mut Criterion, name: &str) {
+    let value = T::default();
+    let mut plain = PlainSpMcData {
+        begin: AtomicU32::new(0),
+        data: std::mem::MaybeUninit::<T>::uninit(),
+        end: AtomicU32::new(0),
+    };
+    let (mut tx, _rx) = spmc_pair::<T, _>(MemChunkHolder::zeroed());
+
+    let mut group = c.benchmark_group(format!("spmc ping {name}"));
+    let mut seqnum = 0u32;
+    group.bench_function("plain", |b| {
+        b.iter(|| {
+            seqnum = seqnum.wrapping_add(1);
+            plain_spmc_send(black_box(&mut plain), seqnum, black_box(&value));
+        })
+    });
+    group.bench_function("atomic words", |b| {
+        b.iter(|| tx.send(black_box(&value)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_spmc_ping(c: &mut Criterion) {
+    bench_spmc_ping_t::<TestDataBig2>(c, "16b");
+    bench_spmc_ping_t::<TestDataBigT<7>>(c, "64b");
+    bench_spmc_ping_t::<TestDataBigT<63>>(c, "512b");
+}
+
+fn bench_racycopy(c: &mut Criterion) {
+    bench_racycopy_t::<TestDataBig2>(c, "16b");
+    bench_racycopy_t::<TestDataBigT<7>>(c, "64b");
+    bench_racycopy_t::<TestDataBigT<63>>(c, "512b");
+}
+
 criterion_group!(
     benches,
     bench_thread_mpsc,
     bench_atomic_swap,
     bench_shmem,
-    bench_shmem_big
+    bench_shmem_big,
+    bench_racycopy,
+    bench_spmc_ping
 );
 //criterion_group!(benches, bench_shmem);
 criterion_main!(benches);
@@ -0,0 +1,165 @@
+//! Cache line size of target and padding to it.
+//!
+//! [`CACHE_LINE_SIZE`] is chosen at compile time per target: it's 128 on x86_64, because of
+//! adjacent-line prefetch (pairs of 64 bytes lines are fetched together), and on aarch64 and
+//! powerpc64, which have 128 bytes lines on some cpus. Data of different writers are put to
+//! different [`CachePadded`] lines to eliminate false sharing.
+//!
+//! [`detect_cache_line_size`] reads actual size at runtime (sysfs, then CPUID), so
+//! startup could check, that compile time padding is enough for this host.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::arch::{CachePadded, CACHE_LINE_SIZE};
+//! use std::sync::atomic::AtomicU32;
+//!
+//! #[repr(C)]
+//! struct Cursors {
+//!     read: CachePadded<AtomicU32>,
+//!     write: CachePadded<AtomicU32>,
+//! }
+//!
+//! assert_eq!(std::mem::size_of::<Cursors>(), 2 * CACHE_LINE_SIZE);
+//! assert!(gts_transport::arch::padding_is_sufficient());
+//! ```
+
+use bytemuck::Zeroable;
+use log::warn;
+use std::ops::{Deref, DerefMut};
+use std::sync::OnceLock;
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "powerpc64"
+))]
+pub const CACHE_LINE_SIZE: usize = 128;
+
+#[cfg(target_arch = "s390x")]
+pub const CACHE_LINE_SIZE: usize = 256;
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "powerpc64",
+    target_arch = "s390x"
+)))]
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// T aligned and padded to [`CACHE_LINE_SIZE`].
+// repr(align) takes only literal, so it's repeated per target.
+#[cfg_attr(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64"
+    ),
+    repr(C, align(128))
+)]
+#[cfg_attr(target_arch = "s390x", repr(C, align(256)))]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "powerpc64",
+        target_arch = "s390x"
+    )),
+    repr(C, align(64))
+)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+unsafe impl<T: Zeroable> Zeroable for CachePadded<T> {}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+const SYSFS_LINE_SIZE: &str = "/sys/devices/system/cpu/cpu0/cache/index0/coherency_line_size";
+
+/// Cache line size of host, None if it can't be detected.
+pub fn detect_cache_line_size() -> Option<usize> {
+    sysfs_cache_line_size().or_else(cpuid_cache_line_size)
+}
+
+fn sysfs_cache_line_size() -> Option<usize> {
+    let size = std::fs::read_to_string(SYSFS_LINE_SIZE).ok()?;
+    size.trim().parse().ok().filter(|&size| size > 0)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpuid_cache_line_size() -> Option<usize> {
+    // leaf 1, EBX[15:8] is CLFLUSH line size in 8 bytes units.
+    let ebx = std::arch::x86_64::__cpuid(1).ebx;
+    Some(((ebx >> 8) & 0xff) as usize * 8).filter(|&size| size > 0)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpuid_cache_line_size() -> Option<usize> {
+    None
+}
+
+/// Detected cache line size (cached), [`CACHE_LINE_SIZE`] if it can't be detected.
+pub fn cache_line_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(|| detect_cache_line_size().unwrap_or(CACHE_LINE_SIZE))
+}
+
+/// False (and warns) if cache line of host is longer, than compile time padding.
+pub fn padding_is_sufficient() -> bool {
+    let size = cache_line_size();
+    if size > CACHE_LINE_SIZE {
+        warn!("cache line of host {size} > CACHE_LINE_SIZE {CACHE_LINE_SIZE}, false sharing is possible");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_layout() {
+        assert_eq!(std::mem::align_of::<CachePadded<u8>>(), CACHE_LINE_SIZE);
+        assert_eq!(std::mem::size_of::<CachePadded<u8>>(), CACHE_LINE_SIZE);
+        assert_eq!(
+            std::mem::size_of::<CachePadded<[u8; CACHE_LINE_SIZE + 1]>>(),
+            2 * CACHE_LINE_SIZE
+        );
+        let mut padded = CachePadded::new(1u32);
+        *padded += 1;
+        assert_eq!(padded.into_inner(), 2);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sysfs and cpuid are not supported by miri.
+    fn test_detect() {
+        let size = cache_line_size();
+        assert!(size.is_power_of_two(), "{size}");
+        assert!(padding_is_sufficient());
+    }
+}
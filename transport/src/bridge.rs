@@ -0,0 +1,627 @@
+//! Bridge mirrors a local channel to another host.
+//! Sender side polls any [`ChannelSource`] (e.g. [`crate::sync::lfringspsc::SpScRingReceiver`],
+//! [`crate::sync::lfspmc::SpMcReceiver`]) and writes framed values to TCP stream or UDP socket,
+//! receiver side reads frames and republishes values to any [`ChannelSink`]
+//! (e.g. sender of a [`crate::membackend::shmem::ShmemHolder`] channel).
+//!
+//! Frame: `{magic: u32, version: u16, reserved: u16, len: u32, seqnum: u64, fingerprint: u64}`
+//! (little-endian) followed by `len` bytes of value (see [`crate::wire`]).
+//! Receiver rejects frames with unexpected fingerprint and counts seqnum gaps as lost values
+//! (could happen with UDP, or SPMC source which keeps only the last value).
+//! Received bytes are not trusted: values are checked by [`CheckedBitPattern`].
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::bridge::{BridgeReceiver, BridgeSender};
+//! use gts_transport::membackend::memchunk::MemChunkHolder;
+//! use gts_transport::sync::lfringspsc::spsc_ring_pair;
+//! use std::net::{TcpListener, TcpStream};
+//!
+//! let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+//! let mut bridge_tx = BridgeSender::<u64, _>::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap());
+//! let mut bridge_rx = BridgeReceiver::<u64, _>::new(listener.accept().unwrap().0);
+//!
+//! let (mut local_tx, mut local_rx) = spsc_ring_pair::<8, u64, _>(MemChunkHolder::zeroed());
+//! local_tx.send(&42).unwrap();
+//! assert_eq!(bridge_tx.pump(&mut local_rx, 100).unwrap(), 1);
+//! assert_eq!(bridge_rx.recv().unwrap(), 42);
+//! ```
+
+use crate::channel::{ChannelSink, ChannelSource};
+use crate::error::GtsTransportError;
+use crate::wire::{fingerprint, read_value, write_value};
+use bytemuck::{CheckedBitPattern, NoUninit};
+use std::io::{ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+pub const FRAME_MAGIC: u32 = u32::from_le_bytes(*b"GTSB");
+pub const FRAME_VERSION: u16 = 1;
+pub const FRAME_HEADER_SIZE: usize = 28;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameHeader {
+    pub len: u32,
+    pub seqnum: u64,
+    pub fingerprint: u64,
+}
+
+impl FrameHeader {
+    pub fn encode(&self, dst: &mut [u8]) {
+        dst[0..4].copy_from_slice(&FRAME_MAGIC.to_le_bytes());
+        dst[4..6].copy_from_slice(&FRAME_VERSION.to_le_bytes());
+        dst[6..8].copy_from_slice(&0u16.to_le_bytes());
+        dst[8..12].copy_from_slice(&self.len.to_le_bytes());
+        dst[12..20].copy_from_slice(&self.seqnum.to_le_bytes());
+        dst[20..28].copy_from_slice(&self.fingerprint.to_le_bytes());
+    }
+
+    pub fn decode(src: &[u8]) -> Result<Self, GtsTransportError> {
+        if src.len() < FRAME_HEADER_SIZE {
+            return Err(GtsTransportError::LogicError("short frame".to_string()));
+        }
+        let magic = u32::from_le_bytes(src[0..4].try_into().unwrap());
+        let version = u16::from_le_bytes(src[4..6].try_into().unwrap());
+        if magic != FRAME_MAGIC || version != FRAME_VERSION {
+            return Err(GtsTransportError::LogicError(format!(
+                "bad frame magic {:x} / version {}",
+                magic, version
+            )));
+        }
+        Ok(FrameHeader {
+            len: u32::from_le_bytes(src[8..12].try_into().unwrap()),
+            seqnum: u64::from_le_bytes(src[12..20].try_into().unwrap()),
+            fingerprint: u64::from_le_bytes(src[20..28].try_into().unwrap()),
+        })
+    }
+}
+
+/// Encodes values of T to frames with increasing seqnum.
+struct FrameEncoder<T: NoUninit> {
+    seqnum: u64,
+    fingerprint: u64,
+    buf: Vec<u8>,
+    _owns_t: PhantomData<T>,
+}
+
+impl<T: NoUninit> FrameEncoder<T> {
+    const FRAME_SIZE: usize = FRAME_HEADER_SIZE + std::mem::size_of::<T>();
+
+    fn new() -> Self {
+        Self {
+            seqnum: 0,
+            fingerprint: fingerprint::<T>(),
+            buf: vec![0; Self::FRAME_SIZE],
+            _owns_t: PhantomData,
+        }
+    }
+
+    fn encode(&mut self, value: &T) -> &[u8] {
+        self.seqnum += 1;
+        FrameHeader {
+            len: std::mem::size_of::<T>() as u32,
+            seqnum: self.seqnum,
+            fingerprint: self.fingerprint,
+        }
+        .encode(&mut self.buf);
+        write_value(value, &mut self.buf[FRAME_HEADER_SIZE..]);
+        &self.buf
+    }
+}
+
+/// Checks frames of T and counts lost ones.
+struct FrameDecoder<T: CheckedBitPattern> {
+    last_seqnum: u64,
+    lost: u64,
+    fingerprint: u64,
+    _owns_t: PhantomData<T>,
+}
+
+impl<T: CheckedBitPattern> FrameDecoder<T> {
+    fn new() -> Self {
+        Self {
+            last_seqnum: 0,
+            lost: 0,
+            fingerprint: fingerprint::<T>(),
+            _owns_t: PhantomData,
+        }
+    }
+
+    fn check(&mut self, header: &FrameHeader) -> Result<(), GtsTransportError> {
+        if header.fingerprint != self.fingerprint || header.len as usize != std::mem::size_of::<T>()
+        {
+            return Err(GtsTransportError::LogicError(format!(
+                "frame fingerprint {:x}/{} doesn't match {:x}/{}",
+                header.fingerprint,
+                header.len,
+                self.fingerprint,
+                std::mem::size_of::<T>()
+            )));
+        }
+        if header.seqnum > self.last_seqnum + 1 {
+            self.lost += header.seqnum - self.last_seqnum - 1;
+        }
+        self.last_seqnum = header.seqnum;
+        Ok(())
+    }
+
+    fn decode(&mut self, frame: &[u8]) -> Result<T, GtsTransportError> {
+        let header = FrameHeader::decode(frame)?;
+        self.check(&header)?;
+        let payload = &frame[FRAME_HEADER_SIZE..];
+        if payload.len() < header.len as usize {
+            return Err(GtsTransportError::LogicError("short frame".to_string()));
+        }
+        read_value(payload)
+    }
+}
+
+fn map_io_error(err: std::io::Error) -> GtsTransportError {
+    match err.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => GtsTransportError::WouldBlock,
+        _ => GtsTransportError::StdIoError(err),
+    }
+}
+
+/// Stream (TCP) sender side of the bridge.
+pub struct BridgeSender<T: NoUninit, W: Write> {
+    dest: W,
+    encoder: FrameEncoder<T>,
+}
+
+impl<T: NoUninit, W: Write> BridgeSender<T, W> {
+    pub fn new(dest: W) -> Self {
+        Self {
+            dest,
+            encoder: FrameEncoder::new(),
+        }
+    }
+
+    /// Writes one value, doesn't flush.
+    pub fn send(&mut self, value: &T) -> Result<(), GtsTransportError> {
+        let frame = self.encoder.encode(value);
+        self.dest.write_all(frame)?;
+        Ok(())
+    }
+
+    /// Forwards up to max_count available values from source and flushes,
+    /// returns number of forwarded values.
+    pub fn pump(
+        &mut self,
+        source: &mut impl ChannelSource<T>,
+        max_count: usize,
+    ) -> Result<usize, GtsTransportError> {
+        let mut counter = 0;
+        while counter < max_count {
+            match source.poll() {
+                Ok(value) => {
+                    self.send(&value)?;
+                    counter += 1;
+                }
+                Err(GtsTransportError::WouldBlock) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if counter > 0 {
+            self.dest.flush()?;
+        }
+        Ok(counter)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.dest
+    }
+}
+
+/// Stream (TCP) receiver side of the bridge.
+pub struct BridgeReceiver<T: CheckedBitPattern, R: Read> {
+    src: R,
+    decoder: FrameDecoder<T>,
+    buf: Vec<u8>,
+    /// Bytes of frame read so far, partial frame is kept between calls.
+    filled: usize,
+    /// Value rejected by sink of pump_into, returned first by recv.
+    pending: Option<T>,
+}
+
+impl<T: CheckedBitPattern, R: Read> BridgeReceiver<T, R> {
+    pub fn new(src: R) -> Self {
+        Self {
+            src,
+            decoder: FrameDecoder::new(),
+            buf: vec![0; FRAME_HEADER_SIZE + std::mem::size_of::<T>()],
+            filled: 0,
+            pending: None,
+        }
+    }
+
+    /// Reads next value, blocks if src is blocking.
+    /// Returns WouldBlock for nonblocking src (or src with timeout) without full frame,
+    /// bytes read already are kept and next call continues the frame.
+    pub fn recv(&mut self) -> Result<T, GtsTransportError> {
+        if let Some(value) = self.pending.take() {
+            return Ok(value);
+        }
+        while self.filled < self.buf.len() {
+            match self.src.read(&mut self.buf[self.filled..]) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(len) => self.filled += len,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(map_io_error(err)),
+            }
+        }
+        self.filled = 0;
+        self.decoder.decode(&self.buf)
+    }
+
+    /// Reads up to max_count values and pushes them to sink,
+    /// returns number of republished values.
+    /// Returns WouldBlock if sink is full, value rejected by sink is pushed first by next call.
+    pub fn pump_into(
+        &mut self,
+        sink: &mut impl ChannelSink<T>,
+        max_count: usize,
+    ) -> Result<usize, GtsTransportError> {
+        for counter in 0..max_count {
+            let value = match self.recv() {
+                Ok(value) => value,
+                Err(GtsTransportError::WouldBlock) => return Ok(counter),
+                Err(err) => return Err(err),
+            };
+            if let Err(err) = sink.push(&value) {
+                self.pending = Some(value);
+                return Err(err);
+            }
+        }
+        Ok(max_count)
+    }
+
+    /// Number of values missed according to seqnums.
+    pub fn lost(&self) -> u64 {
+        self.decoder.lost
+    }
+}
+
+/// UDP (unicast or multicast) sender side of the bridge, one value per datagram.
+pub struct UdpBridgeSender<T: NoUninit> {
+    socket: UdpSocket,
+    dest: SocketAddr,
+    encoder: FrameEncoder<T>,
+}
+
+impl<T: NoUninit> UdpBridgeSender<T> {
+    pub fn new(socket: UdpSocket, dest: SocketAddr) -> Self {
+        Self {
+            socket,
+            dest,
+            encoder: FrameEncoder::new(),
+        }
+    }
+
+    /// Binds to any port and sends to multicast group (or unicast) dest.
+    pub fn bind(dest: SocketAddr) -> Result<Self, GtsTransportError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        Ok(Self::new(socket, dest))
+    }
+
+    pub fn send(&mut self, value: &T) -> Result<(), GtsTransportError> {
+        let frame = self.encoder.encode(value);
+        self.socket.send_to(frame, self.dest)?;
+        Ok(())
+    }
+
+    /// Forwards up to max_count available values from source,
+    /// returns number of forwarded values.
+    pub fn pump(
+        &mut self,
+        source: &mut impl ChannelSource<T>,
+        max_count: usize,
+    ) -> Result<usize, GtsTransportError> {
+        let mut counter = 0;
+        while counter < max_count {
+            match source.poll() {
+                Ok(value) => {
+                    self.send(&value)?;
+                    counter += 1;
+                }
+                Err(GtsTransportError::WouldBlock) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(counter)
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+/// UDP receiver side of the bridge.
+pub struct UdpBridgeReceiver<T: CheckedBitPattern> {
+    socket: UdpSocket,
+    decoder: FrameDecoder<T>,
+    buf: Vec<u8>,
+    /// Value rejected by sink of pump_into, returned first by recv.
+    pending: Option<T>,
+}
+
+impl<T: CheckedBitPattern> UdpBridgeReceiver<T> {
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            decoder: FrameDecoder::new(),
+            buf: vec![0; FRAME_HEADER_SIZE + std::mem::size_of::<T>()],
+            pending: None,
+        }
+    }
+
+    /// Binds to `0.0.0.0:port` and joins multicast group on interface.
+    pub fn join_multicast(
+        group: Ipv4Addr,
+        port: u16,
+        interface: Ipv4Addr,
+    ) -> Result<Self, GtsTransportError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.join_multicast_v4(&group, &interface)?;
+        Ok(Self::new(socket))
+    }
+
+    /// Receives next value, returns WouldBlock for nonblocking socket without data.
+    pub fn recv(&mut self) -> Result<T, GtsTransportError> {
+        if let Some(value) = self.pending.take() {
+            return Ok(value);
+        }
+        let len = self.socket.recv(&mut self.buf).map_err(map_io_error)?;
+        self.decoder.decode(&self.buf[..len])
+    }
+
+    /// Receives up to max_count values and pushes them to sink,
+    /// returns number of republished values.
+    /// Returns WouldBlock if sink is full, value rejected by sink is pushed first by next call.
+    pub fn pump_into(
+        &mut self,
+        sink: &mut impl ChannelSink<T>,
+        max_count: usize,
+    ) -> Result<usize, GtsTransportError> {
+        for counter in 0..max_count {
+            let value = match self.recv() {
+                Ok(value) => value,
+                Err(GtsTransportError::WouldBlock) => return Ok(counter),
+                Err(err) => return Err(err),
+            };
+            if let Err(err) = sink.push(&value) {
+                self.pending = Some(value);
+                return Err(err);
+            }
+        }
+        Ok(max_count)
+    }
+
+    /// Number of values missed according to seqnums.
+    pub fn lost(&self) -> u64 {
+        self.decoder.lost
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membackend::memchunk::MemChunkHolder;
+    use crate::membackend::shmem::ShmemHolder;
+    use crate::sync::lfringspsc::spsc_ring_pair;
+    use crate::sync::lfspmc::{SpMcReceiver, SpMcSender};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Default)]
+    struct TestData {
+        timestamp: u64,
+        price: f64,
+        flag: u8,
+        _pad: [u8; 7],
+    }
+
+    // SAFETY: no padding, any bytes are valid.
+    unsafe impl bytemuck::Zeroable for TestData {}
+    unsafe impl bytemuck::Pod for TestData {}
+
+    #[test]
+    fn test_header() {
+        let header = FrameHeader {
+            len: 24,
+            seqnum: 77,
+            fingerprint: 0xdeadbeef,
+        };
+        let mut buf = [0u8; FRAME_HEADER_SIZE];
+        header.encode(&mut buf);
+        assert_eq!(FrameHeader::decode(&buf).unwrap(), header);
+        buf[0] = 0;
+        assert!(FrameHeader::decode(&buf).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sockets are not supported by miri.
+    fn test_tcp_spsc_to_shmem_spmc() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let remote = std::thread::spawn(move || {
+            let stream = listener.accept().unwrap().0;
+            let mut bridge_rx = BridgeReceiver::<TestData, _>::new(stream);
+            let shmem_name = "testbridgetcp";
+            let mut tx = SpMcSender::<TestData, _>::new(ShmemHolder::create(shmem_name));
+            let mut rx = SpMcReceiver::<TestData, _>::new(ShmemHolder::connect_ro(shmem_name));
+            let mut last = 0;
+            while last < 1000 {
+                assert_eq!(bridge_rx.pump_into(&mut tx, 1).unwrap(), 1);
+                let value = *rx.try_recv().unwrap();
+                assert_eq!(value.timestamp, last + 1);
+                assert_eq!(value.price, value.timestamp as f64 / 2.0);
+                last = value.timestamp;
+            }
+            assert_eq!(bridge_rx.lost(), 0);
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut bridge_tx = BridgeSender::<TestData, _>::new(std::io::BufWriter::new(stream));
+        let (mut local_tx, mut local_rx) =
+            spsc_ring_pair::<16, TestData, _>(MemChunkHolder::zeroed());
+        let mut sent = 0;
+        while sent < 1000 {
+            let value = TestData {
+                timestamp: sent + 1,
+                price: (sent + 1) as f64 / 2.0,
+                flag: (sent % 2) as u8,
+                ..Default::default()
+            };
+            if local_tx.send(&value).is_ok() {
+                sent += 1;
+            }
+            bridge_tx.pump(&mut local_rx, 8).unwrap();
+        }
+        while bridge_tx.pump(&mut local_rx, 8).unwrap() > 0 {}
+        remote.join().unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sockets are not supported by miri.
+    fn test_fingerprint_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut bridge_tx = BridgeSender::<u64, _>::new(
+            TcpStream::connect(listener.local_addr().unwrap()).unwrap(),
+        );
+        let mut bridge_rx = BridgeReceiver::<i64, _>::new(listener.accept().unwrap().0);
+        bridge_tx.send(&1).unwrap();
+        assert!(matches!(
+            bridge_rx.recv(),
+            Err(GtsTransportError::LogicError(_))
+        ));
+    }
+
+    /// Stream returning chunks, None is WouldBlock.
+    struct ChunkedStream(std::collections::VecDeque<Option<Vec<u8>>>);
+
+    impl Read for ChunkedStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Some(mut chunk)) => {
+                    let len = chunk.len().min(buf.len());
+                    buf[..len].copy_from_slice(&chunk[..len]);
+                    if len < chunk.len() {
+                        self.0.push_front(Some(chunk.split_off(len)));
+                    }
+                    Ok(len)
+                }
+                Some(None) => Err(ErrorKind::WouldBlock.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_partial_frame() {
+        let mut bridge_tx = BridgeSender::<u64, _>::new(Vec::new());
+        bridge_tx.send(&1).unwrap();
+        bridge_tx.send(&2).unwrap();
+        let frames = bridge_tx.get_ref().clone();
+        let frame_size = FRAME_HEADER_SIZE + 8;
+
+        let mut chunks = std::collections::VecDeque::new();
+        chunks.push_back(Some(frames[..5].to_vec()));
+        chunks.push_back(None);
+        chunks.push_back(Some(frames[5..frame_size + 3].to_vec()));
+        chunks.push_back(None);
+        chunks.push_back(Some(frames[frame_size + 3..].to_vec()));
+        let mut bridge_rx = BridgeReceiver::<u64, _>::new(ChunkedStream(chunks));
+
+        assert!(matches!(
+            bridge_rx.recv(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        assert_eq!(bridge_rx.recv().unwrap(), 1);
+        assert!(matches!(
+            bridge_rx.recv(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        assert_eq!(bridge_rx.recv().unwrap(), 2);
+        assert_eq!(bridge_rx.lost(), 0);
+        assert!(matches!(
+            bridge_rx.recv(),
+            Err(GtsTransportError::StdIoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_sink_would_block() {
+        let mut bridge_tx = BridgeSender::<u64, _>::new(Vec::new());
+        for value in 1..=3 {
+            bridge_tx.send(&value).unwrap();
+        }
+        let chunks = [Some(bridge_tx.get_ref().clone()), None];
+        let mut bridge_rx = BridgeReceiver::<u64, _>::new(ChunkedStream(chunks.into()));
+        let (mut sink_tx, mut sink_rx) = spsc_ring_pair::<3, u64, _>(MemChunkHolder::zeroed());
+
+        assert!(matches!(
+            bridge_rx.pump_into(&mut sink_tx, 10),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        assert_eq!(*sink_rx.try_recv().unwrap(), 1);
+        assert_eq!(*sink_rx.try_recv().unwrap(), 2);
+        // value rejected by full sink is not lost.
+        assert_eq!(bridge_rx.pump_into(&mut sink_tx, 10).unwrap(), 1);
+        assert_eq!(*sink_rx.try_recv().unwrap(), 3);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sockets are not supported by miri.
+    fn test_udp_loopback() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let dest = socket.local_addr().unwrap();
+        let mut bridge_rx = UdpBridgeReceiver::<TestData>::new(socket);
+        let mut bridge_tx =
+            UdpBridgeSender::<TestData>::new(UdpSocket::bind("127.0.0.1:0").unwrap(), dest);
+
+        let (mut spmc_tx, mut spmc_rx) =
+            crate::sync::lfspmc::spmc_pair::<TestData, _>(MemChunkHolder::zeroed());
+        let (mut sink_tx, mut sink_rx) =
+            spsc_ring_pair::<16, TestData, _>(MemChunkHolder::zeroed());
+
+        for i in 1..=5 {
+            spmc_tx
+                .send(&TestData {
+                    timestamp: i,
+                    ..Default::default()
+                })
+                .unwrap();
+            assert_eq!(bridge_tx.pump(&mut spmc_rx, 10).unwrap(), 1);
+        }
+        // lost datagram.
+        bridge_tx.encoder.seqnum += 1;
+        bridge_tx
+            .send(&TestData {
+                timestamp: 7,
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(bridge_rx.pump_into(&mut sink_tx, 6).unwrap(), 6);
+        for i in [1, 2, 3, 4, 5, 7] {
+            assert_eq!(sink_rx.try_recv().unwrap().timestamp, i);
+        }
+        assert_eq!(bridge_rx.lost(), 1);
+
+        bridge_rx.socket().set_nonblocking(true).unwrap();
+        assert!(matches!(
+            bridge_rx.recv(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+    }
+}
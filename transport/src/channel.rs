@@ -0,0 +1,54 @@
+//! Common traits over channel endpoints, used by tools which move data between
+//! channels (bridge, recorder, replayer) without caring about primitive.
+//!
+//! [`ChannelSource::poll`] returns `Err(GtsTransportError::WouldBlock)` when there is no new value.
+
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::{MemHolder, MemHolderMut};
+use crate::sync::lfringspsc::{SpScRingData, SpScRingReceiver, SpScRingSender};
+use crate::sync::lfspmc::{SpMcData, SpMcReceiver, SpMcSender};
+use bytemuck::NoUninit;
+
+pub trait ChannelSource<T> {
+    fn poll(&mut self) -> Result<T, GtsTransportError>;
+}
+
+pub trait ChannelSink<T> {
+    fn push(&mut self, value: &T) -> Result<(), GtsTransportError>;
+}
+
+impl<const RSIZE: usize, T: Copy, BackT: MemHolderMut<SpScRingData<RSIZE, T>>> ChannelSource<T>
+    for SpScRingReceiver<RSIZE, T, BackT>
+{
+    fn poll(&mut self) -> Result<T, GtsTransportError> {
+        self.try_recv_value()
+    }
+}
+
+impl<T: Copy, BackT: MemHolder<SpMcData<T>>> ChannelSource<T> for SpMcReceiver<T, BackT> {
+    fn poll(&mut self) -> Result<T, GtsTransportError> {
+        match self.try_recv_value() {
+            Ok(val) => Ok(val),
+            // nothing sent yet or writer is in the middle of send.
+            Err(GtsTransportError::Unitialized | GtsTransportError::Inconsistent) => {
+                Err(GtsTransportError::WouldBlock)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<const RSIZE: usize, T: Copy, BackT: MemHolderMut<SpScRingData<RSIZE, T>>> ChannelSink<T>
+    for SpScRingSender<RSIZE, T, BackT>
+{
+    fn push(&mut self, value: &T) -> Result<(), GtsTransportError> {
+        self.send(value)
+    }
+}
+
+impl<T: NoUninit, BackT: MemHolderMut<SpMcData<T>>> ChannelSink<T> for SpMcSender<T, BackT> {
+    fn push(&mut self, value: &T) -> Result<(), GtsTransportError> {
+        self.send(value)
+            .map_err(|_| GtsTransportError::CommonError("spmc send failed".to_string()))
+    }
+}
@@ -20,6 +20,21 @@ pub enum GtsTransportError {
     #[error("would block")]
     WouldBlock,
 
+    #[error("timeout")]
+    Timeout,
+
+    #[error("owner of ({0}) is alive")]
+    OwnerAlive(String),
+
+    #[error("({0}) already exists")]
+    AlreadyExists(String),
+
+    #[error("({0}) is incompatible")]
+    Incompatible(String),
+
+    #[error("decode error ({0})")]
+    DecodeError(String),
+
     #[error("StdIoError error")]
     StdIoError(#[from] std::io::Error),
 }
@@ -1,3 +1,10 @@
+pub mod arch;
+pub mod bridge;
+pub mod channel;
 pub mod error;
 pub mod membackend;
+pub mod record;
+pub mod rpc;
 pub mod sync;
+pub mod thread;
+pub mod wire;
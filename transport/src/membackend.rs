@@ -1,3 +1,5 @@
 pub mod memchunk;
+pub mod memfd;
 pub mod memholder;
+pub mod rawshmem;
 pub mod shmem;
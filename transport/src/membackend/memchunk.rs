@@ -6,8 +6,10 @@
 //!
 //! See also https://doc.rust-lang.org/reference/behavior-considered-undefined.html
 
-use crate::membackend::memholder::MemHolder;
+use crate::arch::CACHE_LINE_SIZE;
+use crate::membackend::memholder::{MemHolder, MemHolderMut, RawMemHolder, RawMemHolderMut};
 use bytemuck::Zeroable;
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
 use std::cell::UnsafeCell;
 use std::sync::Arc;
 
@@ -29,7 +31,8 @@ impl<T> Clone for MemChunkHolder<T> {
 }
 
 impl<T> MemChunkHolder<T> {
-    /// Safety: T must be Zeroed.
+    /// # Safety
+    /// T must be valid when all bytes are zero.
     pub unsafe fn init_zeroed() -> Self {
         // SAFETY: T must be Zeroed.
         let data = Arc::new(UnsafeCell::new(unsafe { std::mem::zeroed() }));
@@ -55,7 +58,7 @@ impl<T: Zeroable> MemChunkHolder<T> {
 impl<T: Default> Default for MemChunkHolder<T> {
     fn default() -> Self {
         let data = Arc::new(UnsafeCell::new(Default::default()));
-        let ptr = data.get() as *mut T;
+        let ptr = data.get();
 
         Self {
             _data_holder: data,
@@ -65,10 +68,70 @@ impl<T: Default> Default for MemChunkHolder<T> {
 }
 
 impl<T> MemHolder<T> for MemChunkHolder<T> {
+    fn get_ptr(&self) -> *const T {
+        self.data as *const T
+    }
+}
+
+impl<T> MemHolderMut<T> for MemChunkHolder<T> {
     fn get_mut_ptr(&self) -> *mut T {
         self.data
     }
-    fn get_ptr(&self) -> *const T {
-        self.data as *const T
+}
+
+/// Zeroed chunk of bytes of runtime length, [`RawMemHolder`] for one process.
+#[derive(Debug, Clone)]
+pub struct RawMemChunk {
+    chunk: Arc<RawChunk>,
+    length: usize,
+}
+
+#[derive(Debug)]
+struct RawChunk {
+    data: *mut u8,
+    layout: Layout,
+}
+
+unsafe impl Send for RawMemChunk {}
+unsafe impl Sync for RawChunk {}
+unsafe impl Send for RawChunk {}
+
+impl RawMemChunk {
+    const ALIGN: usize = CACHE_LINE_SIZE;
+
+    pub fn zeroed(length: usize) -> Self {
+        // zero sized alloc is UB.
+        let layout = Layout::from_size_align(length.max(1), Self::ALIGN).expect("bad length");
+        // SAFETY: layout has non zero size.
+        let data = unsafe { alloc_zeroed(layout) };
+        if data.is_null() {
+            handle_alloc_error(layout);
+        }
+        Self {
+            chunk: Arc::new(RawChunk { data, layout }),
+            length,
+        }
+    }
+}
+
+impl Drop for RawChunk {
+    fn drop(&mut self) {
+        // SAFETY: data is allocated with the same layout in RawMemChunk::zeroed.
+        unsafe { dealloc(self.data, self.layout) };
+    }
+}
+
+impl RawMemHolder for RawMemChunk {
+    fn get_ptr(&self) -> *const u8 {
+        self.chunk.data as *const u8
+    }
+    fn length(&self) -> usize {
+        self.length
+    }
+}
+
+impl RawMemHolderMut for RawMemChunk {
+    fn get_mut_ptr(&self) -> *mut u8 {
+        self.chunk.data
     }
 }
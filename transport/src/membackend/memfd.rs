@@ -0,0 +1,373 @@
+//! Memfd creates anonymous chunk of shared memory (no name in /dev/shm), which could be
+//! shared only by passing its fd: to child by fork (mapping is inherited, or fd
+//! number for exec, see [`MemfdHolder::inheritable_fd`]) or to any process over unix
+//! socket by SCM_RIGHTS ([`MemfdHolder::send_to`], [`MemfdHolder::recv_from`]).
+//!
+//! Size of memfd is sealed (`F_SEAL_GROW`/`F_SEAL_SHRINK`), so peer can't truncate it
+//! under our mapping (SIGBUS). Receiver checks seals, size and fingerprint of T.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::membackend::memfd::MemfdHolder;
+//! use gts_transport::sync::lfspmc::{SpMcData, SpMcReceiver, SpMcSender};
+//! use std::os::unix::net::UnixStream;
+//!
+//! #[derive(Copy, Clone, Debug)]
+//! struct TestData {
+//!     timestamp: u64,
+//! }
+//! // SAFETY: no padding.
+//! unsafe impl bytemuck::NoUninit for TestData {}
+//!
+//! let (parent_sock, child_sock) = UnixStream::pair()?;
+//! let holder = MemfdHolder::<SpMcData<TestData>>::create("md_feed")?;
+//! holder.send_to(&parent_sock)?;
+//! let mut tx = SpMcSender::new(holder);
+//!
+//! // e.g. in sandboxed child process.
+//! let mut rx = SpMcReceiver::new(MemfdHolder::<SpMcData<TestData>>::recv_from(&child_sock)?);
+//! tx.send(&TestData { timestamp: 1 }).unwrap();
+//! assert_eq!(rx.try_recv_value()?.timestamp, 1);
+//! # Ok::<(), gts_transport::error::GtsTransportError>(())
+//! ```
+
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::{MemHolder, MemHolderMut};
+use crate::wire::fingerprint;
+use bytemuck::Zeroable;
+use libc::{c_void, off_t};
+use libc::{F_ADD_SEALS, F_GET_SEALS, F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK};
+use libc::{MAP_FAILED, MAP_SHARED, MFD_ALLOW_SEALING, MFD_CLOEXEC, PROT_READ, PROT_WRITE};
+use log::error;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+const SEALS: i32 = F_SEAL_GROW | F_SEAL_SHRINK;
+
+#[derive(Debug)]
+struct MemfdMapping {
+    fd: OwnedFd,
+    data: *mut c_void,
+    length: usize,
+}
+
+// SAFETY: mapping is just a memory region, synchronization is on users of holder.
+unsafe impl Send for MemfdMapping {}
+unsafe impl Sync for MemfdMapping {}
+
+impl Drop for MemfdMapping {
+    fn drop(&mut self) {
+        let ret = unsafe { libc::munmap(self.data, self.length) };
+        if ret != 0 {
+            error!("MemfdHolder UNMAP OF {:p} -> {}", self.data, ret);
+        }
+    }
+}
+
+/// Clones share the same mapping, it's unmapped (and fd closed) with last clone.
+#[derive(Debug)]
+pub struct MemfdHolder<T> {
+    mapping: Arc<MemfdMapping>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for MemfdHolder<T> {
+    fn clone(&self) -> Self {
+        Self {
+            mapping: self.mapping.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn cvt(ret: libc::c_int) -> std::io::Result<libc::c_int> {
+    match ret {
+        -1 => Err(std::io::Error::last_os_error()),
+        ret => Ok(ret),
+    }
+}
+
+impl<T: Zeroable> MemfdHolder<T> {
+    /// Creates zeroed memfd of size of T, name is only for debug (/proc/pid/fd).
+    pub fn create(name: &str) -> Result<Self, GtsTransportError> {
+        let name_cstr = CString::new(name)
+            .map_err(|_| GtsTransportError::LogicError(format!("bad memfd name {name}")))?;
+        let fd = cvt(unsafe {
+            libc::memfd_create(name_cstr.as_ptr(), MFD_CLOEXEC | MFD_ALLOW_SEALING)
+        })?;
+        // SAFETY: fd is just created and owned only here.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let length = std::mem::size_of::<T>();
+        cvt(unsafe { libc::ftruncate(fd.as_raw_fd(), length as off_t) })?;
+        cvt(unsafe { libc::fcntl(fd.as_raw_fd(), F_ADD_SEALS, SEALS | F_SEAL_SEAL) })?;
+        Self::map(fd)
+    }
+
+    /// Maps memfd received from other process, checks its seals and size.
+    pub fn from_fd(fd: OwnedFd) -> Result<Self, GtsTransportError> {
+        let seals = cvt(unsafe { libc::fcntl(fd.as_raw_fd(), F_GET_SEALS) })?;
+        let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+        cvt(unsafe { libc::fstat(fd.as_raw_fd(), &mut st) })?;
+        if seals & SEALS != SEALS || st.st_size as usize != std::mem::size_of::<T>() {
+            return Err(GtsTransportError::Incompatible(format!(
+                "memfd {} (seals {seals:#x}, size {})",
+                fd.as_raw_fd(),
+                st.st_size
+            )));
+        }
+        Self::map(fd)
+    }
+
+    /// Receives memfd sent by [`Self::send_to`].
+    pub fn recv_from(stream: &UnixStream) -> Result<Self, GtsTransportError> {
+        let (fd, fingerprint_of_t) = recv_fd(stream)?;
+        if fingerprint_of_t != fingerprint::<T>() {
+            return Err(GtsTransportError::Incompatible(format!(
+                "memfd {} of other type",
+                fd.as_raw_fd()
+            )));
+        }
+        Self::from_fd(fd)
+    }
+
+    fn map(fd: OwnedFd) -> Result<Self, GtsTransportError> {
+        let length = std::mem::size_of::<T>();
+        let null = std::ptr::null_mut();
+        let prot = PROT_READ | PROT_WRITE;
+        let data = unsafe { libc::mmap(null, length, prot, MAP_SHARED, fd.as_raw_fd(), 0) };
+        if data == MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            mapping: Arc::new(MemfdMapping { fd, data, length }),
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T> MemfdHolder<T> {
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.mapping.fd.as_fd()
+    }
+
+    /// Sends memfd (with fingerprint of T) to peer of unix socket.
+    pub fn send_to(&self, stream: &UnixStream) -> Result<(), GtsTransportError> {
+        send_fd(stream, self.fd(), fingerprint::<T>())?;
+        Ok(())
+    }
+
+    /// Duplicate of fd without close-on-exec flag, to pass its number to exec'ed child
+    /// (e.g. in args), which calls `from_fd(OwnedFd::from_raw_fd(num))`.
+    /// Child after plain fork could just use inherited holder.
+    pub fn inheritable_fd(&self) -> Result<OwnedFd, GtsTransportError> {
+        let fd = cvt(unsafe { libc::dup(self.mapping.fd.as_raw_fd()) })?;
+        // SAFETY: fd is just duplicated, dup clears FD_CLOEXEC.
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+impl<T> MemHolder<T> for MemfdHolder<T> {
+    fn get_ptr(&self) -> *const T {
+        self.mapping.data as *const T
+    }
+}
+
+impl<T> MemHolderMut<T> for MemfdHolder<T> {
+    fn get_mut_ptr(&self) -> *mut T {
+        self.mapping.data as *mut T
+    }
+}
+
+/// Sends fd with 8 bytes of payload over unix socket (SCM_RIGHTS).
+pub fn send_fd(stream: &UnixStream, fd: BorrowedFd<'_>, payload: u64) -> std::io::Result<()> {
+    send_fds(stream, &[fd], payload)
+}
+
+fn send_fds(stream: &UnixStream, fds: &[BorrowedFd<'_>], payload: u64) -> std::io::Result<()> {
+    let mut payload = payload.to_le_bytes();
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+    // u64 array to align cmsg header.
+    let mut cmsg_buf = [0u64; 8];
+    let fds_size = std::mem::size_of_val(fds);
+
+    // SAFETY: msghdr points to live buffers, cmsg_buf is big enough for fds.
+    unsafe {
+        let space = libc::CMSG_SPACE(fds_size as u32) as usize;
+        assert!(space <= std::mem::size_of_val(&cmsg_buf));
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = space as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size as u32) as _;
+        let data = libc::CMSG_DATA(cmsg) as *mut RawFd;
+        for (idx, fd) in fds.iter().enumerate() {
+            std::ptr::write_unaligned(data.add(idx), fd.as_raw_fd());
+        }
+
+        let sent = libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL);
+        if sent == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if sent as usize != payload.len() {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+    }
+    Ok(())
+}
+
+/// Receives fd sent by [`send_fd`], fd is close-on-exec.
+/// Message with other number of fds or truncated one is rejected, received fds are closed.
+pub fn recv_fd(stream: &UnixStream) -> std::io::Result<(OwnedFd, u64)> {
+    let mut payload = [0u8; 8];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+    // space for several fds, so extra ones are received and closed, not left in socket.
+    let mut cmsg_buf = [0u64; 8];
+
+    // SAFETY: msghdr points to live buffers, fds from cmsgs are owned by us after recvmsg.
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = std::mem::size_of_val(&cmsg_buf) as _;
+
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if received == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut fds = Vec::new();
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for idx in 0..len / std::mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(std::ptr::read_unaligned(
+                        data.add(idx),
+                    )));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        if received as usize != payload.len() || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "truncated fd message",
+            ));
+        }
+        if fds.len() != 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} fds in message", fds.len()),
+            ));
+        }
+        Ok((fds.pop().unwrap(), u64::from_le_bytes(payload)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::lfringspsc::{spsc_ring_pair, SpScRingData};
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // memfd is not supported by miri.
+    fn test_send_over_socket() {
+        let (sock1, sock2) = UnixStream::pair().unwrap();
+        let holder = MemfdHolder::<SpScRingData<4, u64>>::create("test_ring").unwrap();
+        holder.send_to(&sock1).unwrap();
+        holder.send_to(&sock1).unwrap();
+
+        let res = MemfdHolder::<SpScRingData<5, u64>>::recv_from(&sock2);
+        assert!(matches!(res, Err(GtsTransportError::Incompatible(_))));
+        let remote = MemfdHolder::<SpScRingData<4, u64>>::recv_from(&sock2).unwrap();
+        assert_ne!(remote.fd().as_raw_fd(), holder.fd().as_raw_fd());
+
+        let (mut tx, _) = spsc_ring_pair::<4, u64, _>(holder);
+        let (_, mut rx) = spsc_ring_pair::<4, u64, _>(remote);
+        tx.send(&7).unwrap();
+        assert_eq!(rx.try_recv_value().unwrap(), 7);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sockets are not supported by miri.
+    fn test_recv_extra_fds() {
+        let (sock1, sock2) = UnixStream::pair().unwrap();
+        let mut pipe = [0; 2];
+        assert_eq!(
+            unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) },
+            0
+        );
+        let (read_end, write_end) =
+            unsafe { (OwnedFd::from_raw_fd(pipe[0]), OwnedFd::from_raw_fd(pipe[1])) };
+        send_fds(&sock1, &[write_end.as_fd(), write_end.as_fd()], 1).unwrap();
+        drop(write_end);
+
+        let res = recv_fd(&sock2);
+        assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        // all write ends are closed, so pipe is at EOF.
+        let mut buf = [0u8; 1];
+        let len = unsafe { libc::read(read_end.as_raw_fd(), buf.as_mut_ptr() as *mut c_void, 1) };
+        assert_eq!(len, 0);
+
+        send_fd(&sock1, read_end.as_fd(), 2).unwrap();
+        assert_eq!(recv_fd(&sock2).unwrap().1, 2);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // memfd is not supported by miri.
+    fn test_sealed() {
+        let holder = MemfdHolder::<[u64; 4]>::create("test_sealed").unwrap();
+        let fd = holder.fd().as_raw_fd();
+        assert_eq!(unsafe { libc::ftruncate(fd, 8) }, -1);
+        assert_eq!(unsafe { libc::ftruncate(fd, 1 << 20) }, -1);
+
+        let res = MemfdHolder::<[u64; 2]>::from_fd(holder.inheritable_fd().unwrap());
+        assert!(matches!(res, Err(GtsTransportError::Incompatible(_))));
+
+        // unsealed memfd is rejected.
+        let fd = unsafe { libc::memfd_create(c"test_unsealed".as_ptr(), MFD_CLOEXEC) };
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        assert_eq!(unsafe { libc::ftruncate(fd.as_raw_fd(), 32) }, 0);
+        let res = MemfdHolder::<[u64; 4]>::from_fd(fd);
+        assert!(matches!(res, Err(GtsTransportError::Incompatible(_))));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // fork is not supported by miri.
+    fn test_fork() {
+        let holder = MemfdHolder::<u64>::create("test_fork").unwrap();
+        let fd = holder.inheritable_fd().unwrap();
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, 0);
+
+        let pid = unsafe { libc::fork() };
+        assert_ne!(pid, -1);
+        if pid == 0 {
+            // child: only async signal safe calls here.
+            unsafe {
+                *holder.get_mut_ptr() = 42;
+                libc::_exit(0);
+            }
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert_eq!(unsafe { *holder.get_ptr() }, 42);
+    }
+}
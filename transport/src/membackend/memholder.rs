@@ -2,11 +2,58 @@
 //! It's UB to cast get_mut_ptr or get_ptr to &T or &mut T
 //! as soon as underlying objects could mutate.
 //!
+//! Holders of read only memory (e.g. shmem connected by `connect_ro`) implement only
+//! [`MemHolder`], so primitive, which writes to memory (e.g. spsc receiver writes its
+//! cursor) can't be built over them:
+//!
+//! ```compile_fail
+//! use gts_transport::membackend::shmem::ShmemHolder;
+//! use gts_transport::sync::lfringspsc::{SpScRingData, SpScRingReceiver};
+//!
+//! let back = ShmemHolder::<SpScRingData<4, u64>>::connect_ro("doc_ro_spsc");
+//! let rx = SpScRingReceiver::<4, u64, _>::new(back);
+//! ```
+//!
 //! See also https://doc.rust-lang.org/reference/behavior-considered-undefined.html
 
 pub trait MemHolder<T> {
     const LENGTH: usize = std::mem::size_of::<T>();
 
-    fn get_mut_ptr(&self) -> *mut T;
     fn get_ptr(&self) -> *const T;
 }
+
+/// Holder of writable memory.
+pub trait MemHolderMut<T>: MemHolder<T> {
+    fn get_mut_ptr(&self) -> *mut T;
+}
+
+/// Holder of chunk of bytes, which length is known only at runtime.
+/// Chunk is aligned at least to cache line ([`crate::arch::CACHE_LINE_SIZE`]).
+pub trait RawMemHolder {
+    fn get_ptr(&self) -> *const u8;
+    fn length(&self) -> usize;
+}
+
+/// Holder of writable chunk of bytes.
+pub trait RawMemHolderMut: RawMemHolder {
+    fn get_mut_ptr(&self) -> *mut u8;
+}
+
+/// Access to mapped memory, checked at compile time, see [`ReadOnly`] and [`ReadWrite`].
+pub trait Access {
+    const WRITE: bool;
+}
+
+#[derive(Debug)]
+pub enum ReadOnly {}
+
+#[derive(Debug)]
+pub enum ReadWrite {}
+
+impl Access for ReadOnly {
+    const WRITE: bool = false;
+}
+
+impl Access for ReadWrite {
+    const WRITE: bool = true;
+}
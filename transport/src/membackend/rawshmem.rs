@@ -0,0 +1,629 @@
+//! RawShmem creates/connect to chunk of shared memory, which length is known only at runtime.
+//! It's base of typed [`ShmemHolder`](crate::membackend::shmem::ShmemHolder) and of
+//! runtime sized primitives (lfdynspsc, lfdynspmc).
+//!
+//! Segment starts with header page ([`ShmemHeader`]): length and fingerprint of data,
+//! pid of owner and time of creation, so client can't connect to segment of other layout.
+//! Owner holds write lock (open file description lock, `F_OFD_SETLK`) on segment while alive,
+//! so next owner (with the same name) could detect, that channel is still in use,
+//! see [`CreateMode`]. Clients only probe the lock (`F_OFD_GETLK`), so they never make
+//! lock attempt of restarting owner fail.
+//! Clients could check [`RawShmem::owner_alive`] and reconnect to segment of restarted owner.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::error::GtsTransportError;
+//! use gts_transport::membackend::memholder::{RawMemHolder, RawMemHolderMut};
+//! use gts_transport::membackend::rawshmem::RawShmem;
+//!
+//! let owner = RawShmem::create("doc_rawshmem", 1000, 0x1234)?;
+//! unsafe { *owner.get_mut_ptr().add(999) = 7 };
+//!
+//! // client learns length from header.
+//! let client = RawShmem::connect_ro("doc_rawshmem")?;
+//! assert_eq!(client.length(), 1000);
+//! assert_eq!(client.header().fingerprint, 0x1234);
+//! assert_eq!(unsafe { *client.get_ptr().add(999) }, 7);
+//! # Ok::<(), GtsTransportError>(())
+//! ```
+
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::{Access, RawMemHolder, RawMemHolderMut, ReadOnly, ReadWrite};
+use libc::{c_int, c_void, gid_t, mode_t, off_t, uid_t};
+use libc::{close, fchmod, fchown, fcntl, fstat, ftruncate, mmap, munmap, shm_open, shm_unlink};
+use libc::{EACCES, EAGAIN, EEXIST, O_EXCL, PROT_READ};
+use libc::{F_OFD_GETLK, F_OFD_SETLK, F_UNLCK, F_WRLCK, SEEK_SET};
+use libc::{MAP_FAILED, MAP_SHARED, O_CREAT, O_RDONLY, O_RDWR, PROT_WRITE, S_IRUSR, S_IWUSR};
+use log::{error, warn};
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const MAGIC: u64 = u64::from_le_bytes(*b"GTSSHM01");
+const VERSION: u32 = 1;
+
+/// Size of header page, data starts right after it.
+pub const HEADER_SIZE: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ShmemHeader {
+    /// written last by owner, zero means segment is not ready yet.
+    pub magic: u64,
+    pub version: u32,
+    pub owner_pid: u32,
+    /// length of data.
+    pub size: u64,
+    /// fingerprint of data layout, e.g. [`fingerprint`](crate::wire::fingerprint) of T.
+    pub fingerprint: u64,
+    /// unix time in nanos.
+    pub created: u64,
+}
+
+/// What create does, if segment with the name exists.
+/// Segment of alive owner is never touched, create fails with `OwnerAlive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CreateMode {
+    /// fails with `AlreadyExists`.
+    FailIfExists,
+    /// takes segment of dead owner with its data, if it has the same length and fingerprint,
+    /// fails with `Incompatible` otherwise.
+    ReuseIfCompatible,
+    /// removes segment of dead owner and creates new one, clients have to reconnect.
+    #[default]
+    Replace,
+}
+
+/// Options of segment creation and name resolution.
+///
+/// Namespace is joined to name with '.', e.g. `prod.md_feed` (shm names can't contain '/').
+///
+/// ```
+/// use gts_transport::membackend::memholder::ReadOnly;
+/// use gts_transport::membackend::shmem::{CreateMode, ShmemHolder, ShmemOptions};
+///
+/// let options = ShmemOptions::new()
+///     .namespace("sim/")
+///     .mode(0o640)
+///     .create_mode(CreateMode::FailIfExists);
+/// let owner: ShmemHolder<u64> = options.create("doc_shmem_options")?;
+/// assert_eq!(owner.name(), "sim.doc_shmem_options");
+///
+/// let client: ShmemHolder<u64, ReadOnly> = options.connect_ro("doc_shmem_options")?;
+/// # Ok::<(), gts_transport::error::GtsTransportError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShmemOptions {
+    namespace: Option<String>,
+    mode: mode_t,
+    group: Option<gid_t>,
+    create_mode: CreateMode,
+}
+
+impl Default for ShmemOptions {
+    fn default() -> Self {
+        Self {
+            namespace: None,
+            mode: S_IRUSR | S_IWUSR,
+            group: None,
+            create_mode: CreateMode::default(),
+        }
+    }
+}
+
+impl ShmemOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prefix of names, e.g. per environment `prod`, `sim`. Trailing '/' or '.' is ignored.
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        let namespace = namespace.trim_end_matches(['/', '.']);
+        self.namespace = (!namespace.is_empty()).then(|| namespace.to_string());
+        self
+    }
+
+    /// Permission bits of created segment, default is 0o600.
+    /// Use 0o640 with [`Self::group`] to share read only with group.
+    pub fn mode(mut self, mode: mode_t) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Group of created segment, creator must be member of it.
+    pub fn group(mut self, gid: gid_t) -> Self {
+        self.group = Some(gid);
+        self
+    }
+
+    pub fn create_mode(mut self, create_mode: CreateMode) -> Self {
+        self.create_mode = create_mode;
+        self
+    }
+
+    /// Name of segment in namespace.
+    pub fn full_name(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{namespace}.{name}"),
+            None => name.to_string(),
+        }
+    }
+
+    pub fn create_raw(
+        &self,
+        name: &str,
+        length: usize,
+        fingerprint: u64,
+    ) -> Result<RawShmem, GtsTransportError> {
+        RawShmem::create_with_options(&self.full_name(name), length, fingerprint, self)
+    }
+
+    pub fn connect_raw_ro(&self, name: &str) -> Result<RawShmem<ReadOnly>, GtsTransportError> {
+        RawShmem::connect_ro(&self.full_name(name))
+    }
+
+    pub fn connect_raw_rw(&self, name: &str) -> Result<RawShmem<ReadWrite>, GtsTransportError> {
+        RawShmem::connect_rw(&self.full_name(name))
+    }
+}
+
+#[derive(Debug)]
+enum ShmemRole {
+    Owner,
+    Client,
+}
+
+/// Segment mapped with access A, only [`ReadWrite`] segment is [`RawMemHolderMut`].
+#[derive(Debug)]
+pub struct RawShmem<A: Access = ReadWrite> {
+    role: ShmemRole,
+    fd: c_int,
+    name: String,
+    // inode of segment, changes when owner replaces segment.
+    ino: u64,
+    header: *mut ShmemHeader,
+    length: usize,
+    _access: PhantomData<A>,
+}
+
+unsafe impl<A: Access> Send for RawShmem<A> {}
+
+/// Closes fd on drop, until it's moved to RawShmem.
+struct FdGuard(c_int);
+
+impl FdGuard {
+    fn into_raw(self) -> c_int {
+        let fd = self.0;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for FdGuard {
+    fn drop(&mut self) {
+        unsafe { close(self.0) };
+    }
+}
+
+fn last_os_error() -> GtsTransportError {
+    std::io::Error::last_os_error().into()
+}
+
+fn to_cstring(name: &str) -> Result<CString, GtsTransportError> {
+    CString::new(name).map_err(|_| GtsTransportError::LogicError(format!("bad shmem name {name}")))
+}
+
+/// Lock of whole segment, it's released when the last fd of the open is closed.
+fn lock_request(l_type: c_int) -> libc::flock {
+    // SAFETY: flock is plain C struct.
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = l_type as _;
+    lock.l_whence = SEEK_SET as _;
+    lock
+}
+
+/// Ok(false) if lock is held by other open of segment (alive owner).
+fn try_lock(fd: c_int) -> Result<bool, GtsTransportError> {
+    if unsafe { fcntl(fd, F_OFD_SETLK, &lock_request(F_WRLCK)) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(EAGAIN) | Some(EACCES) => Ok(false),
+        _ => Err(err.into()),
+    }
+}
+
+/// Ok(true) if lock is held by other open of segment, lock is not taken.
+fn is_locked(fd: c_int) -> Result<bool, GtsTransportError> {
+    let mut lock = lock_request(F_WRLCK);
+    if unsafe { fcntl(fd, F_OFD_GETLK, &mut lock) } != 0 {
+        return Err(last_os_error());
+    }
+    Ok(lock.l_type as c_int != F_UNLCK)
+}
+
+/// (inode, size) of segment.
+fn stat(fd: c_int) -> Result<(u64, usize), GtsTransportError> {
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { fstat(fd, &mut st) } != 0 {
+        return Err(last_os_error());
+    }
+    Ok((st.st_ino, st.st_size as usize))
+}
+
+fn now_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|dur| dur.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+impl RawShmem<ReadOnly> {
+    /// Connects to existing segment, see [`RawShmem::connect`].
+    pub fn connect_ro(name: &str) -> Result<Self, GtsTransportError> {
+        Self::connect(name)
+    }
+}
+
+impl RawShmem<ReadWrite> {
+    /// Creates zeroed segment of length bytes with default [`ShmemOptions`].
+    pub fn create(name: &str, length: usize, fingerprint: u64) -> Result<Self, GtsTransportError> {
+        Self::create_with_options(name, length, fingerprint, &ShmemOptions::new())
+    }
+
+    /// Connects to existing segment, see [`RawShmem::connect`].
+    pub fn connect_rw(name: &str) -> Result<Self, GtsTransportError> {
+        Self::connect(name)
+    }
+    pub(crate) fn create_with_options(
+        name: &str,
+        length: usize,
+        fingerprint: u64,
+        options: &ShmemOptions,
+    ) -> Result<Self, GtsTransportError> {
+        let name_cstr = to_cstring(name)?;
+        let cname = name_cstr.as_ptr();
+
+        let (fd, fresh) = Self::open_for_create(name, &name_cstr, options.create_mode)?;
+
+        if fresh {
+            // other creator could take lock between O_EXCL create and this call.
+            if !try_lock(fd.0)? {
+                return Err(GtsTransportError::OwnerAlive(name.to_string()));
+            }
+            if let Err(err) = Self::setup_fresh(fd.0, length, options) {
+                unsafe { shm_unlink(cname) };
+                return Err(err);
+            }
+        }
+
+        let res = Self::map(name, fd.0, length).and_then(|raw| {
+            // fresh segment is zeroed by ftruncate, so header is checked only on reuse.
+            if !fresh {
+                raw.check_header(Some(fingerprint))?;
+            }
+            Ok(raw)
+        });
+        let mut raw = match res {
+            Ok(raw) => raw,
+            Err(err) => {
+                if fresh {
+                    unsafe { shm_unlink(cname) };
+                }
+                return Err(err);
+            }
+        };
+        raw.fd = fd.into_raw();
+        raw.role = ShmemRole::Owner;
+
+        unsafe {
+            let header = raw.header;
+            (*header).version = VERSION;
+            (*header).owner_pid = std::process::id();
+            (*header).size = length as u64;
+            (*header).fingerprint = fingerprint;
+            (*header).created = now_nanos();
+            AtomicU64::from_ptr(std::ptr::addr_of_mut!((*header).magic))
+                .store(MAGIC, Ordering::Release);
+        }
+
+        Ok(raw)
+    }
+}
+
+impl<A: Access> RawShmem<A> {
+    /// Connects to existing segment, returns `Unitialized` if owner has not finished creation.
+    /// Caller must check [`Self::header`] fingerprint.
+    pub(crate) fn connect(name: &str) -> Result<Self, GtsTransportError> {
+        let shmem_flag = if A::WRITE { O_RDWR } else { O_RDONLY };
+        let name_cstr = to_cstring(name)?;
+
+        let fd = match unsafe { shm_open(name_cstr.as_ptr(), shmem_flag, 0) } {
+            -1 => return Err(last_os_error()),
+            fd => FdGuard(fd),
+        };
+        let (_, size) = stat(fd.0)?;
+        if size <= HEADER_SIZE {
+            // owner has not truncated it yet.
+            return Err(GtsTransportError::Unitialized);
+        }
+        let mut raw = Self::map(name, fd.0, size - HEADER_SIZE)?;
+        raw.check_header(None)?;
+        raw.fd = fd.into_raw();
+        Ok(raw)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn write_permission(&self) -> bool {
+        A::WRITE
+    }
+
+    pub fn header(&self) -> ShmemHeader {
+        unsafe { std::ptr::read_volatile(self.header) }
+    }
+
+    /// False if owner of segment exited (or crashed), or replaced segment by new one.
+    pub fn owner_alive(&self) -> bool {
+        if matches!(self.role, ShmemRole::Owner) {
+            return true;
+        }
+        match is_locked(self.fd) {
+            Ok(locked) => locked,
+            Err(err) => {
+                error!("owner_alive check of {} failed: {}", self.name, err);
+                false
+            }
+        }
+    }
+
+    /// True if name refers to other segment now (owner restarted), so client must reconnect.
+    /// Returns error if there is no segment with the name.
+    pub fn is_replaced(&self) -> Result<bool, GtsTransportError> {
+        if matches!(self.role, ShmemRole::Owner) {
+            return Err(GtsTransportError::LogicError(format!(
+                "reconnect of owner of {}",
+                self.name
+            )));
+        }
+        let name_cstr = to_cstring(&self.name)?;
+        let fd = match unsafe { shm_open(name_cstr.as_ptr(), O_RDONLY, 0) } {
+            -1 => return Err(last_os_error()),
+            fd => FdGuard(fd),
+        };
+        Ok(stat(fd.0)?.0 != self.ino)
+    }
+
+    fn setup_fresh(
+        fd: c_int,
+        length: usize,
+        options: &ShmemOptions,
+    ) -> Result<(), GtsTransportError> {
+        // mode of shm_open is masked by umask, so it's set explicitly.
+        if unsafe { fchmod(fd, options.mode) } != 0 {
+            return Err(last_os_error());
+        }
+        if let Some(gid) = options.group {
+            if unsafe { fchown(fd, uid_t::MAX, gid) } != 0 {
+                return Err(last_os_error());
+            }
+        }
+        if unsafe { ftruncate(fd, (HEADER_SIZE + length) as off_t) } != 0 {
+            return Err(last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Opens new segment or existing one of dead owner, returns (fd, is segment new).
+    fn open_for_create(
+        name: &str,
+        name_cstr: &CString,
+        mode: CreateMode,
+    ) -> Result<(FdGuard, bool), GtsTransportError> {
+        let cname = name_cstr.as_ptr();
+        let create_new =
+            || match unsafe { shm_open(cname, O_RDWR | O_CREAT | O_EXCL, S_IRUSR | S_IWUSR) } {
+                -1 => Err(std::io::Error::last_os_error()),
+                fd => Ok((FdGuard(fd), true)),
+            };
+
+        match create_new() {
+            Err(err) if err.raw_os_error() == Some(EEXIST) => {}
+            res => return res.map_err(GtsTransportError::from),
+        }
+
+        let fd = match unsafe { shm_open(cname, O_RDWR, 0) } {
+            -1 => return Err(last_os_error()),
+            fd => FdGuard(fd),
+        };
+        if !try_lock(fd.0)? {
+            return Err(GtsTransportError::OwnerAlive(name.to_string()));
+        }
+        match mode {
+            CreateMode::FailIfExists => Err(GtsTransportError::AlreadyExists(name.to_string())),
+            CreateMode::ReuseIfCompatible => Ok((fd, false)),
+            CreateMode::Replace => {
+                warn!("shmem {} of dead owner is replaced", name);
+                unsafe { shm_unlink(cname) };
+                drop(fd);
+                // other creator could win the race after unlink.
+                create_new().map_err(|err| match err.raw_os_error() {
+                    Some(EEXIST) => GtsTransportError::AlreadyExists(name.to_string()),
+                    _ => err.into(),
+                })
+            }
+        }
+    }
+
+    /// Maps segment as client, fd is not owned by result until it's set by caller.
+    fn map(name: &str, fd: c_int, length: usize) -> Result<Self, GtsTransportError> {
+        let (ino, size) = stat(fd)?;
+        if size == 0 {
+            // owner has not truncated it yet.
+            return Err(GtsTransportError::Unitialized);
+        }
+        if size != HEADER_SIZE + length {
+            return Err(GtsTransportError::Incompatible(name.to_string()));
+        }
+
+        let mmap_flag = if A::WRITE {
+            PROT_READ | PROT_WRITE
+        } else {
+            PROT_READ
+        };
+        let null = std::ptr::null_mut();
+        let addr = unsafe { mmap(null, size, mmap_flag, MAP_SHARED, fd, 0) };
+        if addr == MAP_FAILED {
+            return Err(last_os_error());
+        }
+
+        Ok(RawShmem {
+            // role is set to owner by create only after checks, so drop doesn't unlink.
+            role: ShmemRole::Client,
+            fd: -1,
+            name: name.to_string(),
+            ino,
+            header: addr as *mut ShmemHeader,
+            length,
+            _access: PhantomData,
+        })
+    }
+
+    fn check_header(&self, fingerprint: Option<u64>) -> Result<(), GtsTransportError> {
+        let magic = unsafe { AtomicU64::from_ptr(std::ptr::addr_of_mut!((*self.header).magic)) }
+            .load(Ordering::Acquire);
+        if magic == 0 {
+            return Err(GtsTransportError::Unitialized);
+        }
+        let header = self.header();
+        if magic != MAGIC
+            || header.version != VERSION
+            || header.size != self.length as u64
+            || fingerprint.is_some_and(|fingerprint| fingerprint != header.fingerprint)
+        {
+            return Err(GtsTransportError::Incompatible(self.name.clone()));
+        }
+        Ok(())
+    }
+}
+
+impl<A: Access> Drop for RawShmem<A> {
+    fn drop(&mut self) {
+        unsafe {
+            let ret = munmap(self.header as *mut c_void, HEADER_SIZE + self.length);
+            if ret != 0 {
+                error!("ShmemSender UNMAP OF {:p} -> {}", self.header, ret);
+            }
+
+            if self.fd != -1 {
+                let ret = close(self.fd);
+                if ret != 0 {
+                    error!("ShmemSender close err  OF {} -> {}", self.fd, ret);
+                }
+            }
+
+            if matches!(self.role, ShmemRole::Owner) {
+                let name_cstr = CString::new(self.name.as_str()).expect("no way!");
+                let ret = shm_unlink(name_cstr.as_ptr());
+                if ret != 0 {
+                    error!("ShmemSender shm_unlink err  OF {} -> {}", self.name, ret);
+                }
+            }
+        }
+    }
+}
+
+impl<A: Access> RawMemHolder for RawShmem<A> {
+    fn get_ptr(&self) -> *const u8 {
+        unsafe { (self.header as *const u8).add(HEADER_SIZE) }
+    }
+    fn length(&self) -> usize {
+        self.length
+    }
+}
+
+impl RawMemHolderMut for RawShmem<ReadWrite> {
+    fn get_mut_ptr(&self) -> *mut u8 {
+        unsafe { (self.header as *mut u8).add(HEADER_SIZE) }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Simulates crash of owner: lock is released, but segment is not unlinked.
+    pub(crate) fn crash(owner: RawShmem) {
+        unsafe { fcntl(owner.fd, F_OFD_SETLK, &lock_request(F_UNLCK)) };
+        std::mem::forget(owner);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_create_modes() {
+        let name = "test_rawshmem_create_modes";
+        let owner = RawShmem::create(name, 100, 1).unwrap();
+        for mode in [
+            CreateMode::FailIfExists,
+            CreateMode::ReuseIfCompatible,
+            CreateMode::Replace,
+        ] {
+            let res = ShmemOptions::new()
+                .create_mode(mode)
+                .create_raw(name, 100, 1);
+            assert!(matches!(res, Err(GtsTransportError::OwnerAlive(_))));
+        }
+        unsafe { *owner.get_mut_ptr() = 42 };
+        crash(owner);
+
+        let options = ShmemOptions::new().create_mode(CreateMode::FailIfExists);
+        let res = options.create_raw(name, 100, 1);
+        assert!(matches!(res, Err(GtsTransportError::AlreadyExists(_))));
+
+        let options = options.create_mode(CreateMode::ReuseIfCompatible);
+        for (length, fingerprint) in [(50, 1), (100, 2)] {
+            let res = options.create_raw(name, length, fingerprint);
+            assert!(matches!(res, Err(GtsTransportError::Incompatible(_))));
+        }
+        let owner = options.create_raw(name, 100, 1).unwrap();
+        assert_eq!(unsafe { *owner.get_ptr() }, 42);
+        crash(owner);
+
+        let owner = RawShmem::create(name, 100, 1).unwrap();
+        assert_eq!(unsafe { *owner.get_ptr() }, 0);
+        drop(owner);
+        assert!(RawShmem::connect_ro(name).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_options() {
+        let gid = unsafe { libc::getegid() };
+        let options = ShmemOptions::new()
+            .namespace("test/")
+            .mode(0o640)
+            .group(gid);
+        assert_eq!(options.full_name("x"), "test.x");
+        assert_eq!(ShmemOptions::new().namespace("").full_name("x"), "x");
+
+        let owner = options.create_raw("rawshmem_options", 8, 0).unwrap();
+        let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+        assert_eq!(unsafe { fstat(owner.fd, &mut st) }, 0);
+        assert_eq!(st.st_mode & 0o777, 0o640);
+        assert_eq!(st.st_gid, gid);
+
+        // the same name in other namespace is other segment.
+        let other = options
+            .clone()
+            .namespace("test2")
+            .create_raw("rawshmem_options", 8, 0)
+            .unwrap();
+        assert_ne!(other.ino, owner.ino);
+        assert!(RawShmem::connect_ro("rawshmem_options").is_err());
+        assert!(options.connect_raw_ro("rawshmem_options").is_ok());
+    }
+}
@@ -2,40 +2,51 @@
 //! While drops shmem holder ShmemHolder<T> doesn't call drop of underlying T.
 //! Logicaly T is Copy type, but could contain some Atomic* data, so it's not pure rust-Copy type
 //!
+//! It's typed wrapper of [`RawShmem`], segment is checked to be created for the same T
+//! (size and [`fingerprint`]). See rawshmem mod for [`CreateMode`] and [`ShmemOptions`].
+//! Clients could check [`ShmemHolder::owner_alive`] and [`ShmemHolder::reconnect`] to
+//! segment of restarted owner.
+//! Segment connected by `connect_ro` is `ShmemHolder<T, ReadOnly>`, which is not
+//! [`MemHolderMut`], so only readers (e.g. spmc receiver) could be built over it.
+//!
 //! # Examples
 //!
-//! Find in lfspmc mod
 //! ```
-//! use anyhow::Result;
+//! use gts_transport::error::GtsTransportError;
+//! use gts_transport::membackend::shmem::{CreateMode, ShmemHolder};
+//!
+//! let name = "doc_shmem_lifecycle";
+//! let owner = ShmemHolder::<u64>::create_with_mode(name, CreateMode::FailIfExists)?;
 //!
-//! pub fn test_fun(
-//! ) -> Result<(),()> {
-//!     Ok(())
-//! }
+//! // second instance can't steal channel from alive owner.
+//! let res = ShmemHolder::<u64>::create_with_mode(name, CreateMode::Replace);
+//! assert!(matches!(res, Err(GtsTransportError::OwnerAlive(_))));
 //!
-//! # Ok::<(), anyhow::Error>(())
+//! // neither client could connect with other type.
+//! let res = ShmemHolder::<u32>::try_connect_ro(name);
+//! assert!(matches!(res, Err(GtsTransportError::Incompatible(_))));
+//!
+//! let client = ShmemHolder::<u64>::try_connect_ro(name)?;
+//! assert!(client.owner_alive());
+//! assert_eq!(client.header().owner_pid, std::process::id());
+//! # Ok::<(), GtsTransportError>(())
 //! ```
 
-use crate::membackend::memholder::MemHolder;
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::{
+    Access, MemHolder, MemHolderMut, RawMemHolder, ReadOnly, ReadWrite,
+};
+use crate::wire::fingerprint;
 use bytemuck::Zeroable;
-use libc::{c_int, c_void, off_t};
-use libc::{close, ftruncate, mmap, munmap, shm_open, shm_unlink, PROT_READ};
-use libc::{MAP_FAILED, MAP_SHARED, O_CREAT, O_RDONLY, O_RDWR, PROT_WRITE, S_IRUSR, S_IWUSR};
-use log::{error, warn};
-use std::ffi::CString;
 use std::marker::PhantomData;
 
-#[derive(Debug)]
-enum ShmemHolderRole {
-    Owner,
-    Client,
-}
+pub use crate::membackend::rawshmem::{
+    CreateMode, RawShmem, ShmemHeader, ShmemOptions, HEADER_SIZE,
+};
 
 #[derive(Debug)]
-pub struct ShmemHolder<T> {
-    role: ShmemHolderRole,
-    fd: c_int,
-    name: String,
+pub struct ShmemHolder<T, A: Access = ReadWrite> {
+    raw: RawShmem<A>,
     data: *mut T,
     // For details, see:
     // https://github.com/rust-lang/rfcs/blob/master/text/0769-sound-generic-drop.md#phantom-data
@@ -45,142 +56,205 @@ pub struct ShmemHolder<T> {
     _marker: PhantomData<T>,
 }
 
-unsafe impl<T> Send for ShmemHolder<T> {}
+unsafe impl<T, A: Access> Send for ShmemHolder<T, A> {}
+
+impl ShmemOptions {
+    pub fn create<T: Zeroable>(&self, name: &str) -> Result<ShmemHolder<T>, GtsTransportError> {
+        ShmemHolder::create_with_options(&self.full_name(name), self)
+    }
+
+    pub fn connect_ro<T: Zeroable>(
+        &self,
+        name: &str,
+    ) -> Result<ShmemHolder<T, ReadOnly>, GtsTransportError> {
+        ShmemHolder::try_connect_ro(&self.full_name(name))
+    }
+
+    pub fn connect_rw<T: Zeroable>(&self, name: &str) -> Result<ShmemHolder<T>, GtsTransportError> {
+        ShmemHolder::try_connect_rw(&self.full_name(name))
+    }
+}
 
 impl<T: Zeroable> ShmemHolder<T> {
+    /// Creates segment with [`CreateMode::Replace`].
+    ///
+    /// # Panics
+    /// if segment can't be created, e.g. owner of segment with the same name is alive.
     pub fn create(name: &str) -> Self {
-        let (fd, data_ptr, length) = unsafe {
-            let name_cstr = CString::new(name).expect("no way!");
-            let null = std::ptr::null_mut();
-            let cname = name_cstr.as_ref().as_ptr();
-            // println!("create {} with size = {}", name, std::mem::size_of::<T>());
-            let res = shm_unlink(cname);
-            if res == 0 {
-                warn!(
-                    "shm_unlink {} is ok. last start crashed or name collision",
-                    name
-                );
-            }
-            let length = std::mem::size_of::<T>();
-
-            let fd = shm_open(cname, O_RDWR | O_CREAT, S_IRUSR | S_IWUSR);
-            assert_ne!(fd, -1, "shm_open {} failed", name);
-
-            let res = ftruncate(fd, length as off_t);
-            assert_eq!(
-                res,
-                0,
-                "truncate {} to {} failed",
-                name,
-                std::mem::size_of::<T>()
-            );
-
-            let addr = mmap(null, length, PROT_WRITE, MAP_SHARED, fd, 0);
-            assert_ne!(addr, MAP_FAILED, "mmap {} failed", name);
-
-            let data_ptr = addr as *mut T;
-            std::ptr::write_bytes(data_ptr, 0x0, 1);
-
-            (fd, data_ptr, length)
-        };
-        assert_eq!(length, Self::LENGTH);
-
-        ShmemHolder {
-            role: ShmemHolderRole::Owner,
-            fd,
-            name: name.to_string(),
-            // seqnum: 0,
-            data: data_ptr,
-            _marker: PhantomData,
-        }
+        Self::create_with_mode(name, CreateMode::Replace)
+            .unwrap_or_else(|err| panic!("create shmem {} failed: {}", name, err))
     }
-    pub fn connect_rw(name: &str) -> Self {
-        Self::connect_ext(name, true)
+
+    pub fn create_with_mode(name: &str, mode: CreateMode) -> Result<Self, GtsTransportError> {
+        ShmemOptions::new().create_mode(mode).create(name)
     }
 
-    pub fn connect_ro(name: &str) -> Self {
-        Self::connect_ext(name, false)
+    fn create_with_options(name: &str, options: &ShmemOptions) -> Result<Self, GtsTransportError> {
+        if std::mem::align_of::<T>() > HEADER_SIZE {
+            return Err(GtsTransportError::LogicError(format!(
+                "align {} of {} is bigger than shmem header",
+                std::mem::align_of::<T>(),
+                std::any::type_name::<T>()
+            )));
+        }
+        let raw = RawShmem::create_with_options(
+            name,
+            std::mem::size_of::<T>(),
+            fingerprint::<T>(),
+            options,
+        )?;
+        Ok(Self::from_raw(raw))
     }
 
-    pub fn connect_ext(name: &str, write_permission: bool) -> Self {
-        let (shmem_flag, mmap_flag) = if write_permission {
-            (O_RDWR, PROT_WRITE)
-        } else {
-            (O_RDONLY, PROT_READ)
-        };
+    /// # Panics
+    /// if segment doesn't exist or incompatible.
+    pub fn connect_rw(name: &str) -> Self {
+        Self::try_connect_rw(name)
+            .unwrap_or_else(|err| panic!("connect shmem {} failed: {}", name, err))
+    }
 
-        let (fd, data_ptr, length) = unsafe {
-            let name_cstr = CString::new(name).expect("no way!");
-            let null = std::ptr::null_mut();
-            let cname = name_cstr.as_ref().as_ptr();
+    pub fn try_connect_rw(name: &str) -> Result<Self, GtsTransportError> {
+        Self::try_connect(name)
+    }
 
-            let length = std::mem::size_of::<T>();
-            let fd = shm_open(cname, shmem_flag, S_IRUSR | S_IWUSR);
-            assert_ne!(fd, -1, "shm_open {} failed", name);
+    /// Maps segment read only, so holder is not [`MemHolderMut`].
+    ///
+    /// # Panics
+    /// if segment doesn't exist or incompatible.
+    pub fn connect_ro(name: &str) -> ShmemHolder<T, ReadOnly> {
+        Self::try_connect_ro(name)
+            .unwrap_or_else(|err| panic!("connect shmem {} failed: {}", name, err))
+    }
 
-            let addr = mmap(null, length, mmap_flag, MAP_SHARED, fd, 0);
-            assert_ne!(addr, MAP_FAILED, "mmap {} failed", name);
+    pub fn try_connect_ro(name: &str) -> Result<ShmemHolder<T, ReadOnly>, GtsTransportError> {
+        ShmemHolder::try_connect(name)
+    }
+}
 
-            let data_ptr = addr as *mut T;
+impl<T: Zeroable, A: Access> ShmemHolder<T, A> {
+    /// Connects to existing segment, returns `Unitialized` if owner has not finished creation,
+    /// `Incompatible` if segment was created for other T.
+    fn try_connect(name: &str) -> Result<Self, GtsTransportError> {
+        let raw = RawShmem::<A>::connect(name)?;
+        let header = raw.header();
+        if header.size != std::mem::size_of::<T>() as u64
+            || header.fingerprint != fingerprint::<T>()
+        {
+            return Err(GtsTransportError::Incompatible(name.to_string()));
+        }
+        Ok(Self::from_raw(raw))
+    }
 
-            (fd, data_ptr, length)
-        };
-        assert_eq!(length, Self::LENGTH);
+    /// Reconnects client to new segment, if owner replaced it (e.g. after restart).
+    /// Returns Ok(false) if segment is still the same.
+    pub fn reconnect(&mut self) -> Result<bool, GtsTransportError> {
+        if !self.raw.is_replaced()? {
+            return Ok(false);
+        }
+        *self = Self::try_connect(self.raw.name())?;
+        Ok(true)
+    }
 
-        ShmemHolder {
-            role: ShmemHolderRole::Client,
-            fd,
-            name: name.to_string(),
-            data: data_ptr,
+    fn from_raw(raw: RawShmem<A>) -> Self {
+        // never written through, if segment is mapped read only.
+        let data = raw.get_ptr() as *mut T;
+        Self {
+            raw,
+            data,
             _marker: PhantomData,
         }
     }
 }
 
-impl<T> Drop for ShmemHolder<T> {
-    fn drop(&mut self) {
-        // println!(
-        //     "Drop ShmemHolder {}/{:?}/{} {:p}@{} ",
-        //     self.fd,
-        //     self.role,
-        //     self.name,
-        //     self.data,
-        //     Self::LENGTH
-        // );
-
-        // NOTE: update docs & examples. drop of T is never called.
-        // std::ptr::drop_in_place(self.data);
-
-        let rname: &str = &self.name;
-        unsafe {
-            let name_cstr = CString::new(rname).expect("no way!");
-            let cname = name_cstr.as_ref().as_ptr();
-
-            let ret = munmap(self.data as *mut c_void, Self::LENGTH);
-            if ret != 0 {
-                error!("ShmemSender UNMAP OF {:p} -> {}", self.data, ret);
-            }
-
-            let ret = close(self.fd);
-            if ret != 0 {
-                error!("ShmemSender close err  OF {} -> {}", self.fd, ret);
-            }
-
-            if matches!(self.role, ShmemHolderRole::Owner) {
-                let ret = shm_unlink(cname);
-                if ret != 0 {
-                    error!("ShmemSender shm_unlink err  OF {} -> {}", self.name, ret);
-                }
-            }
-        }
+impl<T, A: Access> ShmemHolder<T, A> {
+    pub fn name(&self) -> &str {
+        self.raw.name()
+    }
+
+    pub fn header(&self) -> ShmemHeader {
+        self.raw.header()
+    }
+
+    /// False if owner of segment exited (or crashed), or replaced segment by new one.
+    pub fn owner_alive(&self) -> bool {
+        self.raw.owner_alive()
     }
 }
 
-impl<T> MemHolder<T> for ShmemHolder<T> {
+// NOTE: update docs & examples. drop of T is never called, RawShmem just unmaps segment.
+
+impl<T, A: Access> MemHolder<T> for ShmemHolder<T, A> {
+    fn get_ptr(&self) -> *const T {
+        self.data as *const T
+    }
+}
+
+impl<T> MemHolderMut<T> for ShmemHolder<T, ReadWrite> {
     fn get_mut_ptr(&self) -> *mut T {
         self.data
     }
-    fn get_ptr(&self) -> *const T {
-        self.data as *const T
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membackend::rawshmem::tests::crash;
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_incompatible_type() {
+        let name = "test_shmem_incompatible";
+        let owner = ShmemHolder::<u64>::create(name);
+        assert!(matches!(
+            ShmemHolder::<u32>::try_connect_ro(name),
+            Err(GtsTransportError::Incompatible(_))
+        ));
+        // the same size, other type.
+        assert!(matches!(
+            ShmemHolder::<i64>::try_connect_ro(name),
+            Err(GtsTransportError::Incompatible(_))
+        ));
+        crash(owner.raw);
+
+        let res = ShmemHolder::<i64>::create_with_mode(name, CreateMode::ReuseIfCompatible);
+        assert!(matches!(res, Err(GtsTransportError::Incompatible(_))));
+        let owner = ShmemHolder::<u64>::create_with_mode(name, CreateMode::ReuseIfCompatible);
+        assert!(owner.is_ok());
+    }
+
+    #[test]
+    fn test_overaligned_type() {
+        #[repr(align(8192))]
+        struct Aligned;
+
+        // SAFETY: no fields.
+        unsafe impl Zeroable for Aligned {}
+
+        let res =
+            ShmemHolder::<Aligned>::create_with_mode("test_shmem_aligned", CreateMode::Replace);
+        assert!(matches!(res, Err(GtsTransportError::LogicError(_))));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_reconnect() {
+        let name = "test_shmem_reconnect";
+        let owner = ShmemHolder::<u64>::create(name);
+        unsafe { *owner.get_mut_ptr() = 1 };
+
+        let mut client = ShmemHolder::<u64>::try_connect_ro(name).unwrap();
+        assert!(client.owner_alive());
+        assert!(!client.reconnect().unwrap());
+        crash(owner.raw);
+        assert!(!client.owner_alive());
+
+        let owner = ShmemHolder::<u64>::create(name);
+        unsafe { *owner.get_mut_ptr() = 2 };
+        assert_eq!(unsafe { *client.get_ptr() }, 1);
+        assert!(client.reconnect().unwrap());
+        assert!(client.owner_alive());
+        assert_eq!(unsafe { *client.get_ptr() }, 2);
+        assert!(!client.reconnect().unwrap());
+        drop(owner);
     }
 }
@@ -0,0 +1,424 @@
+//! Recording and replay of channel traffic.
+//! [`RecordingTap`] wraps any [`ChannelSource`] and writes every polled value with
+//! `minstant` unix nanos timestamp to a binary file, [`Replayer`] reads it back and
+//! republishes values to any [`ChannelSink`] with original pacing, accelerated or
+//! as fast as possible.
+//!
+//! File: header `{magic: [u8; 8], version: u16, reserved: u16, len: u32, fingerprint: u64,
+//! created: u64}` (little-endian), followed by records `{timestamp: u64, value: [u8; len]}`.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::channel::ChannelSource;
+//! use gts_transport::membackend::memchunk::MemChunkHolder;
+//! use gts_transport::record::{Pacing, RecordingTap, Replayer};
+//! use gts_transport::sync::lfringspsc::spsc_ring_pair;
+//!
+//! let (mut tx, rx) = spsc_ring_pair::<8, u64, _>(MemChunkHolder::zeroed());
+//! let mut tap = RecordingTap::new(rx, Vec::new()).unwrap();
+//! tx.send(&7).unwrap();
+//! assert_eq!(tap.poll().unwrap(), 7);
+//!
+//! let (_, recorder) = tap.into_inner();
+//! let file = recorder.into_inner().unwrap();
+//!
+//! let (mut replay_tx, mut replay_rx) = spsc_ring_pair::<8, u64, _>(MemChunkHolder::zeroed());
+//! let mut replayer = Replayer::<u64, _>::new(file.as_slice()).unwrap();
+//! assert_eq!(replayer.replay_into(&mut replay_tx, Pacing::AsFastAsPossible).unwrap(), 1);
+//! assert_eq!(*replay_rx.try_recv().unwrap(), 7);
+//! ```
+
+use crate::channel::{ChannelSink, ChannelSource};
+use crate::error::GtsTransportError;
+use crate::wire::{fingerprint, read_value, write_value};
+use bytemuck::{CheckedBitPattern, NoUninit};
+use std::io::{ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+pub const RECORD_MAGIC: [u8; 8] = *b"GTSREC01";
+pub const RECORD_VERSION: u16 = 1;
+pub const RECORD_HEADER_SIZE: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecordHeader {
+    pub len: u32,
+    pub fingerprint: u64,
+    /// unix nanos of recorder creation.
+    pub created: u64,
+}
+
+impl RecordHeader {
+    pub fn encode(&self) -> [u8; RECORD_HEADER_SIZE] {
+        let mut dst = [0u8; RECORD_HEADER_SIZE];
+        dst[0..8].copy_from_slice(&RECORD_MAGIC);
+        dst[8..10].copy_from_slice(&RECORD_VERSION.to_le_bytes());
+        dst[12..16].copy_from_slice(&self.len.to_le_bytes());
+        dst[16..24].copy_from_slice(&self.fingerprint.to_le_bytes());
+        dst[24..32].copy_from_slice(&self.created.to_le_bytes());
+        dst
+    }
+
+    pub fn decode(src: &[u8; RECORD_HEADER_SIZE]) -> Result<Self, GtsTransportError> {
+        let version = u16::from_le_bytes(src[8..10].try_into().unwrap());
+        if src[0..8] != RECORD_MAGIC || version != RECORD_VERSION {
+            return Err(GtsTransportError::LogicError(format!(
+                "bad record file magic / version {}",
+                version
+            )));
+        }
+        Ok(RecordHeader {
+            len: u32::from_le_bytes(src[12..16].try_into().unwrap()),
+            fingerprint: u64::from_le_bytes(src[16..24].try_into().unwrap()),
+            created: u64::from_le_bytes(src[24..32].try_into().unwrap()),
+        })
+    }
+}
+
+/// Writes values of T, which must have no padding (see [`write_value`]).
+pub struct Recorder<T: NoUninit, W: Write> {
+    dest: W,
+    anc: minstant::Anchor,
+    buf: Vec<u8>,
+    counter: u64,
+    _owns_t: PhantomData<T>,
+}
+
+impl<T: NoUninit, W: Write> Recorder<T, W> {
+    /// Writes file header to dest.
+    pub fn new(mut dest: W) -> Result<Self, GtsTransportError> {
+        let anc = minstant::Anchor::new();
+        let header = RecordHeader {
+            len: std::mem::size_of::<T>() as u32,
+            fingerprint: fingerprint::<T>(),
+            created: minstant::Instant::now().as_unix_nanos(&anc),
+        };
+        dest.write_all(&header.encode())?;
+        Ok(Self {
+            dest,
+            anc,
+            buf: vec![0; 8 + std::mem::size_of::<T>()],
+            counter: 0,
+            _owns_t: PhantomData,
+        })
+    }
+
+    /// Records value with current timestamp.
+    pub fn record(&mut self, value: &T) -> Result<(), GtsTransportError> {
+        let timestamp = minstant::Instant::now().as_unix_nanos(&self.anc);
+        self.record_at(timestamp, value)
+    }
+
+    pub fn record_at(&mut self, timestamp: u64, value: &T) -> Result<(), GtsTransportError> {
+        self.buf[0..8].copy_from_slice(&timestamp.to_le_bytes());
+        write_value(value, &mut self.buf[8..]);
+        self.dest.write_all(&self.buf)?;
+        self.counter += 1;
+        Ok(())
+    }
+
+    /// Number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.counter
+    }
+
+    pub fn flush(&mut self) -> Result<(), GtsTransportError> {
+        self.dest.flush()?;
+        Ok(())
+    }
+
+    /// Flushes and returns dest.
+    pub fn into_inner(mut self) -> Result<W, GtsTransportError> {
+        self.flush()?;
+        Ok(self.dest)
+    }
+}
+
+/// Source which records every value it returns.
+pub struct RecordingTap<T: NoUninit, S: ChannelSource<T>, W: Write> {
+    source: S,
+    recorder: Recorder<T, W>,
+}
+
+impl<T: NoUninit, S: ChannelSource<T>, W: Write> RecordingTap<T, S, W> {
+    pub fn new(source: S, dest: W) -> Result<Self, GtsTransportError> {
+        Ok(Self {
+            source,
+            recorder: Recorder::new(dest)?,
+        })
+    }
+
+    pub fn recorder(&mut self) -> &mut Recorder<T, W> {
+        &mut self.recorder
+    }
+
+    pub fn into_inner(self) -> (S, Recorder<T, W>) {
+        (self.source, self.recorder)
+    }
+}
+
+impl<T: NoUninit, S: ChannelSource<T>, W: Write> ChannelSource<T> for RecordingTap<T, S, W> {
+    fn poll(&mut self) -> Result<T, GtsTransportError> {
+        let value = self.source.poll()?;
+        self.recorder.record(&value)?;
+        Ok(value)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pacing {
+    /// Keep original intervals between values.
+    Original,
+    /// Intervals are divided by speed, which must be finite and positive.
+    Accelerated(f64),
+    AsFastAsPossible,
+}
+
+/// Reads values of T, file is not trusted and values are checked (see [`read_value`]).
+pub struct Replayer<T: CheckedBitPattern, R: Read> {
+    src: R,
+    header: RecordHeader,
+    buf: Vec<u8>,
+    _owns_t: PhantomData<T>,
+}
+
+impl<T: CheckedBitPattern, R: Read> Replayer<T, R> {
+    /// Reads and checks file header.
+    pub fn new(mut src: R) -> Result<Self, GtsTransportError> {
+        let mut raw_header = [0u8; RECORD_HEADER_SIZE];
+        src.read_exact(&mut raw_header)?;
+        let header = RecordHeader::decode(&raw_header)?;
+        if header.fingerprint != fingerprint::<T>()
+            || header.len as usize != std::mem::size_of::<T>()
+        {
+            return Err(GtsTransportError::LogicError(format!(
+                "record fingerprint {:x}/{} doesn't match {:x}/{}",
+                header.fingerprint,
+                header.len,
+                fingerprint::<T>(),
+                std::mem::size_of::<T>()
+            )));
+        }
+        Ok(Self {
+            src,
+            header,
+            buf: vec![0; 8 + std::mem::size_of::<T>()],
+            _owns_t: PhantomData,
+        })
+    }
+
+    pub fn header(&self) -> &RecordHeader {
+        &self.header
+    }
+
+    /// Returns next (timestamp, value) or None at the end of file.
+    /// Record truncated by the end of file (e.g. crash of recorder) or invalid value
+    /// is DecodeError.
+    pub fn next_record(&mut self) -> Result<Option<(u64, T)>, GtsTransportError> {
+        let mut filled = 0;
+        while filled < self.buf.len() {
+            match self.src.read(&mut self.buf[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(GtsTransportError::DecodeError(format!(
+                        "truncated record, {} of {} bytes",
+                        filled,
+                        self.buf.len()
+                    )))
+                }
+                Ok(len) => filled += len,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let timestamp = u64::from_le_bytes(self.buf[0..8].try_into().unwrap());
+        let value = read_value(&self.buf[8..])?;
+        Ok(Some((timestamp, value)))
+    }
+
+    /// Pushes all remaining values to sink, returns number of replayed values.
+    /// Sink is retried while it returns WouldBlock.
+    /// Returns LogicError for bad speed of Accelerated pacing.
+    pub fn replay_into(
+        &mut self,
+        sink: &mut impl ChannelSink<T>,
+        pacing: Pacing,
+    ) -> Result<u64, GtsTransportError> {
+        let speed = match pacing {
+            Pacing::Original => Some(1.0),
+            Pacing::Accelerated(speed) if speed.is_finite() && speed > 0.0 => Some(speed),
+            Pacing::Accelerated(speed) => {
+                return Err(GtsTransportError::LogicError(format!(
+                    "bad replay speed {}",
+                    speed
+                )))
+            }
+            Pacing::AsFastAsPossible => None,
+        };
+        let start = minstant::Instant::now();
+        let mut first_ts = None;
+        let mut counter = 0;
+
+        while let Some((timestamp, value)) = self.next_record()? {
+            if let Some(speed) = speed {
+                let first_ts = *first_ts.get_or_insert(timestamp);
+                let offset = timestamp.saturating_sub(first_ts) as f64 / speed;
+                let target = Duration::from_nanos(offset as u64);
+                loop {
+                    let elapsed = start.elapsed();
+                    if elapsed >= target {
+                        break;
+                    }
+                    let left = target - elapsed;
+                    if left > Duration::from_millis(1) {
+                        std::thread::sleep(left - Duration::from_millis(1));
+                    } else {
+                        std::hint::spin_loop();
+                    }
+                }
+            }
+            loop {
+                match sink.push(&value) {
+                    Ok(()) => break,
+                    Err(GtsTransportError::WouldBlock) => std::hint::spin_loop(),
+                    Err(err) => return Err(err),
+                }
+            }
+            counter += 1;
+        }
+        Ok(counter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membackend::memchunk::MemChunkHolder;
+    use crate::sync::lfringspsc::spsc_ring_pair;
+    use crate::sync::lfspmc::spmc_pair;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Default)]
+    struct TestData {
+        timestamp: u64,
+        qty: u64,
+    }
+
+    // SAFETY: no padding, any bytes are valid.
+    unsafe impl bytemuck::Zeroable for TestData {}
+    unsafe impl bytemuck::Pod for TestData {}
+
+    fn make_file(intervals_ms: &[u64]) -> Vec<u8> {
+        let mut recorder = Recorder::<TestData, _>::new(Vec::new()).unwrap();
+        let mut ts = 1_000_000_000;
+        for (i, interval) in intervals_ms.iter().enumerate() {
+            ts += interval * 1_000_000;
+            let value = TestData {
+                timestamp: ts,
+                qty: i as u64,
+            };
+            recorder.record_at(ts, &value).unwrap();
+        }
+        assert_eq!(recorder.count(), intervals_ms.len() as u64);
+        recorder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_tap() {
+        let (mut tx, rx) = spmc_pair::<TestData, _>(MemChunkHolder::zeroed());
+        let mut tap = RecordingTap::new(rx, Vec::new()).unwrap();
+        assert!(matches!(tap.poll(), Err(GtsTransportError::WouldBlock)));
+        for i in 0..3 {
+            tx.send(&TestData {
+                timestamp: i,
+                qty: 10,
+            })
+            .unwrap();
+            assert_eq!(tap.poll().unwrap().timestamp, i);
+        }
+        assert!(matches!(tap.poll(), Err(GtsTransportError::WouldBlock)));
+
+        let (_, recorder) = tap.into_inner();
+        let file = recorder.into_inner().unwrap();
+        assert_eq!(
+            file.len(),
+            RECORD_HEADER_SIZE + 3 * (8 + std::mem::size_of::<TestData>())
+        );
+
+        let mut replayer = Replayer::<TestData, _>::new(file.as_slice()).unwrap();
+        assert_eq!(
+            replayer.header().len as usize,
+            std::mem::size_of::<TestData>()
+        );
+        let mut last_ts = 0;
+        for i in 0..3 {
+            let (ts, value) = replayer.next_record().unwrap().unwrap();
+            assert!(ts >= last_ts);
+            last_ts = ts;
+            assert_eq!(value.timestamp, i);
+        }
+        assert!(replayer.next_record().unwrap().is_none());
+
+        assert!(Replayer::<u64, _>::new(file.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_pacing() {
+        let file = make_file(&[0, 20, 20]);
+
+        let (mut tx, mut rx) = spsc_ring_pair::<8, TestData, _>(MemChunkHolder::zeroed());
+        let start = std::time::Instant::now();
+        let mut replayer = Replayer::<TestData, _>::new(file.as_slice()).unwrap();
+        assert_eq!(replayer.replay_into(&mut tx, Pacing::Original).unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        for i in 0..3 {
+            assert_eq!(rx.try_recv().unwrap().qty, i);
+        }
+
+        let start = std::time::Instant::now();
+        let mut replayer = Replayer::<TestData, _>::new(file.as_slice()).unwrap();
+        let res = replayer.replay_into(&mut tx, Pacing::Accelerated(4.0));
+        assert_eq!(res.unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        for i in 0..3 {
+            assert_eq!(rx.try_recv().unwrap().qty, i);
+        }
+
+        let file = make_file(&[0, 60_000]);
+        let start = std::time::Instant::now();
+        let mut replayer = Replayer::<TestData, _>::new(file.as_slice()).unwrap();
+        let res = replayer.replay_into(&mut tx, Pacing::AsFastAsPossible);
+        assert_eq!(res.unwrap(), 2);
+        assert!(start.elapsed() < Duration::from_secs(60));
+
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let mut replayer = Replayer::<TestData, _>::new(file.as_slice()).unwrap();
+            let res = replayer.replay_into(&mut tx, Pacing::Accelerated(speed));
+            assert!(matches!(res, Err(GtsTransportError::LogicError(_))));
+        }
+    }
+
+    #[test]
+    fn test_bad_records() {
+        let file = make_file(&[0, 20]);
+        let mut replayer = Replayer::<TestData, _>::new(&file[..RECORD_HEADER_SIZE]).unwrap();
+        assert!(replayer.next_record().unwrap().is_none());
+
+        let mut replayer = Replayer::<TestData, _>::new(&file[..file.len() - 1]).unwrap();
+        assert!(replayer.next_record().unwrap().is_some());
+        assert!(matches!(
+            replayer.next_record(),
+            Err(GtsTransportError::DecodeError(_))
+        ));
+
+        let mut recorder = Recorder::<bool, _>::new(Vec::new()).unwrap();
+        recorder.record_at(1, &true).unwrap();
+        let mut file = recorder.into_inner().unwrap();
+        *file.last_mut().unwrap() = 2;
+        let mut replayer = Replayer::<bool, _>::new(file.as_slice()).unwrap();
+        assert!(matches!(
+            replayer.next_record(),
+            Err(GtsTransportError::DecodeError(_))
+        ));
+    }
+}
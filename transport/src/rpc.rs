@@ -0,0 +1,412 @@
+//! Request/response over two [`crate::sync::lfringspsc`] rings.
+//! Client sends [`RpcFrame`] with new correlation id to request ring,
+//! server replies with the same correlation id to response ring.
+//! Responses with unknown correlation id (e.g. for timed out calls) are dropped by client.
+//!
+//! Works both inside one process ([`crate::membackend::memchunk::MemChunkHolder`])
+//! and across processes ([`ShmemRpcServer::create`] / [`ShmemRpcClient::connect`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::membackend::memchunk::MemChunkHolder;
+//! use gts_transport::rpc::rpc_pair;
+//! use std::time::Duration;
+//!
+//! let (mut client, mut server) =
+//!     rpc_pair::<16, u64, u64, _, _>(MemChunkHolder::zeroed(), MemChunkHolder::zeroed());
+//!
+//! let corr_id = client.send_request(&20).unwrap();
+//! server.try_serve_one(|req| req + 1).unwrap();
+//! assert_eq!(client.wait_response(corr_id, Duration::from_millis(10)).unwrap(), 21);
+//! ```
+
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::MemHolderMut;
+use crate::membackend::shmem::ShmemHolder;
+use crate::sync::lfringspsc::{SpScRingData, SpScRingReceiver, SpScRingSender};
+use log::debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RpcFrame<T: Copy> {
+    pub corr_id: u64,
+    pub payload: T,
+}
+
+pub type RpcRingData<const RSIZE: usize, T> = SpScRingData<RSIZE, RpcFrame<T>>;
+
+pub struct RpcClient<const RSIZE: usize, Req, Resp, ReqBackT, RespBackT>
+where
+    Req: Copy,
+    Resp: Copy,
+    ReqBackT: MemHolderMut<RpcRingData<RSIZE, Req>>,
+    RespBackT: MemHolderMut<RpcRingData<RSIZE, Resp>>,
+{
+    req_tx: SpScRingSender<RSIZE, RpcFrame<Req>, ReqBackT>,
+    resp_rx: SpScRingReceiver<RSIZE, RpcFrame<Resp>, RespBackT>,
+    last_corr_id: u64,
+}
+
+impl<const RSIZE: usize, Req, Resp, ReqBackT, RespBackT>
+    RpcClient<RSIZE, Req, Resp, ReqBackT, RespBackT>
+where
+    Req: Copy,
+    Resp: Copy,
+    ReqBackT: MemHolderMut<RpcRingData<RSIZE, Req>>,
+    RespBackT: MemHolderMut<RpcRingData<RSIZE, Resp>>,
+{
+    pub fn new(req_backend: ReqBackT, resp_backend: RespBackT) -> Self {
+        Self {
+            req_tx: SpScRingSender::new(req_backend),
+            resp_rx: SpScRingReceiver::new(resp_backend),
+            last_corr_id: 0,
+        }
+    }
+
+    /// Sends request without waiting, returns its correlation id.
+    pub fn send_request(&mut self, req: &Req) -> Result<u64, GtsTransportError> {
+        let corr_id = self.last_corr_id + 1;
+        self.req_tx.send(&RpcFrame {
+            corr_id,
+            payload: *req,
+        })?;
+        self.last_corr_id = corr_id;
+        Ok(corr_id)
+    }
+
+    /// Returns next response with its correlation id.
+    pub fn try_recv_response(&mut self) -> Result<(u64, Resp), GtsTransportError> {
+        let frame = self.resp_rx.try_recv()?;
+        Ok((frame.corr_id, frame.payload))
+    }
+
+    /// Spins until response for corr_id, responses for other ids are dropped.
+    pub fn wait_response(
+        &mut self,
+        corr_id: u64,
+        timeout: Duration,
+    ) -> Result<Resp, GtsTransportError> {
+        let start = minstant::Instant::now();
+        loop {
+            match self.resp_rx.try_recv() {
+                Ok(frame) if frame.corr_id == corr_id => return Ok(frame.payload),
+                Ok(frame) => {
+                    debug!(
+                        "rpc drop stale response {} while waiting {}",
+                        frame.corr_id, corr_id
+                    );
+                    continue;
+                }
+                Err(GtsTransportError::WouldBlock) => {}
+                Err(err) => return Err(err),
+            }
+            if start.elapsed() > timeout {
+                return Err(GtsTransportError::Timeout);
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    /// Sends request and spins until response or timeout.
+    pub fn call(&mut self, req: &Req, timeout: Duration) -> Result<Resp, GtsTransportError> {
+        let start = minstant::Instant::now();
+        let corr_id = loop {
+            match self.send_request(req) {
+                Ok(corr_id) => break corr_id,
+                Err(GtsTransportError::WouldBlock) => {}
+                Err(err) => return Err(err),
+            }
+            if start.elapsed() > timeout {
+                return Err(GtsTransportError::Timeout);
+            }
+            std::hint::spin_loop();
+        };
+        self.wait_response(corr_id, timeout.saturating_sub(start.elapsed()))
+    }
+}
+
+/// Outcome of [`RpcServer::try_serve_one`], with correlation id.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Served {
+    /// Request is handled and response is sent.
+    Sent(u64),
+    /// Request is handled, but response ring is full, response is kept.
+    Deferred(u64),
+    /// Kept response is sent, no new request.
+    Resent(u64),
+}
+
+pub struct RpcServer<const RSIZE: usize, Req, Resp, ReqBackT, RespBackT>
+where
+    Req: Copy,
+    Resp: Copy,
+    ReqBackT: MemHolderMut<RpcRingData<RSIZE, Req>>,
+    RespBackT: MemHolderMut<RpcRingData<RSIZE, Resp>>,
+{
+    req_rx: SpScRingReceiver<RSIZE, RpcFrame<Req>, ReqBackT>,
+    resp_tx: SpScRingSender<RSIZE, RpcFrame<Resp>, RespBackT>,
+    pending: Option<RpcFrame<Resp>>,
+}
+
+impl<const RSIZE: usize, Req, Resp, ReqBackT, RespBackT>
+    RpcServer<RSIZE, Req, Resp, ReqBackT, RespBackT>
+where
+    Req: Copy,
+    Resp: Copy,
+    ReqBackT: MemHolderMut<RpcRingData<RSIZE, Req>>,
+    RespBackT: MemHolderMut<RpcRingData<RSIZE, Resp>>,
+{
+    pub fn new(req_backend: ReqBackT, resp_backend: RespBackT) -> Self {
+        Self {
+            req_rx: SpScRingReceiver::new(req_backend),
+            resp_tx: SpScRingSender::new(resp_backend),
+            pending: None,
+        }
+    }
+
+    /// Handles one request.
+    /// If response ring is full, response is kept and resent by next call,
+    /// no new requests are read until then.
+    /// Returns WouldBlock if there is no request and kept response (if any) is not sent.
+    pub fn try_serve_one(
+        &mut self,
+        handler: impl FnOnce(&Req) -> Resp,
+    ) -> Result<Served, GtsTransportError> {
+        let mut resent = None;
+        if let Some(frame) = self.pending {
+            self.resp_tx.send(&frame)?;
+            self.pending = None;
+            resent = Some(frame.corr_id);
+        }
+
+        let req = match (self.req_rx.try_recv(), resent) {
+            (Ok(req), _) => *req,
+            (Err(GtsTransportError::WouldBlock), Some(corr_id)) => {
+                return Ok(Served::Resent(corr_id))
+            }
+            (Err(err), _) => return Err(err),
+        };
+        let frame = RpcFrame {
+            corr_id: req.corr_id,
+            payload: handler(&req.payload),
+        };
+        match self.resp_tx.send(&frame) {
+            Ok(()) => Ok(Served::Sent(req.corr_id)),
+            Err(GtsTransportError::WouldBlock) => {
+                self.pending = Some(frame);
+                Ok(Served::Deferred(req.corr_id))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Serves requests while `running` is set, returns number of handled requests
+    /// (including ones with deferred response).
+    pub fn run(
+        &mut self,
+        running: &AtomicBool,
+        mut handler: impl FnMut(&Req) -> Resp,
+    ) -> Result<u64, GtsTransportError> {
+        let mut counter = 0;
+        while running.load(Ordering::Relaxed) {
+            match self.try_serve_one(&mut handler) {
+                Ok(Served::Sent(_)) | Ok(Served::Deferred(_)) => counter += 1,
+                Ok(Served::Resent(_)) => {}
+                Err(GtsTransportError::WouldBlock) => std::hint::spin_loop(),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(counter)
+    }
+}
+
+pub type ShmemRpcClient<const RSIZE: usize, Req, Resp> = RpcClient<
+    RSIZE,
+    Req,
+    Resp,
+    ShmemHolder<RpcRingData<RSIZE, Req>>,
+    ShmemHolder<RpcRingData<RSIZE, Resp>>,
+>;
+
+pub type ShmemRpcServer<const RSIZE: usize, Req, Resp> = RpcServer<
+    RSIZE,
+    Req,
+    Resp,
+    ShmemHolder<RpcRingData<RSIZE, Req>>,
+    ShmemHolder<RpcRingData<RSIZE, Resp>>,
+>;
+
+fn req_name(name: &str) -> String {
+    format!("{}_req", name)
+}
+
+fn resp_name(name: &str) -> String {
+    format!("{}_resp", name)
+}
+
+impl<const RSIZE: usize, Req: Copy, Resp: Copy> ShmemRpcServer<RSIZE, Req, Resp> {
+    /// Creates `{name}_req` and `{name}_resp` shared mem chunks.
+    pub fn create(name: &str) -> Self {
+        Self::new(
+            ShmemHolder::create(&req_name(name)),
+            ShmemHolder::create(&resp_name(name)),
+        )
+    }
+}
+
+impl<const RSIZE: usize, Req: Copy, Resp: Copy> ShmemRpcClient<RSIZE, Req, Resp> {
+    /// Connects to chunks created by [`ShmemRpcServer::create`].
+    pub fn connect(name: &str) -> Self {
+        Self::new(
+            ShmemHolder::connect_rw(&req_name(name)),
+            ShmemHolder::connect_rw(&resp_name(name)),
+        )
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn rpc_pair<const RSIZE: usize, Req, Resp, ReqBackT, RespBackT>(
+    req_backend: ReqBackT,
+    resp_backend: RespBackT,
+) -> (
+    RpcClient<RSIZE, Req, Resp, ReqBackT, RespBackT>,
+    RpcServer<RSIZE, Req, Resp, ReqBackT, RespBackT>,
+)
+where
+    Req: Copy,
+    Resp: Copy,
+    ReqBackT: Clone + MemHolderMut<RpcRingData<RSIZE, Req>>,
+    RespBackT: Clone + MemHolderMut<RpcRingData<RSIZE, Resp>>,
+{
+    (
+        RpcClient::new(req_backend.clone(), resp_backend.clone()),
+        RpcServer::new(req_backend, resp_backend),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membackend::memchunk::MemChunkHolder;
+    use std::sync::Arc;
+
+    #[derive(Copy, Clone, Debug)]
+    struct Request {
+        a: u64,
+        b: u64,
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Response {
+        Sum(u64),
+        Overflow,
+    }
+
+    fn handle(req: &Request) -> Response {
+        match req.a.checked_add(req.b) {
+            Some(sum) => Response::Sum(sum),
+            None => Response::Overflow,
+        }
+    }
+
+    #[test]
+    fn test_call_threads() {
+        let (mut client, mut server) = rpc_pair::<4, Request, Response, _, _>(
+            MemChunkHolder::zeroed(),
+            MemChunkHolder::zeroed(),
+        );
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+        let server = std::thread::spawn(move || server.run(&running_clone, handle).unwrap());
+
+        for i in 0..100 {
+            let res = client.call(&Request { a: i, b: 1 }, Duration::from_secs(5));
+            assert_eq!(res.unwrap(), Response::Sum(i + 1));
+        }
+        let res = client.call(&Request { a: u64::MAX, b: 1 }, Duration::from_secs(5));
+        assert_eq!(res.unwrap(), Response::Overflow);
+
+        running.store(false, Ordering::Relaxed);
+        assert_eq!(server.join().unwrap(), 101);
+    }
+
+    #[test]
+    fn test_timeout_and_stale() {
+        let (mut client, mut server) = rpc_pair::<4, Request, Response, _, _>(
+            MemChunkHolder::zeroed(),
+            MemChunkHolder::zeroed(),
+        );
+
+        let res = client.call(&Request { a: 1, b: 1 }, Duration::from_millis(1));
+        assert!(matches!(res, Err(GtsTransportError::Timeout)));
+
+        // late response for timed out call is dropped.
+        assert_eq!(server.try_serve_one(handle).unwrap(), Served::Sent(1));
+        let corr_id = client.send_request(&Request { a: 2, b: 2 }).unwrap();
+        assert_eq!(corr_id, 2);
+        assert_eq!(server.try_serve_one(handle).unwrap(), Served::Sent(2));
+        let res = client.wait_response(corr_id, Duration::from_millis(10));
+        assert_eq!(res.unwrap(), Response::Sum(4));
+
+        assert!(matches!(
+            server.try_serve_one(handle),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        assert!(matches!(
+            client.try_recv_response(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+    }
+
+    #[test]
+    fn test_pending_response() {
+        let (mut client, mut server) = rpc_pair::<3, Request, Response, _, _>(
+            MemChunkHolder::zeroed(),
+            MemChunkHolder::zeroed(),
+        );
+        // ring of 3 holds 2 frames.
+        for i in 0..2 {
+            client.send_request(&Request { a: i, b: 0 }).unwrap();
+        }
+        assert_eq!(server.try_serve_one(handle).unwrap(), Served::Sent(1));
+        assert_eq!(server.try_serve_one(handle).unwrap(), Served::Sent(2));
+        client.send_request(&Request { a: 2, b: 0 }).unwrap();
+        // response ring is full, response is kept.
+        assert_eq!(server.try_serve_one(handle).unwrap(), Served::Deferred(3));
+        assert!(matches!(
+            server.try_serve_one(handle),
+            Err(GtsTransportError::WouldBlock)
+        ));
+
+        assert_eq!(client.try_recv_response().unwrap(), (1, Response::Sum(0)));
+        assert_eq!(server.try_serve_one(handle).unwrap(), Served::Resent(3));
+        assert!(matches!(
+            server.try_serve_one(handle),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        assert_eq!(client.try_recv_response().unwrap(), (2, Response::Sum(1)));
+        assert_eq!(client.try_recv_response().unwrap(), (3, Response::Sum(2)));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_shmem() {
+        let name = "testrpcshmem";
+        let mut server = ShmemRpcServer::<8, Request, Response>::create(name);
+        let mut client = ShmemRpcClient::<8, Request, Response>::connect(name);
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+        let server = std::thread::spawn(move || server.run(&running_clone, handle).unwrap());
+
+        for i in 0..10 {
+            let res = client.call(&Request { a: i, b: i }, Duration::from_secs(5));
+            assert_eq!(res.unwrap(), Response::Sum(2 * i));
+        }
+
+        running.store(false, Ordering::Relaxed);
+        assert_eq!(server.join().unwrap(), 10);
+    }
+}
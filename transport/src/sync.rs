@@ -1,2 +1,10 @@
+pub mod atomic;
+pub mod envelope;
+pub mod lfbytespsc;
+pub mod lfdynspmc;
+pub mod lfdynspsc;
 pub mod lfringspsc;
+pub mod lfsplitspsc;
 pub mod lfspmc;
+pub mod racycopy;
+pub mod received;
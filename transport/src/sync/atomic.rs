@@ -0,0 +1,11 @@
+//! Atomics used by lock free primitives.
+//! Built with `RUSTFLAGS="--cfg loom"` they are replaced by [loom](https://docs.rs/loom)
+//! ones, so orderings could be model checked (see tests/loom.rs).
+//! Loom atomics can't live in shared mem chunk, under loom primitives must be
+//! created with `Default` backends (e.g. `MemChunkHolder::default()`), not zeroed ones.
+
+#[cfg(loom)]
+pub use loom::sync::atomic::{fence, AtomicU32, Ordering};
+
+#[cfg(not(loom))]
+pub use std::sync::atomic::{fence, AtomicU32, Ordering};
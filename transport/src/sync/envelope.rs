@@ -0,0 +1,351 @@
+//! Typed envelopes on top of [`crate::sync::lfbytespsc`] byte ring.
+//! Many message kinds could be sent through one ring, each one is written with
+//! its tag and exactly `size_of::<M>()` bytes, receiver dispatches messages to
+//! handlers registered per type.
+//!
+//! Message types are registered by implementing [`Message`], usually via
+//! [`impl_message!`](crate::impl_message), which is unsafe as the trait.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::impl_message;
+//! use gts_transport::membackend::memchunk::MemChunkHolder;
+//! use gts_transport::sync::envelope::{envelope_pair, Dispatcher};
+//!
+//! #[derive(Copy, Clone, Debug)]
+//! struct Quote {
+//!     bid: u64,
+//!     ask: u64,
+//! }
+//! #[derive(Copy, Clone, Debug)]
+//! struct Heartbeat;
+//!
+//! // SAFETY: tags are unique in channel, sender and receiver share definitions.
+//! impl_message!(unsafe Quote, 1);
+//! impl_message!(unsafe Heartbeat, 2);
+//!
+//! let (mut tx, mut rx) = envelope_pair::<1024, _>(MemChunkHolder::zeroed());
+//! tx.send(&Quote { bid: 10, ask: 11 }).unwrap();
+//! tx.send(&Heartbeat).unwrap();
+//!
+//! let mut quotes = 0;
+//! let mut heartbeats = 0;
+//! let mut dispatcher = Dispatcher::new();
+//! dispatcher.on(|q: &Quote| quotes += q.ask - q.bid).unwrap();
+//! dispatcher.on(|_: &Heartbeat| heartbeats += 1).unwrap();
+//!
+//! assert_eq!(rx.dispatch_all(&mut dispatcher).unwrap(), 2);
+//! drop(dispatcher);
+//! assert_eq!((quotes, heartbeats), (1, 1));
+//! ```
+
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::MemHolderMut;
+use crate::sync::lfbytespsc::{SpScByteRingData, SpScByteRingReceiver, SpScByteRingSender};
+use std::mem::MaybeUninit;
+
+/// Message which could be sent through envelope channel.
+///
+/// # Safety
+/// TAG must be unique per type within a channel, receiver reinterprets bytes
+/// of a record as the type registered for its tag.
+/// TAG must not be equal to [`crate::sync::lfbytespsc::WRAP_TAG`].
+pub unsafe trait Message: Copy + 'static {
+    const TAG: u16;
+}
+
+/// Implements [`Message`] for a type with the given tag: `impl_message!(unsafe T, tag)`.
+/// Caller upholds safety contract of [`Message`], so invocation is marked by `unsafe`.
+#[macro_export]
+macro_rules! impl_message {
+    (unsafe $t:ty, $tag:expr) => {
+        unsafe impl $crate::sync::envelope::Message for $t {
+            const TAG: u16 = {
+                assert!(
+                    $tag != $crate::sync::lfbytespsc::WRAP_TAG,
+                    "tag is reserved"
+                );
+                $tag
+            };
+        }
+    };
+}
+
+pub struct EnvelopeSender<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>> {
+    tx: SpScByteRingSender<BSIZE, BackT>,
+}
+
+impl<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>>
+    EnvelopeSender<BSIZE, BackT>
+{
+    pub fn new(backend: BackT) -> Self {
+        Self {
+            tx: SpScByteRingSender::new(backend),
+        }
+    }
+
+    pub fn send<M: Message>(&mut self, msg: &M) -> Result<(), GtsTransportError> {
+        // SAFETY: msg is valid for size_of::<M>() bytes reads.
+        unsafe {
+            self.tx.send_raw(
+                M::TAG,
+                msg as *const M as *const u8,
+                std::mem::size_of::<M>(),
+            )
+        }
+    }
+}
+
+type Handler<'a> = Box<dyn FnMut(*const u8, usize) -> Result<(), GtsTransportError> + 'a>;
+
+/// Set of handlers per message type.
+pub struct Dispatcher<'a> {
+    /// Handler and type name of message per tag.
+    handlers: Vec<Option<(Handler<'a>, &'static str)>>,
+}
+
+impl<'a> Default for Dispatcher<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Dispatcher<'a> {
+    pub fn new() -> Self {
+        Self {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Registers handler for M, only one handler per tag is allowed,
+    /// so tag used by other type is rejected too.
+    pub fn on<M: Message>(&mut self, mut f: impl FnMut(&M) + 'a) -> Result<(), GtsTransportError> {
+        let idx = M::TAG as usize;
+        let name = std::any::type_name::<M>();
+        if self.handlers.len() <= idx {
+            self.handlers.resize_with(idx + 1, || None);
+        }
+        if let Some((_, registered)) = &self.handlers[idx] {
+            return Err(GtsTransportError::LogicError(format!(
+                "tag {} of {} is already registered for {}",
+                M::TAG,
+                name,
+                registered
+            )));
+        }
+
+        let handler: Handler = Box::new(move |payload, len| {
+            if len != std::mem::size_of::<M>() {
+                return Err(GtsTransportError::Inconsistent);
+            }
+            let mut msg = MaybeUninit::<M>::uninit();
+            // SAFETY: record with M::TAG was written from M (see Message),
+            // length is checked above.
+            let msg = unsafe {
+                std::ptr::copy_nonoverlapping(payload, msg.as_mut_ptr() as *mut u8, len);
+                msg.assume_init()
+            };
+            f(&msg);
+            Ok(())
+        });
+        self.handlers[idx] = Some((handler, name));
+        Ok(())
+    }
+
+    fn dispatch(
+        &mut self,
+        tag: u16,
+        payload: *const u8,
+        len: usize,
+    ) -> Result<(), GtsTransportError> {
+        match self.handlers.get_mut(tag as usize) {
+            Some(Some((handler, _))) => handler(payload, len),
+            _ => Err(GtsTransportError::LogicError(format!(
+                "no handler for tag {}",
+                tag
+            ))),
+        }
+    }
+}
+
+pub struct EnvelopeReceiver<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>> {
+    rx: SpScByteRingReceiver<BSIZE, BackT>,
+}
+
+impl<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>>
+    EnvelopeReceiver<BSIZE, BackT>
+{
+    pub fn new(backend: BackT) -> Self {
+        Self {
+            rx: SpScByteRingReceiver::new(backend),
+        }
+    }
+
+    /// Dispatches one message, returns its tag.
+    /// Message without registered handler is consumed and reported as LogicError.
+    pub fn try_dispatch(&mut self, dispatcher: &mut Dispatcher) -> Result<u16, GtsTransportError> {
+        self.rx
+            .try_recv_raw(|tag, payload, len| dispatcher.dispatch(tag, payload, len).map(|_| tag))?
+    }
+
+    /// Dispatches messages until ring is empty, returns number of dispatched messages.
+    pub fn dispatch_all(
+        &mut self,
+        dispatcher: &mut Dispatcher,
+    ) -> Result<usize, GtsTransportError> {
+        let mut counter = 0;
+        loop {
+            match self.try_dispatch(dispatcher) {
+                Ok(_) => counter += 1,
+                Err(GtsTransportError::WouldBlock) => return Ok(counter),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+pub fn envelope_pair<const BSIZE: usize, BackT>(
+    backend: BackT,
+) -> (EnvelopeSender<BSIZE, BackT>, EnvelopeReceiver<BSIZE, BackT>)
+where
+    BackT: Clone + MemHolderMut<SpScByteRingData<BSIZE>>,
+{
+    (
+        EnvelopeSender::new(backend.clone()),
+        EnvelopeReceiver::new(backend),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membackend::memchunk::MemChunkHolder;
+    use crate::membackend::shmem::ShmemHolder;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Small {
+        val: u8,
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Big {
+        timestamp: u64,
+        levels: [u64; 10],
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Side {
+        Buy(u32),
+        Sell(u64),
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Clash {
+        val: u64,
+    }
+
+    // SAFETY: tags are unique, except Clash, which is never sent,
+    // it checks that dispatcher rejects the same tag of other type.
+    impl_message!(unsafe Small, 1);
+    impl_message!(unsafe Big, 2);
+    impl_message!(unsafe Side, 3);
+    impl_message!(unsafe Clash, 1);
+
+    #[test]
+    fn test_dispatch() {
+        let (mut tx, mut rx) = envelope_pair::<1024, _>(MemChunkHolder::zeroed());
+
+        tx.send(&Small { val: 5 }).unwrap();
+        tx.send(&Big {
+            timestamp: 77,
+            levels: [3; 10],
+        })
+        .unwrap();
+        tx.send(&Side::Sell(99)).unwrap();
+        tx.send(&Side::Buy(11)).unwrap();
+
+        let mut smalls = Vec::new();
+        let mut bigs = Vec::new();
+        let mut sides = Vec::new();
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.on(|m: &Small| smalls.push(*m)).unwrap();
+        dispatcher.on(|m: &Big| bigs.push(*m)).unwrap();
+        dispatcher.on(|m: &Side| sides.push(*m)).unwrap();
+        assert!(matches!(
+            dispatcher.on(|_: &Small| {}),
+            Err(GtsTransportError::LogicError(_))
+        ));
+        // the same tag of other type.
+        assert!(matches!(
+            dispatcher.on(|_: &Clash| {}),
+            Err(GtsTransportError::LogicError(_))
+        ));
+
+        assert_eq!(rx.try_dispatch(&mut dispatcher).unwrap(), Small::TAG);
+        assert_eq!(rx.dispatch_all(&mut dispatcher).unwrap(), 3);
+        assert!(matches!(
+            rx.try_dispatch(&mut dispatcher),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        drop(dispatcher);
+
+        assert_eq!(smalls, vec![Small { val: 5 }]);
+        assert_eq!(bigs[0].timestamp, 77);
+        assert_eq!(bigs[0].levels, [3; 10]);
+        assert_eq!(sides, vec![Side::Sell(99), Side::Buy(11)]);
+    }
+
+    #[test]
+    fn test_unknown_tag() {
+        let (mut tx, mut rx) = envelope_pair::<256, _>(MemChunkHolder::zeroed());
+        tx.send(&Small { val: 1 }).unwrap();
+        tx.send(&Small { val: 2 }).unwrap();
+
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.on(|_: &Big| {}).unwrap();
+        // message is consumed even without handler.
+        assert!(matches!(
+            rx.dispatch_all(&mut dispatcher),
+            Err(GtsTransportError::LogicError(_))
+        ));
+        assert!(matches!(
+            rx.try_dispatch(&mut dispatcher),
+            Err(GtsTransportError::LogicError(_))
+        ));
+        assert!(matches!(
+            rx.try_dispatch(&mut dispatcher),
+            Err(GtsTransportError::WouldBlock)
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_shmem() {
+        let shmem_name = "testenvelope";
+        let mut tx = EnvelopeSender::<4096, _>::new(ShmemHolder::create(shmem_name));
+        let mut rx = EnvelopeReceiver::<4096, _>::new(ShmemHolder::connect_rw(shmem_name));
+
+        let total = std::cell::Cell::new(0u64);
+        for i in 0..1000u64 {
+            if i % 2 == 0 {
+                tx.send(&Side::Sell(i)).unwrap();
+            } else {
+                tx.send(&Small { val: 1 }).unwrap();
+            }
+            let mut dispatcher = Dispatcher::new();
+            dispatcher
+                .on(|m: &Side| {
+                    if let Side::Sell(val) = m {
+                        total.set(total.get() + val)
+                    }
+                })
+                .unwrap();
+            dispatcher
+                .on(|m: &Small| total.set(total.get() + m.val as u64))
+                .unwrap();
+            assert_eq!(rx.dispatch_all(&mut dispatcher).unwrap(), 1);
+        }
+        assert_eq!(total.get(), (0..1000u64).step_by(2).sum::<u64>() + 500);
+    }
+}
@@ -0,0 +1,394 @@
+//! Lock free single producer single consumer ring of variable sized records.
+//! Unlike [`crate::sync::lfringspsc`], every record occupies exactly
+//! `HEADER_SIZE + len` bytes (rounded up to 8), so messages of different types
+//! and sizes could share one ring without being padded to the biggest one.
+//!
+//! Each record is prefixed with a header `{tag: u16, reserved: u16, len: u32}`.
+//! If record doesn't fit till the end of the ring, writer puts a wrap record
+//! (tag == [`WRAP_TAG`]) and starts from the beginning.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::error::GtsTransportError;
+//! use gts_transport::membackend::memchunk::MemChunkHolder;
+//! use gts_transport::sync::lfbytespsc::spsc_byte_ring_pair;
+//!
+//! let (mut tx, mut rx) = spsc_byte_ring_pair::<256, _>(MemChunkHolder::zeroed());
+//! tx.send_bytes(7, b"hello").unwrap();
+//!
+//! let mut buf = [0u8; 16];
+//! let (tag, len) = rx.try_recv_into(&mut buf).unwrap();
+//! assert_eq!(tag, 7);
+//! assert_eq!(&buf[..len], b"hello");
+//! assert!(matches!(rx.try_recv_into(&mut buf), Err(GtsTransportError::WouldBlock)));
+//! ```
+
+use crate::arch::CachePadded;
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::MemHolderMut;
+use crate::sync::atomic::{AtomicU32, Ordering};
+use bytemuck::Zeroable;
+
+/// Size of record header, every record (and payload) is aligned to it.
+pub const HEADER_SIZE: usize = 8;
+
+/// Reserved tag, marks the tail of the ring as unused.
+pub const WRAP_TAG: u16 = u16::MAX;
+
+/// SpScByteRingData keeps read and write positions in separate cache lines
+/// (see [`crate::sync::lfringspsc::SpScRingData`] for details).
+/// Positions are monotonic byte counters (wrapping u32), index in data is `pos % BSIZE`.
+#[repr(C)]
+pub struct SpScByteRingData<const BSIZE: usize> {
+    pub read_done_pos: CachePadded<AtomicU32>,
+    pub write_done_pos: CachePadded<AtomicU32>,
+    pub data: [u8; BSIZE],
+}
+
+unsafe impl<const BSIZE: usize> Zeroable for SpScByteRingData<BSIZE> {}
+
+impl<const BSIZE: usize> Default for SpScByteRingData<BSIZE> {
+    fn default() -> Self {
+        Self {
+            read_done_pos: CachePadded::new(AtomicU32::new(0)),
+            write_done_pos: CachePadded::new(AtomicU32::new(0)),
+            data: [0; BSIZE],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct RecordHeader {
+    tag: u16,
+    _reserved: u16,
+    len: u32,
+}
+
+const fn record_size(len: usize) -> usize {
+    HEADER_SIZE + ((len + HEADER_SIZE - 1) & !(HEADER_SIZE - 1))
+}
+
+pub struct SpScByteRingSender<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>> {
+    write_pos: u32,
+    back: BackT,
+}
+
+impl<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>>
+    SpScByteRingSender<BSIZE, BackT>
+{
+    const MASK: u32 = {
+        assert!(BSIZE.is_power_of_two(), "BSIZE must be power of two");
+        assert!(BSIZE >= HEADER_SIZE && BSIZE <= (1 << 31));
+        (BSIZE - 1) as u32
+    };
+
+    pub fn new(backend: BackT) -> Self {
+        let _ = Self::MASK;
+        Self {
+            write_pos: 0,
+            back: backend,
+        }
+    }
+
+    /// Max payload which could be sent in one record.
+    pub const fn max_payload() -> usize {
+        BSIZE / 2 - HEADER_SIZE
+    }
+
+    pub fn send_bytes(&mut self, tag: u16, bytes: &[u8]) -> Result<(), GtsTransportError> {
+        // SAFETY: bytes is valid for bytes.len() reads.
+        unsafe { self.send_raw(tag, bytes.as_ptr(), bytes.len()) }
+    }
+
+    /// # Safety
+    /// `src` must be valid for `len` bytes reads.
+    pub unsafe fn send_raw(
+        &mut self,
+        tag: u16,
+        src: *const u8,
+        len: usize,
+    ) -> Result<(), GtsTransportError> {
+        // SAFETY:
+        // only one producer is allowed per backend.
+        // we write
+        // 1. check there is enough free room between write_pos and read_done_pos
+        // 2. write (optional) wrap record, header and payload
+        // 3. advance write_done_pos
+        if tag == WRAP_TAG {
+            return Err(GtsTransportError::LogicError(format!(
+                "tag {} is reserved",
+                WRAP_TAG
+            )));
+        }
+        if len > Self::max_payload() {
+            return Err(GtsTransportError::LogicError(format!(
+                "payload {} is bigger than {}",
+                len,
+                Self::max_payload()
+            )));
+        }
+
+        let pdata = self.back.get_mut_ptr();
+        let total = record_size(len) as u32;
+        let read_pos = unsafe { (*pdata).read_done_pos.load(Ordering::Acquire) };
+        let free = BSIZE as u32 - self.write_pos.wrapping_sub(read_pos);
+
+        let mut idx = self.write_pos & Self::MASK;
+        let contiguous = BSIZE as u32 - idx;
+        let need = if total > contiguous {
+            contiguous + total
+        } else {
+            total
+        };
+        if need > free {
+            return Err(GtsTransportError::WouldBlock);
+        }
+
+        unsafe {
+            let base = std::ptr::addr_of_mut!((*pdata).data) as *mut u8;
+            if total > contiguous {
+                let wrap = RecordHeader {
+                    tag: WRAP_TAG,
+                    _reserved: 0,
+                    len: contiguous - HEADER_SIZE as u32,
+                };
+                std::ptr::write(base.add(idx as usize) as *mut RecordHeader, wrap);
+                idx = 0;
+            }
+            let header = RecordHeader {
+                tag,
+                _reserved: 0,
+                len: len as u32,
+            };
+            std::ptr::write(base.add(idx as usize) as *mut RecordHeader, header);
+            std::ptr::copy_nonoverlapping(src, base.add(idx as usize + HEADER_SIZE), len);
+        }
+
+        self.write_pos = self.write_pos.wrapping_add(need);
+        unsafe {
+            (*pdata)
+                .write_done_pos
+                .store(self.write_pos, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+pub struct SpScByteRingReceiver<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>> {
+    back: BackT,
+}
+
+impl<const BSIZE: usize, BackT: MemHolderMut<SpScByteRingData<BSIZE>>>
+    SpScByteRingReceiver<BSIZE, BackT>
+{
+    const MASK: u32 = (BSIZE - 1) as u32;
+
+    pub fn new(backend: BackT) -> Self {
+        Self { back: backend }
+    }
+
+    /// Calls `f(tag, payload_ptr, len)` for next record and releases it.
+    /// payload_ptr is 8-aligned and valid for `len` bytes reads only inside `f`.
+    /// Returns Inconsistent if ring is corrupted, e.g. by other process, record is not released.
+    pub fn try_recv_raw<R>(
+        &mut self,
+        f: impl FnOnce(u16, *const u8, usize) -> R,
+    ) -> Result<R, GtsTransportError> {
+        // SAFETY: we read
+        // 1) check read_pos != write_done_pos, otherwise return GtsTransportError::WouldBlock
+        // 2) skip wrap record if any, pass payload to f
+        // 3) advance read_done_pos
+        let pdata = self.back.get_mut_ptr();
+
+        let (write_pos, mut read_pos) = unsafe {
+            let write_pos = (*pdata).write_done_pos.load(Ordering::Acquire);
+            let read_pos = (*pdata).read_done_pos.load(Ordering::Relaxed);
+            (write_pos, read_pos)
+        };
+
+        if write_pos == read_pos {
+            return Err(GtsTransportError::WouldBlock);
+        }
+        // positions and headers are written by other side, so they are checked before use:
+        // record must be inside of written part and must not cross the end of data.
+        let mut available = write_pos.wrapping_sub(read_pos) as usize;
+        if available > BSIZE || !(read_pos as usize).is_multiple_of(HEADER_SIZE) {
+            return Err(GtsTransportError::Inconsistent);
+        }
+        let fits = |read_pos: u32, size: usize, available: usize| {
+            size <= available && (read_pos & Self::MASK) as usize + size <= BSIZE
+        };
+
+        unsafe {
+            let base = std::ptr::addr_of!((*pdata).data) as *const u8;
+            let mut header =
+                std::ptr::read(base.add((read_pos & Self::MASK) as usize) as *const RecordHeader);
+            if header.tag == WRAP_TAG {
+                let size = record_size(header.len as usize);
+                if !fits(read_pos, size, available) {
+                    return Err(GtsTransportError::Inconsistent);
+                }
+                read_pos = read_pos.wrapping_add(size as u32);
+                available -= size;
+                if available == 0 {
+                    return Err(GtsTransportError::Inconsistent);
+                }
+                header = std::ptr::read(
+                    base.add((read_pos & Self::MASK) as usize) as *const RecordHeader
+                );
+            }
+            if !fits(read_pos, record_size(header.len as usize), available) {
+                return Err(GtsTransportError::Inconsistent);
+            }
+            let payload = base.add((read_pos & Self::MASK) as usize + HEADER_SIZE);
+            let res = f(header.tag, payload, header.len as usize);
+
+            read_pos = read_pos.wrapping_add(record_size(header.len as usize) as u32);
+            (*pdata).read_done_pos.store(read_pos, Ordering::Release);
+            Ok(res)
+        }
+    }
+
+    /// Copies next record to buf, returns (tag, len).
+    /// Record is dropped with LogicError if it doesn't fit to buf.
+    pub fn try_recv_into(&mut self, buf: &mut [u8]) -> Result<(u16, usize), GtsTransportError> {
+        self.try_recv_raw(|tag, payload, len| {
+            if len > buf.len() {
+                return Err(GtsTransportError::LogicError(format!(
+                    "record {} doesn't fit to buffer {}",
+                    len,
+                    buf.len()
+                )));
+            }
+            // SAFETY: payload is valid for len reads (see try_recv_raw).
+            unsafe { std::ptr::copy_nonoverlapping(payload, buf.as_mut_ptr(), len) };
+            Ok((tag, len))
+        })?
+    }
+}
+
+pub fn spsc_byte_ring_pair<const BSIZE: usize, BackT>(
+    backend: BackT,
+) -> (
+    SpScByteRingSender<BSIZE, BackT>,
+    SpScByteRingReceiver<BSIZE, BackT>,
+)
+where
+    BackT: Clone + MemHolderMut<SpScByteRingData<BSIZE>>,
+{
+    (
+        SpScByteRingSender::new(backend.clone()),
+        SpScByteRingReceiver::new(backend),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::CACHE_LINE_SIZE;
+    use crate::membackend::memchunk::MemChunkHolder;
+
+    #[test]
+    fn test_sizes() {
+        let test_data = SpScByteRingData::<64>::zeroed();
+        let addr_of_read_done = std::ptr::addr_of!(test_data.read_done_pos);
+        let addr_of_write_done = std::ptr::addr_of!(test_data.write_done_pos);
+        let addr_of_data = std::ptr::addr_of!(test_data.data);
+
+        assert_eq!(
+            addr_of_write_done as usize,
+            addr_of_read_done as usize + CACHE_LINE_SIZE
+        );
+        assert_eq!(
+            addr_of_data as usize,
+            addr_of_read_done as usize + 2 * CACHE_LINE_SIZE
+        );
+        assert_eq!(record_size(0), 8);
+        assert_eq!(record_size(1), 16);
+        assert_eq!(record_size(8), 16);
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let (mut tx, mut rx) = spsc_byte_ring_pair::<64, _>(MemChunkHolder::zeroed());
+        let mut buf = [0u8; 32];
+
+        // 3 records of 16 bytes, 16 bytes left at the end.
+        for i in 0..3u8 {
+            tx.send_bytes(i as u16, &[i; 5]).unwrap();
+        }
+        // 24 bytes record doesn't fit the tail and head is busy.
+        let res = tx.send_bytes(10, &[10; 12]);
+        assert!(matches!(res, Err(GtsTransportError::WouldBlock)));
+
+        for i in 0..2u8 {
+            let (tag, len) = rx.try_recv_into(&mut buf).unwrap();
+            assert_eq!(tag, i as u16);
+            assert_eq!(&buf[..len], &[i; 5]);
+        }
+
+        // wrap: 16 bytes of tail skipped, record goes to the head.
+        tx.send_bytes(10, &[10; 12]).unwrap();
+        let (tag, len) = rx.try_recv_into(&mut buf).unwrap();
+        assert_eq!((tag, len), (2, 5));
+        let (tag, len) = rx.try_recv_into(&mut buf).unwrap();
+        assert_eq!(tag, 10);
+        assert_eq!(&buf[..len], &[10; 12]);
+
+        let res = rx.try_recv_into(&mut buf);
+        assert!(matches!(res, Err(GtsTransportError::WouldBlock)));
+
+        // too big and reserved tag
+        let res = tx.send_bytes(1, &[0; 32]);
+        assert!(matches!(res, Err(GtsTransportError::LogicError(_))));
+        let res = tx.send_bytes(WRAP_TAG, &[0; 1]);
+        assert!(matches!(res, Err(GtsTransportError::LogicError(_))));
+    }
+
+    #[test]
+    fn test_many_records() {
+        let (mut tx, mut rx) = spsc_byte_ring_pair::<128, _>(MemChunkHolder::zeroed());
+        let mut buf = [0u8; 64];
+        let mut sent = 0u32;
+        let mut received = 0u32;
+        while received < 10_000 {
+            let len = (sent % 37) as usize;
+            let res = tx.send_bytes(sent as u16, &vec![sent as u8; len]);
+            if res.is_ok() {
+                sent += 1;
+            }
+            if res.is_err() || sent.is_multiple_of(3) {
+                while let Ok((tag, len)) = rx.try_recv_into(&mut buf) {
+                    assert_eq!(tag, received as u16);
+                    assert_eq!(len, (received % 37) as usize);
+                    assert!(buf[..len].iter().all(|&b| b == received as u8));
+                    received += 1;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let holder = MemChunkHolder::<SpScByteRingData<64>>::zeroed();
+        let (mut tx, mut rx) = spsc_byte_ring_pair::<64, _>(holder.clone());
+        let mut buf = [0u8; 64];
+        tx.send_bytes(1, &[1; 5]).unwrap();
+
+        let pdata = holder.get_mut_ptr();
+        // len of header points beyond written part and the end of data.
+        unsafe { (&mut (*pdata).data)[4..8].copy_from_slice(&1000u32.to_ne_bytes()) };
+        let res = rx.try_recv_into(&mut buf);
+        assert!(matches!(res, Err(GtsTransportError::Inconsistent)));
+
+        unsafe { (&mut (*pdata).data)[4..8].copy_from_slice(&5u32.to_ne_bytes()) };
+        unsafe { (*pdata).write_done_pos.store(1000, Ordering::Release) };
+        let res = rx.try_recv_into(&mut buf);
+        assert!(matches!(res, Err(GtsTransportError::Inconsistent)));
+
+        unsafe { (*pdata).write_done_pos.store(16, Ordering::Release) };
+        assert_eq!(rx.try_recv_into(&mut buf).unwrap(), (1, 5));
+    }
+}
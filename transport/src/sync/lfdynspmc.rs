@@ -0,0 +1,407 @@
+//! Lock free ring single producer multiple consumers with capacity chosen at runtime.
+//! Sender never blocks: it overwrites oldest value, each receiver reads all values
+//! in order while it keeps up, and skips overwritten ones otherwise (see
+//! [`DynSpMcReceiver::lost`]). Every slot is seqlock, like lfspmc.
+//!
+//! Capacity is stored in shared header, so receiver (e.g. tool or other binary) learns it on
+//! attach. Receivers only read chunk, so shmem could be connected read only.
+//! New receiver starts from last sent value.
+//!
+//! Atomics here are std ones (not loom), as they are placed to raw memory.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::error::GtsTransportError;
+//! use gts_transport::membackend::shmem::ShmemOptions;
+//! use gts_transport::sync::lfdynspmc::{DynSpMcReceiver, DynSpMcSender};
+//!
+//! let options = ShmemOptions::new();
+//! let mut tx = DynSpMcSender::<u64, _>::create_shmem("doc_dynspmc", 4, &options)?;
+//! let mut rx = DynSpMcReceiver::<u64, _>::connect_shmem("doc_dynspmc", &options)?;
+//! assert_eq!(rx.capacity(), 4);
+//!
+//! for val in 0..10 {
+//!     tx.send(&val);
+//! }
+//! // receiver is lagged, only last 4 values are left.
+//! assert_eq!(rx.try_recv_value()?, 6);
+//! assert_eq!(rx.lost(), 6);
+//! # Ok::<(), GtsTransportError>(())
+//! ```
+
+use crate::arch::CachePadded;
+use crate::error::GtsTransportError;
+use crate::membackend::memchunk::RawMemChunk;
+use crate::membackend::memholder::{RawMemHolder, RawMemHolderMut, ReadOnly};
+use crate::membackend::rawshmem::{RawShmem, ShmemOptions};
+use crate::sync::racycopy::{racy_load, racy_store};
+use crate::sync::received::Received;
+use crate::wire::{fingerprint, fnv1a};
+use bytemuck::NoUninit;
+use log::debug;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+/// Header in the beginning of chunk, then slots follow.
+#[repr(C)]
+struct DynSpMcHeader {
+    /// written last by sender, zero means header is not ready.
+    capacity: AtomicU32,
+    elem_size: u32,
+    elem_align: u32,
+    /// seqnum of last sent value, values are numbered from 1.
+    write_seqnum: CachePadded<AtomicU64>,
+}
+
+/// Value with seqlock, begin == end == seqnum of value when it's consistent.
+#[repr(C)]
+struct DynSpMcSlot<T> {
+    begin: AtomicU64,
+    end: AtomicU64,
+    data: MaybeUninit<T>,
+}
+
+/// Fingerprint of ring of T, for shmem header.
+pub fn layout_fingerprint<T>() -> u64 {
+    fingerprint::<T>() ^ fnv1a(b"lfdynspmc")
+}
+
+fn slots_offset<T>() -> usize {
+    std::mem::size_of::<DynSpMcHeader>().next_multiple_of(std::mem::align_of::<DynSpMcSlot<T>>())
+}
+
+/// Length of chunk for ring of capacity values.
+pub fn required_length<T>(capacity: usize) -> usize {
+    slots_offset::<T>() + capacity * std::mem::size_of::<DynSpMcSlot<T>>()
+}
+
+/// # Safety
+/// base must be chunk of ring with more than index slots.
+unsafe fn slot<T>(base: *const u8, index: u64) -> *const DynSpMcSlot<T> {
+    (base.add(slots_offset::<T>()) as *const DynSpMcSlot<T>).add(index as usize)
+}
+
+pub struct DynSpMcSender<T: NoUninit, BackT: RawMemHolderMut> {
+    back: BackT,
+    capacity: u64,
+    seqnum: u64,
+    _owns_t: std::marker::PhantomData<T>,
+}
+
+impl<T: NoUninit, BackT: RawMemHolderMut> DynSpMcSender<T, BackT> {
+    /// Initializes ring in zeroed chunk.
+    pub fn new(backend: BackT, capacity: usize) -> Result<Self, GtsTransportError> {
+        if capacity == 0 || capacity > u32::MAX as usize {
+            return Err(GtsTransportError::LogicError(format!(
+                "bad capacity {capacity}"
+            )));
+        }
+        if backend.length() < required_length::<T>(capacity)
+            || !(backend.get_ptr() as usize).is_multiple_of(std::mem::align_of::<DynSpMcHeader>())
+        {
+            return Err(GtsTransportError::LogicError(format!(
+                "chunk of {} bytes doesn't fit ring of {capacity}",
+                backend.length()
+            )));
+        }
+
+        let header = backend.get_mut_ptr() as *mut DynSpMcHeader;
+        // SAFETY: chunk is big enough, receivers don't touch header until capacity is set.
+        unsafe {
+            (*header).elem_size = std::mem::size_of::<T>() as u32;
+            (*header).elem_align = std::mem::align_of::<T>() as u32;
+            (*header).write_seqnum.store(0, Ordering::Relaxed);
+            (*header).capacity.store(capacity as u32, Ordering::Release);
+        }
+
+        Ok(Self {
+            back: backend,
+            capacity: capacity as u64,
+            seqnum: 0,
+            _owns_t: std::marker::PhantomData::<T> {},
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Overwrites oldest value, never blocks.
+    pub fn send(&mut self, new_data: &T) {
+        // SAFETY: only one producer is allowed per chunk, we write
+        // 1. slot begin, Release fence (see lfspmc).
+        // 2. data by relaxed atomic words.
+        // 3. slot end, then write_seqnum to publish value.
+        let base = self.back.get_mut_ptr();
+        let header = base as *const DynSpMcHeader;
+
+        self.seqnum += 1;
+        unsafe {
+            let slot = slot::<T>(base, self.seqnum % self.capacity).cast_mut();
+            (*slot).begin.store(self.seqnum, Ordering::Relaxed);
+            fence(Ordering::Release);
+            racy_store(new_data as *const _, (*slot).data.as_mut_ptr());
+            (*slot).end.store(self.seqnum, Ordering::Release);
+            (*header).write_seqnum.store(self.seqnum, Ordering::Release);
+        }
+    }
+}
+
+impl<T: NoUninit> DynSpMcSender<T, RawShmem> {
+    /// Creates shmem segment for ring of capacity values.
+    pub fn create_shmem(
+        name: &str,
+        capacity: usize,
+        options: &ShmemOptions,
+    ) -> Result<Self, GtsTransportError> {
+        let raw = options.create_raw(
+            name,
+            required_length::<T>(capacity),
+            layout_fingerprint::<T>(),
+        )?;
+        Self::new(raw, capacity)
+    }
+}
+
+pub struct DynSpMcReceiver<T: Copy, BackT: RawMemHolder> {
+    back: BackT,
+    capacity: u64,
+    // seqnum of next value to read.
+    next_seqnum: u64,
+    lost: u64,
+    // last_copy is initialized iff next_seqnum > first_seqnum.
+    first_seqnum: u64,
+    last_copy: MaybeUninit<T>,
+}
+
+impl<T: Copy, BackT: RawMemHolder> DynSpMcReceiver<T, BackT> {
+    const MAX_ITER_TILL_HANG: usize = 1000;
+
+    /// Attaches to ring initialized by sender.
+    pub fn new(backend: BackT) -> Result<Self, GtsTransportError> {
+        if backend.length() < std::mem::size_of::<DynSpMcHeader>() {
+            return Err(GtsTransportError::Incompatible("dynspmc chunk".to_string()));
+        }
+        let header = backend.get_ptr() as *const DynSpMcHeader;
+        // SAFETY: chunk is big enough for header, the rest is read after capacity.
+        let (capacity, elem_size, elem_align, write_seqnum) = unsafe {
+            let capacity = (*header).capacity.load(Ordering::Acquire);
+            (
+                capacity,
+                (*header).elem_size,
+                (*header).elem_align,
+                (*header).write_seqnum.load(Ordering::Acquire),
+            )
+        };
+        if capacity == 0 {
+            return Err(GtsTransportError::Unitialized);
+        }
+        if elem_size as usize != std::mem::size_of::<T>()
+            || elem_align as usize != std::mem::align_of::<T>()
+            || backend.length() < required_length::<T>(capacity as usize)
+        {
+            return Err(GtsTransportError::Incompatible("dynspmc chunk".to_string()));
+        }
+
+        let next_seqnum = write_seqnum.max(1);
+        Ok(Self {
+            back: backend,
+            capacity: capacity as u64,
+            next_seqnum,
+            lost: 0,
+            first_seqnum: next_seqnum,
+            last_copy: MaybeUninit::uninit(),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Number of values overwritten by sender before this receiver read them.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    pub fn get_last_value(&self) -> Option<&T> {
+        // SAFETY: last_copy is written before next_seqnum is advanced.
+        match self.next_seqnum > self.first_seqnum {
+            true => Some(unsafe { self.last_copy.assume_init_ref() }),
+            false => None,
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<&T, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(self.get_last_value().unwrap())
+    }
+
+    /// Same as [`Self::try_recv`], but returns copy of value.
+    pub fn try_recv_value(&mut self) -> Result<T, GtsTransportError> {
+        self.try_recv().copied()
+    }
+
+    /// Same as [`Self::try_recv`], but value is tagged with sender's seqnum and receive time.
+    pub fn try_recv_tagged(&mut self) -> Result<Received<'_, T>, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(Received::new(
+            self.get_last_value().unwrap(),
+            self.next_seqnum - 1,
+        ))
+    }
+
+    fn recv_to_last_copy(&mut self) -> Result<(), GtsTransportError> {
+        // SAFETY: we read (see lfspmc)
+        // 1. slot end (Acquire)
+        // 2. data by relaxed atomic words, Acquire fence.
+        // 3. slot begin.
+        // IFF begin == end == next_seqnum, data is value of next_seqnum.
+        let base = self.back.get_ptr();
+        let header = base as *const DynSpMcHeader;
+
+        for _ in 0..Self::MAX_ITER_TILL_HANG {
+            let write_seqnum = unsafe { (*header).write_seqnum.load(Ordering::Acquire) };
+            if self.next_seqnum > write_seqnum {
+                return Err(GtsTransportError::WouldBlock);
+            }
+            if write_seqnum - self.next_seqnum >= self.capacity {
+                let oldest = write_seqnum - self.capacity + 1;
+                self.lost += oldest - self.next_seqnum;
+                self.next_seqnum = oldest;
+            }
+
+            let (begin, end) = unsafe {
+                let slot = slot::<T>(base, self.next_seqnum % self.capacity);
+                let end = (*slot).end.load(Ordering::Acquire);
+                racy_load((*slot).data.as_ptr(), self.last_copy.as_mut_ptr());
+                fence(Ordering::Acquire);
+                (end, (*slot).begin.load(Ordering::Relaxed))
+            };
+
+            if begin == end && end == self.next_seqnum {
+                self.next_seqnum += 1;
+                return Ok(());
+            }
+            // slot is overwritten by sender meanwhile, last_copy is garbage.
+            self.first_seqnum = self.next_seqnum;
+        }
+        debug!("dynspmc recv reach MAX_ITER_TILL_HANG, receiver is too slow");
+        Err(GtsTransportError::InconsistentHang)
+    }
+}
+
+impl<T: Copy> DynSpMcReceiver<T, RawShmem<ReadOnly>> {
+    /// Connects (read only) to shmem segment created by [`DynSpMcSender::create_shmem`].
+    pub fn connect_shmem(name: &str, options: &ShmemOptions) -> Result<Self, GtsTransportError> {
+        let raw = options.connect_raw_ro(name)?;
+        if raw.header().fingerprint != layout_fingerprint::<T>() {
+            return Err(GtsTransportError::Incompatible(raw.name().to_string()));
+        }
+        Self::new(raw)
+    }
+}
+
+/// Ring of capacity values in one process, receivers are created by
+/// `DynSpMcReceiver::new(chunk.clone())`.
+pub fn dyn_spmc_sender<T: NoUninit>(
+    capacity: usize,
+) -> Result<(DynSpMcSender<T, RawMemChunk>, RawMemChunk), GtsTransportError> {
+    let chunk = RawMemChunk::zeroed(required_length::<T>(capacity));
+    Ok((DynSpMcSender::new(chunk.clone(), capacity)?, chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug)]
+    struct Pair {
+        first: u64,
+        second: u64,
+    }
+
+    // SAFETY: no padding.
+    unsafe impl NoUninit for Pair {}
+
+    #[test]
+    fn test_in_order_and_lag() {
+        let (mut tx, chunk) = dyn_spmc_sender::<u64>(4).unwrap();
+        let mut rx1 = DynSpMcReceiver::<u64, _>::new(chunk.clone()).unwrap();
+        assert!(matches!(rx1.try_recv(), Err(GtsTransportError::WouldBlock)));
+        assert!(rx1.get_last_value().is_none());
+
+        for val in 1..=3 {
+            tx.send(&val);
+        }
+        let mut rx2 = DynSpMcReceiver::<u64, _>::new(chunk.clone()).unwrap();
+        for val in 1..=3 {
+            assert_eq!(rx1.try_recv_value().unwrap(), val);
+        }
+        // late receiver starts from last value.
+        let received = rx2.try_recv_tagged().unwrap();
+        assert_eq!((*received, received.seqnum()), (3, 3));
+        assert!(matches!(rx2.try_recv(), Err(GtsTransportError::WouldBlock)));
+
+        for val in 4..=13 {
+            tx.send(&val);
+        }
+        assert_eq!(rx1.try_recv_value().unwrap(), 10);
+        assert_eq!(rx1.lost(), 6);
+        for val in 11..=13 {
+            assert_eq!(rx1.try_recv_value().unwrap(), val);
+        }
+        assert!(matches!(rx1.try_recv(), Err(GtsTransportError::WouldBlock)));
+        assert_eq!(rx1.get_last_value(), Some(&13));
+        assert!(matches!(
+            DynSpMcReceiver::<u32, _>::new(chunk),
+            Err(GtsTransportError::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    fn test_no_torn_reads() {
+        let (mut tx, chunk) = dyn_spmc_sender::<Pair>(3).unwrap();
+        let mut rx = DynSpMcReceiver::<Pair, _>::new(chunk).unwrap();
+        let total = if cfg!(miri) { 50 } else { 10_000 };
+
+        let writer = std::thread::spawn(move || {
+            for i in 1..=total {
+                tx.send(&Pair {
+                    first: i,
+                    second: i,
+                });
+                std::thread::yield_now();
+            }
+        });
+
+        let mut last = 0;
+        while last != total {
+            match rx.try_recv_value() {
+                Ok(val) => {
+                    assert_eq!(val.first, val.second);
+                    assert!(val.first > last);
+                    last = val.first;
+                }
+                Err(_) => std::thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_shmem() {
+        let options = ShmemOptions::new().namespace("test");
+        let name = "dynspmc";
+        let mut tx = DynSpMcSender::<u64, _>::create_shmem(name, 8, &options).unwrap();
+        assert!(matches!(
+            DynSpMcReceiver::<u32, _>::connect_shmem(name, &options),
+            Err(GtsTransportError::Incompatible(_))
+        ));
+        let mut rx = DynSpMcReceiver::<u64, _>::connect_shmem(name, &options).unwrap();
+        assert_eq!(rx.capacity(), 8);
+        tx.send(&7);
+        assert_eq!(rx.try_recv_value().unwrap(), 7);
+    }
+}
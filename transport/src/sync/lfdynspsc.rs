@@ -0,0 +1,362 @@
+//! Lock free ring single producer single consumer with capacity chosen at runtime.
+//! Same as lfringspsc, but capacity is stored in shared header (not in RSIZE const generic),
+//! so receiver (e.g. tool or other binary) learns it on attach.
+//!
+//! Sender initializes header of [`RawMemHolder`] chunk, receiver fails with `Unitialized`
+//! until it's done, and with `Incompatible` if chunk was initialized for other T.
+//!
+//! Atomics here are std ones (not loom), as they are placed to raw memory.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::error::GtsTransportError;
+//! use gts_transport::membackend::shmem::ShmemOptions;
+//! use gts_transport::sync::lfdynspsc::{DynSpScRingReceiver, DynSpScRingSender};
+//!
+//! let options = ShmemOptions::new();
+//! let mut tx = DynSpScRingSender::<u64, _>::create_shmem("doc_dynspsc", 1000, &options)?;
+//! // receiver doesn't know capacity.
+//! let mut rx = DynSpScRingReceiver::<u64, _>::connect_shmem("doc_dynspsc", &options)?;
+//! assert_eq!(rx.capacity(), 1000);
+//!
+//! tx.send(&42)?;
+//! assert_eq!(rx.try_recv_value()?, 42);
+//! assert!(matches!(rx.try_recv_value(), Err(GtsTransportError::WouldBlock)));
+//! # Ok::<(), GtsTransportError>(())
+//! ```
+
+use crate::arch::CachePadded;
+use crate::error::GtsTransportError;
+use crate::membackend::memchunk::RawMemChunk;
+use crate::membackend::memholder::RawMemHolderMut;
+use crate::membackend::rawshmem::{RawShmem, ShmemOptions};
+use crate::sync::received::Received;
+use crate::wire::{fingerprint, fnv1a};
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Header in the beginning of chunk, then slots of T follow.
+#[repr(C)]
+struct DynSpScHeader {
+    /// written last by sender, zero means header is not ready.
+    capacity: AtomicU32,
+    elem_size: u32,
+    elem_align: u32,
+    read_done_seqnum: CachePadded<AtomicU32>,
+    write_done_seqnum: CachePadded<AtomicU32>,
+}
+
+/// Fingerprint of ring of T, for shmem header.
+pub fn layout_fingerprint<T>() -> u64 {
+    fingerprint::<T>() ^ fnv1a(b"lfdynspsc")
+}
+
+fn slots_offset<T>() -> usize {
+    std::mem::size_of::<DynSpScHeader>().next_multiple_of(std::mem::align_of::<T>())
+}
+
+/// Length of chunk for ring of capacity values.
+pub fn required_length<T>(capacity: usize) -> usize {
+    // one slot is always empty to distinguish full ring from empty one.
+    slots_offset::<T>() + (capacity + 1) * std::mem::size_of::<T>()
+}
+
+pub struct DynSpScRingSender<T: Copy, BackT: RawMemHolderMut> {
+    back: BackT,
+    slots: u32,
+    last_send_seqnum: u32,
+    _owns_t: std::marker::PhantomData<T>,
+}
+
+impl<T: Copy, BackT: RawMemHolderMut> DynSpScRingSender<T, BackT> {
+    /// Initializes ring in zeroed chunk.
+    pub fn new(backend: BackT, capacity: usize) -> Result<Self, GtsTransportError> {
+        if capacity == 0 || capacity >= u32::MAX as usize {
+            return Err(GtsTransportError::LogicError(format!(
+                "bad capacity {capacity}"
+            )));
+        }
+        if backend.length() < required_length::<T>(capacity)
+            || !(backend.get_ptr() as usize).is_multiple_of(std::mem::align_of::<DynSpScHeader>())
+        {
+            return Err(GtsTransportError::LogicError(format!(
+                "chunk of {} bytes doesn't fit ring of {capacity}",
+                backend.length()
+            )));
+        }
+
+        let header = backend.get_mut_ptr() as *mut DynSpScHeader;
+        // SAFETY: chunk is big enough, receiver doesn't touch header until capacity is set.
+        unsafe {
+            (*header).elem_size = std::mem::size_of::<T>() as u32;
+            (*header).elem_align = std::mem::align_of::<T>() as u32;
+            (*header).read_done_seqnum.store(0, Ordering::Relaxed);
+            (*header).write_done_seqnum.store(0, Ordering::Relaxed);
+            (*header).capacity.store(capacity as u32, Ordering::Release);
+        }
+
+        Ok(Self {
+            back: backend,
+            slots: capacity as u32 + 1,
+            last_send_seqnum: 0,
+            _owns_t: std::marker::PhantomData::<T> {},
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots as usize - 1
+    }
+
+    pub fn send(&mut self, new_data: &T) -> Result<(), GtsTransportError> {
+        // SAFETY: the same as SpScRingSender::send, slots are inside chunk.
+        let base = self.back.get_mut_ptr();
+        let header = base as *const DynSpScHeader;
+
+        let next_seqnum = (self.last_send_seqnum + 1) % self.slots;
+        let read_seqnum = unsafe { (*header).read_done_seqnum.load(Ordering::Acquire) };
+
+        if read_seqnum == next_seqnum {
+            return Err(GtsTransportError::WouldBlock);
+        }
+
+        self.last_send_seqnum = next_seqnum;
+        unsafe {
+            let slot = base.add(slots_offset::<T>()) as *mut T;
+            std::ptr::copy_nonoverlapping(new_data as *const _, slot.add(next_seqnum as usize), 1);
+            (*header)
+                .write_done_seqnum
+                .store(self.last_send_seqnum, Ordering::Release);
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Copy> DynSpScRingSender<T, RawShmem> {
+    /// Creates shmem segment for ring of capacity values.
+    pub fn create_shmem(
+        name: &str,
+        capacity: usize,
+        options: &ShmemOptions,
+    ) -> Result<Self, GtsTransportError> {
+        let raw = options.create_raw(
+            name,
+            required_length::<T>(capacity),
+            layout_fingerprint::<T>(),
+        )?;
+        Self::new(raw, capacity)
+    }
+}
+
+pub struct DynSpScRingReceiver<T: Copy, BackT: RawMemHolderMut> {
+    back: BackT,
+    slots: u32,
+    // number of received values, last_copy is initialized iff received > 0.
+    received: u64,
+    last_copy: MaybeUninit<T>,
+}
+
+impl<T: Copy, BackT: RawMemHolderMut> DynSpScRingReceiver<T, BackT> {
+    /// Attaches to ring initialized by sender.
+    pub fn new(backend: BackT) -> Result<Self, GtsTransportError> {
+        if backend.length() < std::mem::size_of::<DynSpScHeader>() {
+            return Err(GtsTransportError::Incompatible("dynspsc chunk".to_string()));
+        }
+        let header = backend.get_ptr() as *const DynSpScHeader;
+        // SAFETY: chunk is big enough for header, the rest is read after capacity.
+        let (capacity, elem_size, elem_align) = unsafe {
+            let capacity = (*header).capacity.load(Ordering::Acquire);
+            (capacity, (*header).elem_size, (*header).elem_align)
+        };
+        if capacity == 0 {
+            return Err(GtsTransportError::Unitialized);
+        }
+        if elem_size as usize != std::mem::size_of::<T>()
+            || elem_align as usize != std::mem::align_of::<T>()
+            || backend.length() < required_length::<T>(capacity as usize)
+        {
+            return Err(GtsTransportError::Incompatible("dynspsc chunk".to_string()));
+        }
+
+        Ok(Self {
+            back: backend,
+            slots: capacity + 1,
+            received: 0,
+            last_copy: MaybeUninit::uninit(),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots as usize - 1
+    }
+
+    pub fn get_last_value(&self) -> Option<&T> {
+        // SAFETY: last_copy is written before received is advanced.
+        match self.received {
+            0 => None,
+            _ => Some(unsafe { self.last_copy.assume_init_ref() }),
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<&T, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(self.get_last_value().unwrap())
+    }
+
+    /// Same as [`Self::try_recv`], but returns copy of value.
+    pub fn try_recv_value(&mut self) -> Result<T, GtsTransportError> {
+        self.try_recv().copied()
+    }
+
+    /// Same as [`Self::try_recv`], but value is tagged with number in channel and receive time.
+    pub fn try_recv_tagged(&mut self) -> Result<Received<'_, T>, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(Received::new(self.get_last_value().unwrap(), self.received))
+    }
+
+    fn recv_to_last_copy(&mut self) -> Result<(), GtsTransportError> {
+        // SAFETY: the same as SpScRingReceiver::try_recv, slots are inside chunk.
+        let base = self.back.get_mut_ptr();
+        let header = base as *const DynSpScHeader;
+
+        let (send_seqnum, read_seqnum) = unsafe {
+            let send_seqnum = (*header).write_done_seqnum.load(Ordering::Acquire);
+            let read_seqnum = (*header).read_done_seqnum.load(Ordering::Acquire);
+            (send_seqnum, read_seqnum)
+        };
+
+        if send_seqnum == read_seqnum {
+            return Err(GtsTransportError::WouldBlock);
+        }
+        let next_read = (read_seqnum + 1) % self.slots;
+
+        unsafe {
+            let slot = base.add(slots_offset::<T>()) as *const T;
+            std::ptr::copy_nonoverlapping(
+                slot.add(next_read as usize),
+                self.last_copy.as_mut_ptr(),
+                1,
+            );
+            (*header)
+                .read_done_seqnum
+                .store(next_read, Ordering::Release);
+        }
+        self.received += 1;
+
+        Ok(())
+    }
+}
+
+impl<T: Copy> DynSpScRingReceiver<T, RawShmem> {
+    /// Connects to shmem segment created by [`DynSpScRingSender::create_shmem`].
+    pub fn connect_shmem(name: &str, options: &ShmemOptions) -> Result<Self, GtsTransportError> {
+        // receiver writes read_done_seqnum.
+        let raw = options.connect_raw_rw(name)?;
+        if raw.header().fingerprint != layout_fingerprint::<T>() {
+            return Err(GtsTransportError::Incompatible(raw.name().to_string()));
+        }
+        Self::new(raw)
+    }
+}
+
+/// Ring of capacity values in one process.
+#[allow(clippy::type_complexity)]
+pub fn dyn_spsc_ring_pair<T: Copy>(
+    capacity: usize,
+) -> Result<
+    (
+        DynSpScRingSender<T, RawMemChunk>,
+        DynSpScRingReceiver<T, RawMemChunk>,
+    ),
+    GtsTransportError,
+> {
+    let chunk = RawMemChunk::zeroed(required_length::<T>(capacity));
+    let sender = DynSpScRingSender::new(chunk.clone(), capacity)?;
+    Ok((sender, DynSpScRingReceiver::new(chunk)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    struct TestData {
+        timestamp: u64,
+        flag: u8,
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let (mut tx, mut rx) = dyn_spsc_ring_pair::<TestData>(2).unwrap();
+        assert_eq!(rx.capacity(), 2);
+        assert!(matches!(rx.try_recv(), Err(GtsTransportError::WouldBlock)));
+
+        for round in 0..5u64 {
+            let first = TestData {
+                timestamp: round * 2,
+                flag: 1,
+            };
+            let second = TestData {
+                timestamp: round * 2 + 1,
+                flag: 2,
+            };
+            tx.send(&first).unwrap();
+            tx.send(&second).unwrap();
+            assert!(matches!(
+                tx.send(&first),
+                Err(GtsTransportError::WouldBlock)
+            ));
+            assert_eq!(rx.try_recv_value().unwrap(), first);
+            let received = rx.try_recv_tagged().unwrap();
+            assert_eq!(*received, second);
+            assert_eq!(received.seqnum(), round * 2 + 2);
+            assert!(matches!(rx.try_recv(), Err(GtsTransportError::WouldBlock)));
+        }
+    }
+
+    #[test]
+    fn test_attach_checks() {
+        let chunk = RawMemChunk::zeroed(required_length::<u64>(10));
+        assert!(matches!(
+            DynSpScRingReceiver::<u64, _>::new(chunk.clone()),
+            Err(GtsTransportError::Unitialized)
+        ));
+        assert!(DynSpScRingSender::<u64, _>::new(chunk.clone(), 11).is_err());
+        let _tx = DynSpScRingSender::<u64, _>::new(chunk.clone(), 10).unwrap();
+        assert!(matches!(
+            DynSpScRingReceiver::<u32, _>::new(chunk.clone()),
+            Err(GtsTransportError::Incompatible(_))
+        ));
+        assert_eq!(
+            DynSpScRingReceiver::<u64, _>::new(chunk)
+                .unwrap()
+                .capacity(),
+            10
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_shmem() {
+        let options = ShmemOptions::new().namespace("test");
+        let name = "dynspsc";
+        let mut tx = DynSpScRingSender::<TestData, _>::create_shmem(name, 3, &options).unwrap();
+        assert!(matches!(
+            DynSpScRingReceiver::<u64, _>::connect_shmem(name, &options),
+            Err(GtsTransportError::Incompatible(_))
+        ));
+        let mut rx = DynSpScRingReceiver::<TestData, _>::connect_shmem(name, &options).unwrap();
+        assert_eq!(rx.capacity(), 3);
+        for timestamp in 0..3 {
+            tx.send(&TestData { timestamp, flag: 0 }).unwrap();
+        }
+        assert!(matches!(
+            tx.send(&TestData::default()),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        for timestamp in 0..3 {
+            assert_eq!(rx.try_recv_value().unwrap().timestamp, timestamp);
+        }
+    }
+}
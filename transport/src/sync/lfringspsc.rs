@@ -1,26 +1,23 @@
+use crate::arch::CachePadded;
 use crate::error::GtsTransportError;
-use crate::membackend::memholder::MemHolder;
+use crate::membackend::memholder::MemHolderMut;
+use crate::sync::atomic::{AtomicU32, Ordering};
+use crate::sync::received::Received;
 use bytemuck::Zeroable;
 use std::mem::MaybeUninit;
-use std::sync::atomic::{AtomicU32, Ordering};
-
-//TODO: use some lib like
-//   https://github.com/lovesegfault/cache-size/blob/master/src/x86.rs
-
-const CACHE_LINE_SIZE: usize = 64;
 
 /// SpScRingData have 2 sections:
 ///     1) read_done_seqnum for writes of reciever, read of sender
 ///     2) write_done_seqnum+data for writes of sender, read of reciever
 ///
-/// to eliminate cache coherence, we must put this data to separate cache lines,
+/// to eliminate cache coherence, we must put this data to separate cache lines
+/// (see [`crate::arch`] for size of line),
 /// In this scenario, we have only 1 core which will write to each cacheline and
 /// this cacheline on this core is always up to date, so there is no invalidate penalty
 /// (by modifying read_done_seqnum) for write to it.
 #[repr(C)]
 pub struct SpScRingData<const RSIZE: usize, T: Copy> {
-    pub read_done_seqnum: AtomicU32,
-    _padding_one: [u8; CACHE_LINE_SIZE - { std::mem::size_of::<AtomicU32>() }],
+    pub read_done_seqnum: CachePadded<AtomicU32>,
     pub write_done_seqnum: AtomicU32,
     // pub data: [MaybeUninit<T>; RSIZE + 1],
     pub data: [MaybeUninit<T>; RSIZE],
@@ -28,13 +25,24 @@ pub struct SpScRingData<const RSIZE: usize, T: Copy> {
 
 unsafe impl<const RSIZE: usize, T: Copy> Zeroable for SpScRingData<RSIZE, T> {}
 
-pub struct SpScRingSender<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>> {
+impl<const RSIZE: usize, T: Copy> Default for SpScRingData<RSIZE, T> {
+    fn default() -> Self {
+        Self {
+            read_done_seqnum: CachePadded::new(AtomicU32::new(0)),
+            write_done_seqnum: AtomicU32::new(0),
+            data: [const { MaybeUninit::uninit() }; RSIZE],
+        }
+    }
+}
+
+pub struct SpScRingSender<const RSIZE: usize, T: Copy, BackT: MemHolderMut<SpScRingData<RSIZE, T>>>
+{
     last_send_seqnum: u32,
     back: BackT,
     _owns_t: std::marker::PhantomData<T>,
 }
 
-impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
+impl<const RSIZE: usize, T: Copy, BackT: MemHolderMut<SpScRingData<RSIZE, T>>>
     SpScRingSender<RSIZE, T, BackT>
 {
     const RING_SIZE: u32 = RSIZE as u32;
@@ -47,6 +55,18 @@ impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
         }
     }
 
+    /// Sender which continues after last value of previous sender of backend,
+    /// e.g. of crashed process, values not read yet are kept.
+    pub fn resume(backend: BackT) -> Self {
+        let pdata = backend.get_mut_ptr();
+        let last_send_seqnum = unsafe { (*pdata).write_done_seqnum.load(Ordering::Acquire) };
+        Self {
+            last_send_seqnum,
+            back: backend,
+            _owns_t: std::marker::PhantomData::<T> {},
+        }
+    }
+
     pub fn send(&mut self, new_data: &T) -> Result<(), GtsTransportError> {
         // SAFETY:
         // only one producer is allowed per backend.
@@ -86,13 +106,18 @@ impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
     }
 }
 
-pub struct SpScRingReceiver<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>> {
+pub struct SpScRingReceiver<
+    const RSIZE: usize,
+    T: Copy,
+    BackT: MemHolderMut<SpScRingData<RSIZE, T>>,
+> {
     back: BackT,
-    last_read_seqnum: Option<u32>,
+    // number of received values, last_copy is initialized iff received > 0.
+    received: u64,
     last_copy: MaybeUninit<T>,
 }
 
-impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
+impl<const RSIZE: usize, T: Copy, BackT: MemHolderMut<SpScRingData<RSIZE, T>>>
     SpScRingReceiver<RSIZE, T, BackT>
 {
     const RING_SIZE: u32 = RSIZE as u32;
@@ -100,26 +125,40 @@ impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
     pub fn new(backend: BackT) -> Self {
         SpScRingReceiver {
             back: backend,
-            last_read_seqnum: None,
+            received: 0,
             last_copy: MaybeUninit::uninit(),
         }
     }
 
-    // TODO: refactor to Option<(u32, MaybeUninit<T>)> without overhead?
-    //       or Option<(u32, &T)> etc. to remove unsafe & ugly code here
     pub fn get_last_value(&self) -> Option<&T> {
-        match self.last_read_seqnum {
-            Some(_) => Some(unsafe { self.last_copy.assume_init_ref() }),
-            None => None,
+        // SAFETY: last_copy is written before received is advanced.
+        match self.received {
+            0 => None,
+            _ => Some(unsafe { self.last_copy.assume_init_ref() }),
         }
     }
 
     pub fn try_recv(&mut self) -> Result<&T, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(self.get_last_value().unwrap())
+    }
+
+    /// Same as [`Self::try_recv`], but returns copy of value.
+    pub fn try_recv_value(&mut self) -> Result<T, GtsTransportError> {
+        self.try_recv().copied()
+    }
+
+    /// Same as [`Self::try_recv`], but value is tagged with number in channel and receive time.
+    pub fn try_recv_tagged(&mut self) -> Result<Received<'_, T>, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(Received::new(self.get_last_value().unwrap(), self.received))
+    }
+
+    fn recv_to_last_copy(&mut self) -> Result<(), GtsTransportError> {
         // SAFETY: we read
         // 1) check read_seqnum != write_seqnum, otherwise return GtsTransportError::WouldBlock
         // 2) read(copy) data from data[write_seqnum]
         // 2) advance read_seqnum
-        //let pdata = unsafe { self.back.get_ref() };
         let pdata = self.back.get_mut_ptr();
 
         let (send_seqnum, read_seqnum) = unsafe {
@@ -128,14 +167,10 @@ impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
             (send_seqnum, read_seqnum)
         };
 
-        // println!("try_recv send_seqnum = {}", send_seqnum);
-        // println!("try_recv read_seqnum = {}", read_seqnum);
-
         if send_seqnum == read_seqnum {
             return Err(GtsTransportError::WouldBlock);
         }
         let next_read = (read_seqnum + 1) % Self::RING_SIZE;
-        // println!("try_recv next_read = {}", next_read);
 
         unsafe {
             std::ptr::copy_nonoverlapping(
@@ -146,11 +181,11 @@ impl<const RSIZE: usize, T: Copy, BackT: MemHolder<SpScRingData<RSIZE, T>>>
 
             (*pdata)
                 .read_done_seqnum
-                .store(next_read as u32, Ordering::Release);
+                .store(next_read, Ordering::Release);
         }
+        self.received += 1;
 
-        let ref_data = unsafe { self.last_copy.assume_init_ref() };
-        Ok(ref_data)
+        Ok(())
     }
 }
 
@@ -162,7 +197,7 @@ pub fn spsc_ring_pair<const RSIZE: usize, T, BackT>(
 )
 where
     T: Copy,
-    BackT: Clone + MemHolder<SpScRingData<RSIZE, T>>,
+    BackT: Clone + MemHolderMut<SpScRingData<RSIZE, T>>,
 {
     (
         SpScRingSender::new(backend.clone()),
@@ -173,6 +208,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arch::CACHE_LINE_SIZE;
     use crate::membackend::memchunk::MemChunkHolder;
 
     #[derive(Copy, Clone, Debug, Default)]
@@ -273,4 +309,28 @@ mod tests {
         let res = rx1.try_recv();
         assert!(matches!(res, Err(GtsTransportError::WouldBlock)));
     }
+
+    #[test]
+    fn test_value_and_tagged() {
+        let (mut tx1, mut rx1) = spsc_ring_pair::<3, u64, _>(MemChunkHolder::zeroed());
+        assert!(rx1.get_last_value().is_none());
+        assert!(matches!(
+            rx1.try_recv_value(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+
+        tx1.send(&11).unwrap();
+        tx1.send(&22).unwrap();
+        assert_eq!(rx1.try_recv_value().unwrap(), 11);
+        let received = rx1.try_recv_tagged().unwrap();
+        assert_eq!(*received, 22);
+        assert_eq!(received.value(), 22);
+        assert_eq!(received.seqnum(), 2);
+        assert!(received.recv_ts() <= minstant::Instant::now());
+        assert!(matches!(
+            rx1.try_recv_tagged(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+        assert_eq!(rx1.get_last_value(), Some(&22));
+    }
 }
@@ -0,0 +1,410 @@
+//! Lock free ring single producer single consumer over two segments, each side maps
+//! writable only what it writes:
+//!  * `{name}.data` - write_done_seqnum and data, written by sender, read only for receiver.
+//!  * `{name}.cursor` - read_done_seqnum, written by receiver, read only for sender.
+//!
+//! So buggy consumer can't corrupt data of producer (and vice versa). Otherwise it's the
+//! same ring as lfringspsc.
+//!
+//! Each side creates own segment and waits (up to timeout) for segment of peer.
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::error::GtsTransportError;
+//! use gts_transport::membackend::shmem::ShmemOptions;
+//! use gts_transport::sync::lfsplitspsc::{SplitSpScReceiver, SplitSpScSender};
+//! use std::time::Duration;
+//!
+//! let options = ShmemOptions::new();
+//! let timeout = Duration::from_secs(1);
+//! let rx_options = options.clone();
+//! let consumer = std::thread::spawn(move || {
+//!     let mut rx = SplitSpScReceiver::<16, u64, _, _>::create_shmem("doc_split", &rx_options, timeout)?;
+//!     loop {
+//!         match rx.try_recv_value() {
+//!             Err(GtsTransportError::WouldBlock) => std::hint::spin_loop(),
+//!             res => return res,
+//!         }
+//!     }
+//! });
+//!
+//! let mut tx = SplitSpScSender::<16, u64, _, _>::create_shmem("doc_split", &options, timeout)?;
+//! tx.send(&42)?;
+//! assert_eq!(consumer.join().unwrap()?, 42);
+//! # Ok::<(), GtsTransportError>(())
+//! ```
+
+use crate::error::GtsTransportError;
+use crate::membackend::memholder::{MemHolder, MemHolderMut, ReadOnly};
+use crate::membackend::shmem::{ShmemHolder, ShmemOptions};
+use crate::sync::atomic::{AtomicU32, Ordering};
+use crate::sync::received::Received;
+use bytemuck::Zeroable;
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+
+const PEER_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Segment of sender: write_done_seqnum+data.
+#[repr(C)]
+pub struct SplitSpScData<const RSIZE: usize, T: Copy> {
+    pub write_done_seqnum: AtomicU32,
+    pub data: [MaybeUninit<T>; RSIZE],
+}
+
+unsafe impl<const RSIZE: usize, T: Copy> Zeroable for SplitSpScData<RSIZE, T> {}
+
+impl<const RSIZE: usize, T: Copy> Default for SplitSpScData<RSIZE, T> {
+    fn default() -> Self {
+        Self {
+            write_done_seqnum: AtomicU32::new(0),
+            data: [const { MaybeUninit::uninit() }; RSIZE],
+        }
+    }
+}
+
+/// Segment of receiver: read_done_seqnum.
+#[repr(C)]
+#[derive(Default)]
+pub struct SplitSpScCursor {
+    pub read_done_seqnum: AtomicU32,
+}
+
+unsafe impl Zeroable for SplitSpScCursor {}
+
+pub fn data_name(name: &str) -> String {
+    format!("{name}.data")
+}
+
+pub fn cursor_name(name: &str) -> String {
+    format!("{name}.cursor")
+}
+
+/// Connects read only to segment of peer, which could be not created yet.
+fn wait_peer<P: Zeroable>(
+    options: &ShmemOptions,
+    name: &str,
+    timeout: Duration,
+) -> Result<ShmemHolder<P, ReadOnly>, GtsTransportError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match options.connect_ro::<P>(name) {
+            Err(GtsTransportError::Unitialized) => {}
+            Err(GtsTransportError::StdIoError(err))
+                if err.kind() == std::io::ErrorKind::NotFound => {}
+            res => return res,
+        }
+        if Instant::now() >= deadline {
+            return Err(GtsTransportError::Timeout);
+        }
+        std::thread::sleep(PEER_POLL_INTERVAL);
+    }
+}
+
+pub struct SplitSpScSender<
+    const RSIZE: usize,
+    T: Copy,
+    DataBackT: MemHolderMut<SplitSpScData<RSIZE, T>>,
+    CursorBackT: MemHolder<SplitSpScCursor>,
+> {
+    last_send_seqnum: u32,
+    data: DataBackT,
+    cursor: CursorBackT,
+    _owns_t: std::marker::PhantomData<T>,
+}
+
+impl<const RSIZE: usize, T, DataBackT, CursorBackT>
+    SplitSpScSender<RSIZE, T, DataBackT, CursorBackT>
+where
+    T: Copy,
+    DataBackT: MemHolderMut<SplitSpScData<RSIZE, T>>,
+    CursorBackT: MemHolder<SplitSpScCursor>,
+{
+    const RING_SIZE: u32 = RSIZE as u32;
+
+    pub fn new(data: DataBackT, cursor: CursorBackT) -> Self {
+        Self {
+            last_send_seqnum: 0,
+            data,
+            cursor,
+            _owns_t: std::marker::PhantomData::<T> {},
+        }
+    }
+
+    pub fn send(&mut self, new_data: &T) -> Result<(), GtsTransportError> {
+        // SAFETY: the same as SpScRingSender::send, read_done_seqnum is in other segment.
+        let pdata = self.data.get_mut_ptr();
+        let pcursor = self.cursor.get_ptr();
+
+        let next_seqnum = (self.last_send_seqnum + 1) % Self::RING_SIZE;
+        let read_seqnum = unsafe { (*pcursor).read_done_seqnum.load(Ordering::Acquire) };
+
+        if read_seqnum == next_seqnum {
+            return Err(GtsTransportError::WouldBlock);
+        }
+
+        self.last_send_seqnum = next_seqnum;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                new_data as *const _,
+                (*pdata).data[next_seqnum as usize].as_mut_ptr(),
+                1,
+            );
+            (*pdata)
+                .write_done_seqnum
+                .store(self.last_send_seqnum, Ordering::Release);
+        }
+
+        Ok(())
+    }
+}
+
+impl<const RSIZE: usize, T: Copy>
+    SplitSpScSender<
+        RSIZE,
+        T,
+        ShmemHolder<SplitSpScData<RSIZE, T>>,
+        ShmemHolder<SplitSpScCursor, ReadOnly>,
+    >
+{
+    /// Creates `{name}.data` segment and waits for `{name}.cursor` of receiver.
+    pub fn create_shmem(
+        name: &str,
+        options: &ShmemOptions,
+        timeout: Duration,
+    ) -> Result<Self, GtsTransportError> {
+        let data = options.create(&data_name(name))?;
+        let cursor = wait_peer(options, &cursor_name(name), timeout)?;
+        Ok(Self::new(data, cursor))
+    }
+
+    /// False if receiver exited (or crashed).
+    pub fn receiver_alive(&self) -> bool {
+        self.cursor.owner_alive()
+    }
+}
+
+pub struct SplitSpScReceiver<
+    const RSIZE: usize,
+    T: Copy,
+    DataBackT: MemHolder<SplitSpScData<RSIZE, T>>,
+    CursorBackT: MemHolderMut<SplitSpScCursor>,
+> {
+    data: DataBackT,
+    cursor: CursorBackT,
+    // number of received values, last_copy is initialized iff received > 0.
+    received: u64,
+    last_copy: MaybeUninit<T>,
+}
+
+impl<const RSIZE: usize, T, DataBackT, CursorBackT>
+    SplitSpScReceiver<RSIZE, T, DataBackT, CursorBackT>
+where
+    T: Copy,
+    DataBackT: MemHolder<SplitSpScData<RSIZE, T>>,
+    CursorBackT: MemHolderMut<SplitSpScCursor>,
+{
+    const RING_SIZE: u32 = RSIZE as u32;
+
+    pub fn new(data: DataBackT, cursor: CursorBackT) -> Self {
+        Self {
+            data,
+            cursor,
+            received: 0,
+            last_copy: MaybeUninit::uninit(),
+        }
+    }
+
+    pub fn get_last_value(&self) -> Option<&T> {
+        // SAFETY: last_copy is written before received is advanced.
+        match self.received {
+            0 => None,
+            _ => Some(unsafe { self.last_copy.assume_init_ref() }),
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<&T, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(self.get_last_value().unwrap())
+    }
+
+    /// Same as [`Self::try_recv`], but returns copy of value.
+    pub fn try_recv_value(&mut self) -> Result<T, GtsTransportError> {
+        self.try_recv().copied()
+    }
+
+    /// Same as [`Self::try_recv`], but value is tagged with number in channel and receive time.
+    pub fn try_recv_tagged(&mut self) -> Result<Received<'_, T>, GtsTransportError> {
+        self.recv_to_last_copy()?;
+        Ok(Received::new(self.get_last_value().unwrap(), self.received))
+    }
+
+    fn recv_to_last_copy(&mut self) -> Result<(), GtsTransportError> {
+        // SAFETY: the same as SpScRingReceiver::try_recv, data segment is only read.
+        let pdata = self.data.get_ptr();
+        let pcursor = self.cursor.get_mut_ptr();
+
+        let (send_seqnum, read_seqnum) = unsafe {
+            let send_seqnum = (*pdata).write_done_seqnum.load(Ordering::Acquire);
+            let read_seqnum = (*pcursor).read_done_seqnum.load(Ordering::Acquire);
+            (send_seqnum, read_seqnum)
+        };
+
+        if send_seqnum == read_seqnum {
+            return Err(GtsTransportError::WouldBlock);
+        }
+        let next_read = (read_seqnum + 1) % Self::RING_SIZE;
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                &(*pdata).data[next_read as usize],
+                &mut self.last_copy as *mut _,
+                1,
+            );
+
+            (*pcursor)
+                .read_done_seqnum
+                .store(next_read, Ordering::Release);
+        }
+        self.received += 1;
+
+        Ok(())
+    }
+}
+
+impl<const RSIZE: usize, T: Copy>
+    SplitSpScReceiver<
+        RSIZE,
+        T,
+        ShmemHolder<SplitSpScData<RSIZE, T>, ReadOnly>,
+        ShmemHolder<SplitSpScCursor>,
+    >
+{
+    /// Creates `{name}.cursor` segment and waits for `{name}.data` of sender.
+    pub fn create_shmem(
+        name: &str,
+        options: &ShmemOptions,
+        timeout: Duration,
+    ) -> Result<Self, GtsTransportError> {
+        let cursor = options.create(&cursor_name(name))?;
+        let data = wait_peer(options, &data_name(name), timeout)?;
+        Ok(Self::new(data, cursor))
+    }
+
+    /// False if sender exited (or crashed).
+    pub fn sender_alive(&self) -> bool {
+        self.data.owner_alive()
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn split_spsc_pair<const RSIZE: usize, T, DataBackT, CursorBackT>(
+    data: DataBackT,
+    cursor: CursorBackT,
+) -> (
+    SplitSpScSender<RSIZE, T, DataBackT, CursorBackT>,
+    SplitSpScReceiver<RSIZE, T, DataBackT, CursorBackT>,
+)
+where
+    T: Copy,
+    DataBackT: Clone + MemHolderMut<SplitSpScData<RSIZE, T>>,
+    CursorBackT: Clone + MemHolderMut<SplitSpScCursor>,
+{
+    (
+        SplitSpScSender::new(data.clone(), cursor.clone()),
+        SplitSpScReceiver::new(data, cursor),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membackend::memchunk::MemChunkHolder;
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    struct TestData {
+        timestamp: u64,
+        flag: u8,
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let (mut tx, mut rx) = split_spsc_pair::<3, TestData, _, _>(
+            MemChunkHolder::zeroed(),
+            MemChunkHolder::zeroed(),
+        );
+        assert!(matches!(rx.try_recv(), Err(GtsTransportError::WouldBlock)));
+
+        for round in 0..5u64 {
+            let first = TestData {
+                timestamp: round,
+                flag: 1,
+            };
+            let second = TestData {
+                timestamp: round,
+                flag: 2,
+            };
+            tx.send(&first).unwrap();
+            tx.send(&second).unwrap();
+            assert!(matches!(
+                tx.send(&first),
+                Err(GtsTransportError::WouldBlock)
+            ));
+            assert_eq!(rx.try_recv_value().unwrap(), first);
+            assert_eq!(rx.try_recv_value().unwrap(), second);
+            assert!(matches!(rx.try_recv(), Err(GtsTransportError::WouldBlock)));
+        }
+        tx.send(&TestData::default()).unwrap();
+        assert_eq!(rx.try_recv_tagged().unwrap().seqnum(), 11);
+        assert_eq!(rx.get_last_value(), Some(&TestData::default()));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_shmem() {
+        let name = "test_split_spsc";
+        let options = ShmemOptions::new();
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(
+            SplitSpScSender::<4, TestData, _, _>::create_shmem(name, &options, timeout),
+            Err(GtsTransportError::Timeout)
+        ));
+
+        let rx_options = options.clone();
+        let receiver = std::thread::spawn(move || {
+            let mut rx = SplitSpScReceiver::<4, TestData, _, _>::create_shmem(
+                name,
+                &rx_options,
+                Duration::from_secs(5),
+            )
+            .unwrap();
+            let mut received = Vec::new();
+            while received.len() < 10 {
+                match rx.try_recv_value() {
+                    Ok(value) => received.push(value.timestamp),
+                    Err(GtsTransportError::WouldBlock) => std::thread::yield_now(),
+                    Err(err) => panic!("{}", err),
+                }
+            }
+            assert!(rx.sender_alive());
+            received
+        });
+
+        let mut tx = SplitSpScSender::<4, TestData, _, _>::create_shmem(
+            name,
+            &options,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        for timestamp in 0..10 {
+            let value = TestData { timestamp, flag: 0 };
+            while let Err(GtsTransportError::WouldBlock) = tx.send(&value) {
+                std::thread::yield_now();
+            }
+        }
+        assert_eq!(receiver.join().unwrap(), (0..10).collect::<Vec<_>>());
+        // receiver is dropped with its segment.
+        assert!(!tx.receiver_alive());
+    }
+}
@@ -4,6 +4,8 @@
 //! use [`SpMcReceiver::try_recv_or_cached()`] to get new or last one from localcopy
 //! (without overhead)
 //! try_recv_or_cached will hang for MAX_ITER_TILL_HANG iters if senders hang with while sending
+//! [`SpMcReceiver::try_recv_value()`] returns copy of value and
+//! [`SpMcReceiver::try_recv_tagged()`] returns value with sender's seqnum and receive time.
 //!
 //! # Examples
 //!
@@ -17,6 +19,9 @@
 //! struct TestData {
 //!     timestamp: u64,
 //! }
+//! // SAFETY: no padding.
+//! unsafe impl bytemuck::NoUninit for TestData {}
+//!
 //! let (mut tx1, mut rx1) = spmc_pair::<TestData, _>(MemChunkHolder::zeroed());
 //! let res = rx1.try_recv();
 //! assert!(matches!(res, Err(GtsTransportError::Unitialized)));
@@ -42,32 +47,49 @@
 //! assert!(matches!(res, Err(GtsTransportError::WouldBlock)));
 //! ```
 
+use crate::arch::CachePadded;
 use crate::error::GtsTransportError;
-use crate::membackend::memholder::MemHolder;
-use bytemuck::Zeroable;
+use crate::membackend::memholder::{Access, MemHolder, MemHolderMut};
+use crate::membackend::shmem::ShmemHolder;
+use crate::sync::atomic::{fence, AtomicU32, Ordering};
+use crate::sync::racycopy::RacyCell;
+use crate::sync::received::Received;
+use bytemuck::{NoUninit, Zeroable};
 use log::debug;
 use std::mem::MaybeUninit;
-use std::sync::atomic::{AtomicU32, Ordering};
 
 const VALUE_BITS: u32 = 1 << 24;
 const GOOD_BIT: u32 = 1 << 24;
 
 #[repr(C)]
 pub struct SpMcData<T: Copy> {
+    // aligns value to cache line, so it doesn't share line with neighbour data.
+    _align: [CachePadded<()>; 0],
     begin: AtomicU32,
-    data: MaybeUninit<T>,
+    data: RacyCell<T>,
     end: AtomicU32,
 }
 
 unsafe impl<T: Copy> Zeroable for SpMcData<T> {}
 
-pub struct SpMcSender<T: Copy, BackT: MemHolder<SpMcData<T>>> {
+impl<T: Copy> Default for SpMcData<T> {
+    fn default() -> Self {
+        Self {
+            _align: [],
+            begin: AtomicU32::new(0),
+            data: RacyCell::new(),
+            end: AtomicU32::new(0),
+        }
+    }
+}
+
+pub struct SpMcSender<T: NoUninit, BackT: MemHolderMut<SpMcData<T>>> {
     seqnum: u32,
     back: BackT,
     _owns_t: std::marker::PhantomData<T>,
 }
 
-impl<T: Copy, BackT: MemHolder<SpMcData<T>>> SpMcSender<T, BackT> {
+impl<T: NoUninit, BackT: MemHolderMut<SpMcData<T>>> SpMcSender<T, BackT> {
     pub fn new(backend: BackT) -> Self {
         Self {
             seqnum: 0,
@@ -76,30 +98,25 @@ impl<T: Copy, BackT: MemHolder<SpMcData<T>>> SpMcSender<T, BackT> {
         }
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn send(&mut self, new_data: &T) -> Result<(), ()> {
         // SAFETY:
         // only one producer is allowed per backend.
         // we write
         // 1. atomic begin.
-        // 2. chunk of data to pdata.data
+        // 2. chunk of data to pdata.data by relaxed atomic words (see racycopy).
         // 3. atomic end.
         // to make reader get proper data from pdata.data.
+        // Release fence keeps data stores after begin store, so reader which sees any of
+        // new data, sees new begin too.
         let pdata = self.back.get_mut_ptr();
 
         self.seqnum = (self.seqnum + 1) % VALUE_BITS;
         let seqnum_to_store = self.seqnum | GOOD_BIT;
-        // use std::intrinsics::volatile_copy_nonoverlapping_memory;
         unsafe {
-            (*pdata).begin.store(seqnum_to_store, Ordering::Release);
-            // write volatile is more correct, but has performance issue.
-            // probably write_volatile doesn't make forget as write does.
-            // TODO: investigate this.
-            // std::ptr::write_volatile((*self.data).data.as_mut_ptr(), *new_data);
-            // std::ptr::write((*self.data).data.as_mut_ptr(), *new_data);
-
-            // added checks from ptr::read to construction.
-            // TODO: replace with https://doc.rust-lang.org/std/intrinsics/fn.volatile_copy_nonoverlapping_memory.html
-            std::ptr::copy_nonoverlapping(new_data as *const _, (*pdata).data.as_mut_ptr(), 1);
+            (*pdata).begin.store(seqnum_to_store, Ordering::Relaxed);
+            fence(Ordering::Release);
+            (*pdata).data.store(new_data);
             (*pdata).end.store(seqnum_to_store, Ordering::Release);
         }
 
@@ -110,6 +127,8 @@ impl<T: Copy, BackT: MemHolder<SpMcData<T>>> SpMcSender<T, BackT> {
 pub struct SpMcReceiver<T: Copy, BackT: MemHolder<SpMcData<T>>> {
     back: BackT,
     last_read_success: Option<u32>,
+    // sender's seqnum of last read, extended to u64 on wrap of VALUE_BITS.
+    seqnum: u64,
     lastcopy: MaybeUninit<T>,
 }
 
@@ -120,12 +139,17 @@ impl<T: Copy, BackT: MemHolder<SpMcData<T>>> SpMcReceiver<T, BackT> {
         Self {
             back: backend,
             last_read_success: None,
+            seqnum: 0,
             lastcopy: MaybeUninit::<_>::uninit(),
         }
     }
 
-    // TODO: refactor to Option<(u32, MaybeUninit<T>)> without overhead?
-    //       or Option<(u32, &T)> etc. to remove unsafe & ugly code here
+    /// Forgets last value, e.g. after reconnect to new sender.
+    pub fn reset(&mut self) {
+        self.last_read_success = None;
+        self.seqnum = 0;
+    }
+
     pub fn get_last_value(&self) -> Option<&T> {
         // SAFETY: lastcopy is only valid last_read_success != None;
         // upheld by the caller.
@@ -151,18 +175,36 @@ impl<T: Copy, BackT: MemHolder<SpMcData<T>>> SpMcReceiver<T, BackT> {
     }
 
     pub fn try_recv(&mut self) -> Result<&T, GtsTransportError> {
+        self.recv_to_lastcopy()?;
+        Ok(self.get_last_value().unwrap())
+    }
+
+    /// Same as [`Self::try_recv`], but returns copy of value.
+    pub fn try_recv_value(&mut self) -> Result<T, GtsTransportError> {
+        self.try_recv().copied()
+    }
+
+    /// Same as [`Self::try_recv`], but value is tagged with sender's seqnum and receive time.
+    pub fn try_recv_tagged(&mut self) -> Result<Received<'_, T>, GtsTransportError> {
+        self.recv_to_lastcopy()?;
+        Ok(Received::new(self.get_last_value().unwrap(), self.seqnum))
+    }
+
+    fn recv_to_lastcopy(&mut self) -> Result<(), GtsTransportError> {
         // SAFETY: we read
         // 1. atomic end
-        // 2. chunk of data to pdata.data
+        // 2. chunk of data to pdata.data by relaxed atomic words (see racycopy).
         // 3. atomic begin.
         // IFF begin == end, we could guarantee, that we read exactly the same bytes as writer
         // writed to pdata.data.
+        // Acquire fence keeps data loads before begin load, pairs with Release fence in send.
         let pdata = self.back.get_ptr();
 
         let (begin, end) = unsafe {
             let end = (*pdata).end.load(Ordering::Acquire);
-            std::ptr::copy_nonoverlapping(&(*pdata).data, &mut self.lastcopy as *mut _, 1);
-            let begin = (*pdata).begin.load(Ordering::Acquire);
+            (*pdata).data.load(self.lastcopy.as_mut_ptr());
+            fence(Ordering::Acquire);
+            let begin = (*pdata).begin.load(Ordering::Relaxed);
             (begin, end)
         };
 
@@ -180,23 +222,41 @@ impl<T: Copy, BackT: MemHolder<SpMcData<T>>> SpMcReceiver<T, BackT> {
             return Err(GtsTransportError::Unitialized);
         }
 
-        // same as self.get_last_value().unwrap();
-        let ref_data = unsafe { self.lastcopy.assume_init_ref() };
-
         if Some(seqnum) == self.last_read_success {
             return Err(GtsTransportError::WouldBlock);
         }
 
         self.last_read_success = Some(seqnum);
+        let epoch = self.seqnum & !(VALUE_BITS as u64 - 1);
+        let mut extended = epoch | (seqnum & !GOOD_BIT) as u64;
+        if extended < self.seqnum {
+            extended += VALUE_BITS as u64;
+        }
+        self.seqnum = extended;
+
+        Ok(())
+    }
+}
+
+impl<T: Copy, A: Access> SpMcReceiver<T, ShmemHolder<SpMcData<T>, A>> {
+    /// Reconnects to segment of restarted sender, see [`ShmemHolder::reconnect`].
+    pub fn reconnect(&mut self) -> Result<bool, GtsTransportError> {
+        let reconnected = self.back.reconnect()?;
+        if reconnected {
+            self.reset();
+        }
+        Ok(reconnected)
+    }
 
-        Ok(ref_data)
+    pub fn sender_alive(&self) -> bool {
+        self.back.owner_alive()
     }
 }
 
 pub fn spmc_pair_def<T, BackT>() -> (SpMcSender<T, BackT>, SpMcReceiver<T, BackT>)
 where
-    T: Copy,
-    BackT: Clone + Default + MemHolder<SpMcData<T>>,
+    T: NoUninit,
+    BackT: Clone + Default + MemHolderMut<SpMcData<T>>,
 {
     let backend: BackT = Default::default();
     (SpMcSender::new(backend.clone()), SpMcReceiver::new(backend))
@@ -204,8 +264,8 @@ where
 
 pub fn spmc_pair<T, BackT>(backend: BackT) -> (SpMcSender<T, BackT>, SpMcReceiver<T, BackT>)
 where
-    T: Copy,
-    BackT: Clone + MemHolder<SpMcData<T>>,
+    T: NoUninit,
+    BackT: Clone + MemHolderMut<SpMcData<T>>,
 {
     (SpMcSender::new(backend.clone()), SpMcReceiver::new(backend))
 }
@@ -214,14 +274,17 @@ where
 mod tests {
     use super::*;
     use crate::membackend::memchunk::MemChunkHolder;
-    use crate::membackend::shmem::ShmemHolder;
 
     #[derive(Copy, Debug, Clone, Default)]
     struct TestData {
         timestamp: u64,
     }
 
+    // SAFETY: no padding.
+    unsafe impl NoUninit for TestData {}
+
     #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
     fn test_simple_ping() {
         let shmem_name = "testtx1simple";
         let mut tx1 = SpMcSender::<TestData, _>::new(ShmemHolder::create(shmem_name));
@@ -247,6 +310,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
     fn test_simple_ping_with_unhang() {
         let shmem_name = "testtx1simpleunhang";
         let mut tx1 = SpMcSender::<TestData, _>::new(ShmemHolder::create(shmem_name));
@@ -295,6 +359,103 @@ mod tests {
     }
 
     #[test]
+    fn test_value_and_tagged() {
+        let (mut tx1, mut rx1) = spmc_pair::<TestData, _>(MemChunkHolder::zeroed());
+        assert!(matches!(
+            rx1.try_recv_value(),
+            Err(GtsTransportError::Unitialized)
+        ));
+
+        tx1.send(&TestData { timestamp: 1 }).unwrap();
+        assert_eq!(rx1.try_recv_value().unwrap().timestamp, 1);
+        tx1.send(&TestData { timestamp: 2 }).unwrap();
+        tx1.send(&TestData { timestamp: 3 }).unwrap();
+        let received = rx1.try_recv_tagged().unwrap();
+        assert_eq!(received.timestamp, 3);
+        assert_eq!(received.seqnum(), 3);
+        assert!(matches!(
+            rx1.try_recv_tagged(),
+            Err(GtsTransportError::WouldBlock)
+        ));
+
+        // sender's seqnum wraps, receiver's one keeps growing.
+        tx1.seqnum = VALUE_BITS - 1;
+        tx1.send(&TestData { timestamp: 4 }).unwrap();
+        assert_eq!(rx1.try_recv_tagged().unwrap().seqnum(), VALUE_BITS as u64);
+        tx1.send(&TestData { timestamp: 5 }).unwrap();
+        assert_eq!(
+            rx1.try_recv_tagged().unwrap().seqnum(),
+            VALUE_BITS as u64 + 1
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
+    fn test_reconnect() {
+        let shmem_name = "testtx1reconnect";
+        let mut tx1 = SpMcSender::<TestData, _>::new(ShmemHolder::create(shmem_name));
+        let mut rx1 = SpMcReceiver::<TestData, _>::new(ShmemHolder::connect_ro(shmem_name));
+        tx1.send(&TestData { timestamp: 1 }).unwrap();
+        assert_eq!(rx1.try_recv_value().unwrap().timestamp, 1);
+        assert!(rx1.sender_alive());
+        assert!(!rx1.reconnect().unwrap());
+
+        drop(tx1);
+        assert!(!rx1.sender_alive());
+        assert!(rx1.reconnect().is_err());
+
+        let mut tx1 = SpMcSender::<TestData, _>::new(ShmemHolder::create(shmem_name));
+        assert!(rx1.reconnect().unwrap());
+        assert!(rx1.get_last_value().is_none());
+        assert!(matches!(
+            rx1.try_recv(),
+            Err(GtsTransportError::Unitialized)
+        ));
+        tx1.send(&TestData { timestamp: 2 }).unwrap();
+        assert_eq!(rx1.try_recv_value().unwrap().timestamp, 2);
+    }
+
+    #[test]
+    fn test_no_torn_reads() {
+        #[derive(Copy, Clone, Debug)]
+        struct Pair {
+            first: u64,
+            second: u64,
+        }
+
+        // SAFETY: no padding.
+        unsafe impl NoUninit for Pair {}
+
+        let (mut tx1, mut rx1) = spmc_pair::<Pair, _>(MemChunkHolder::zeroed());
+        let total = if cfg!(miri) { 50 } else { 10_000 };
+
+        let writer = std::thread::spawn(move || {
+            for i in 1..=total {
+                tx1.send(&Pair {
+                    first: i,
+                    second: i,
+                })
+                .unwrap();
+                std::thread::yield_now();
+            }
+        });
+
+        let mut last = 0;
+        while last != total {
+            match rx1.try_recv() {
+                Ok(val) => {
+                    assert_eq!(val.first, val.second);
+                    assert!(val.first > last);
+                    last = val.first;
+                }
+                Err(_) => std::thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // shm_open is not supported by miri.
     fn test_heavy_pingpong() {
         //        let mut rng = rand::thread_rng();
         // The first call will take some time for calibartion
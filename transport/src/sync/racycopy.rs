@@ -0,0 +1,197 @@
+//! Copies of data, which could be concurrently written by other side (seqlock in lfspmc).
+//! Plain copy of such data is a data race by the Rust memory model, even if result is
+//! dropped, when reader detects inconsistency.
+//!
+//! Both sides copy data by relaxed `AtomicU64` loads/stores (`AtomicU8` for unaligned
+//! head and tail), so there is no data race, only a possibly torn value, which is detected
+//! by seqlock. Ordering with seqlock counters is made by fences on caller side:
+//!  * writer: counter store, `fence(Release)`, [`racy_store`], counter store (Release).
+//!  * reader: counter load (Acquire), [`racy_load`], `fence(Acquire)`, counter load.
+//!
+//! On x86_64 relaxed loads/stores are plain movs, cost is only lost vectorization of copy,
+//! see `racycopy` group in benches/criterion.rs.
+//!
+//! Bytes of T are copied as integers, so stored T must be [`NoUninit`] (no padding),
+//! reading uninitialized padding as integer is UB.
+//!
+//! [`RacyCell`] holds such data. Under loom it's a set of loom `AtomicU64` words instead,
+//! so model checker interleaves copies of single words and torn reads are reachable.
+//!
+//! See also https://doc.rust-lang.org/reference/behavior-considered-undefined.html
+//! and https://github.com/rust-lang/rfcs/pull/3301
+
+use bytemuck::NoUninit;
+#[cfg(not(loom))]
+use std::cell::UnsafeCell;
+#[cfg(not(loom))]
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+const WORD: usize = std::mem::size_of::<u64>();
+
+/// Splits len bytes at addr to (head, words, tail), so addr + head is aligned for u64.
+#[inline(always)]
+fn split(addr: usize, len: usize) -> (usize, usize, usize) {
+    let head = (addr.wrapping_neg() % WORD).min(len);
+    let words = (len - head) / WORD;
+    (head, words, len - head - words * WORD)
+}
+
+/// Copies one T from src, which could be concurrently written by [`racy_store`].
+///
+/// # Safety
+/// src and dst must be valid for `size_of::<T>()` bytes, dst must not overlap src.
+/// dst could hold torn value, it's valid T only if no concurrent store happened.
+#[inline(always)]
+pub unsafe fn racy_load<T>(src: *const T, dst: *mut T) {
+    let src = src as *mut u8;
+    let dst = dst as *mut u8;
+    let (head, words, tail) = split(src as usize, std::mem::size_of::<T>());
+
+    for i in 0..head {
+        *dst.add(i) = AtomicU8::from_ptr(src.add(i)).load(Ordering::Relaxed);
+    }
+    for i in 0..words {
+        let off = head + i * WORD;
+        let word = AtomicU64::from_ptr(src.add(off) as *mut u64).load(Ordering::Relaxed);
+        std::ptr::write_unaligned(dst.add(off) as *mut u64, word);
+    }
+    for i in head + words * WORD..head + words * WORD + tail {
+        *dst.add(i) = AtomicU8::from_ptr(src.add(i)).load(Ordering::Relaxed);
+    }
+}
+
+/// Copies one T to dst, which could be concurrently read by [`racy_load`].
+///
+/// # Safety
+/// src and dst must be valid for `size_of::<T>()` bytes, dst must not overlap src.
+#[inline(always)]
+pub unsafe fn racy_store<T: NoUninit>(src: *const T, dst: *mut T) {
+    let src = src as *const u8;
+    let dst = dst as *mut u8;
+    let (head, words, tail) = split(dst as usize, std::mem::size_of::<T>());
+
+    for i in 0..head {
+        AtomicU8::from_ptr(dst.add(i)).store(*src.add(i), Ordering::Relaxed);
+    }
+    for i in 0..words {
+        let off = head + i * WORD;
+        let word = std::ptr::read_unaligned(src.add(off) as *const u64);
+        AtomicU64::from_ptr(dst.add(off) as *mut u64).store(word, Ordering::Relaxed);
+    }
+    for i in head + words * WORD..head + words * WORD + tail {
+        AtomicU8::from_ptr(dst.add(i)).store(*src.add(i), Ordering::Relaxed);
+    }
+}
+
+/// One T, which could be stored by one writer and loaded by readers concurrently.
+#[cfg(not(loom))]
+#[repr(transparent)]
+pub struct RacyCell<T>(UnsafeCell<MaybeUninit<T>>);
+
+#[cfg(not(loom))]
+impl<T> RacyCell<T> {
+    pub fn new() -> Self {
+        RacyCell(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+
+    /// # Safety
+    /// dst must be valid for `size_of::<T>()` bytes, see [`racy_load`].
+    #[inline(always)]
+    pub unsafe fn load(&self, dst: *mut T) {
+        racy_load(self.0.get() as *const T, dst)
+    }
+}
+
+#[cfg(not(loom))]
+impl<T: NoUninit> RacyCell<T> {
+    /// # Safety
+    /// only one writer is allowed.
+    #[inline(always)]
+    pub unsafe fn store(&self, src: &T) {
+        racy_store(src, self.0.get() as *mut T)
+    }
+}
+
+/// Model of [`RacyCell`] for loom, copied by words.
+#[cfg(loom)]
+pub struct RacyCell<T> {
+    words: Box<[loom::sync::atomic::AtomicU64]>,
+    _owns_t: std::marker::PhantomData<T>,
+}
+
+#[cfg(loom)]
+impl<T> RacyCell<T> {
+    pub fn new() -> Self {
+        let len = std::mem::size_of::<T>().div_ceil(WORD);
+        RacyCell {
+            words: (0..len)
+                .map(|_| loom::sync::atomic::AtomicU64::new(0))
+                .collect(),
+            _owns_t: std::marker::PhantomData,
+        }
+    }
+
+    /// # Safety
+    /// dst must be valid for `size_of::<T>()` bytes.
+    pub unsafe fn load(&self, dst: *mut T) {
+        let dst = dst as *mut u8;
+        let size = std::mem::size_of::<T>();
+        for (i, word) in self.words.iter().enumerate() {
+            let bytes = word.load(Ordering::Relaxed).to_ne_bytes();
+            let len = WORD.min(size - i * WORD);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst.add(i * WORD), len);
+        }
+    }
+}
+
+#[cfg(loom)]
+impl<T: NoUninit> RacyCell<T> {
+    /// # Safety
+    /// only one writer is allowed.
+    pub unsafe fn store(&self, src: &T) {
+        let src = src as *const T as *const u8;
+        let size = std::mem::size_of::<T>();
+        for (i, word) in self.words.iter().enumerate() {
+            let mut bytes = [0u8; WORD];
+            let len = WORD.min(size - i * WORD);
+            std::ptr::copy_nonoverlapping(src.add(i * WORD), bytes.as_mut_ptr(), len);
+            word.store(u64::from_ne_bytes(bytes), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T> Default for RacyCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(0, 24), (0, 3, 0));
+        assert_eq!(split(4, 24), (4, 2, 4));
+        assert_eq!(split(7, 3), (1, 0, 2));
+        assert_eq!(split(5, 2), (2, 0, 0));
+    }
+
+    #[test]
+    fn test_unaligned_copy() {
+        let src: Vec<u8> = (0..64).collect();
+        for offset in 0..WORD {
+            let mut shared = [0u8; 80];
+            let shared_ptr = shared[offset..].as_mut_ptr() as *mut [u8; 31];
+            let mut dst = [0u8; 31];
+            unsafe {
+                racy_store(src[1..].as_ptr() as *const [u8; 31], shared_ptr);
+                racy_load(shared_ptr, &mut dst);
+            }
+            assert_eq!(&dst[..], &src[1..32]);
+            assert_eq!(&shared[offset..offset + 31], &src[1..32]);
+        }
+    }
+}
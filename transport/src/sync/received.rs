@@ -0,0 +1,48 @@
+//! Guard returned by `try_recv_tagged` of receivers: reference to received value
+//! with its sequence number and receive time.
+//!
+//! Sequence number semantic depends on primitive:
+//!  * lfringspsc - number of value in the channel, starts from 1, has no gaps.
+//!  * lfspmc - sender's number of value, gaps mean values replaced before read.
+
+use std::ops::Deref;
+
+#[derive(Debug)]
+pub struct Received<'a, T> {
+    value: &'a T,
+    seqnum: u64,
+    recv_ts: minstant::Instant,
+}
+
+impl<'a, T> Received<'a, T> {
+    pub(crate) fn new(value: &'a T, seqnum: u64) -> Self {
+        Self {
+            value,
+            seqnum,
+            recv_ts: minstant::Instant::now(),
+        }
+    }
+
+    pub fn seqnum(&self) -> u64 {
+        self.seqnum
+    }
+
+    /// Time of receive, use `as_unix_nanos` to get wall clock time.
+    pub fn recv_ts(&self) -> minstant::Instant {
+        self.recv_ts
+    }
+}
+
+impl<T: Copy> Received<'_, T> {
+    pub fn value(&self) -> T {
+        *self.value
+    }
+}
+
+impl<T> Deref for Received<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
@@ -0,0 +1,269 @@
+//! Spawn of latency critical threads: named, pinned to core, optionally with `SCHED_FIFO`
+//! priority and minimal timer slack (so sleeps are not coalesced by kernel).
+//!
+//! Settings are applied by spawned thread itself before user closure is called, failure of
+//! any of them is returned by [`ThreadBuilder::spawn`] (and closure is not called).
+//! [`ThreadBuilder::require_isolated`] checks, that core is isolated from scheduler
+//! (`isolcpus=` kernel option, see [`isolated_cpus`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use gts_transport::thread::ThreadBuilder;
+//!
+//! // first core allowed for the process (e.g. by taskset or cgroup).
+//! let core = core_affinity::get_core_ids().unwrap()[0].id;
+//! let handle = ThreadBuilder::new()
+//!     .name("md_feed")
+//!     .core(core)
+//!     .no_timer_slack()
+//!     .spawn(|| 42)?;
+//! assert_eq!(handle.thread().name(), Some("md_feed"));
+//! assert_eq!(handle.join().unwrap(), 42);
+//!
+//! // not existing core.
+//! assert!(ThreadBuilder::new().core(100_000).spawn(|| ()).is_err());
+//! # Ok::<(), gts_transport::error::GtsTransportError>(())
+//! ```
+
+use crate::error::GtsTransportError;
+use libc::{cpu_set_t, sched_param, CPU_SET, CPU_SETSIZE, CPU_ZERO, PR_SET_TIMERSLACK, SCHED_FIFO};
+use std::sync::mpsc;
+use std::thread::{JoinHandle, Thread};
+
+const SYSFS_ISOLATED: &str = "/sys/devices/system/cpu/isolated";
+
+#[derive(Debug, Clone, Default)]
+pub struct ThreadBuilder {
+    name: Option<String>,
+    core: Option<usize>,
+    fifo_priority: Option<i32>,
+    timer_slack_ns: Option<u64>,
+    require_isolated: bool,
+    stack_size: Option<usize>,
+}
+
+/// Handle of thread spawned by [`ThreadBuilder::spawn`].
+#[derive(Debug)]
+pub struct ThreadHandle<R> {
+    // None only if setup failed, such handle is never returned.
+    inner: JoinHandle<Option<R>>,
+}
+
+impl<R> ThreadHandle<R> {
+    pub fn thread(&self) -> &Thread {
+        self.inner.thread()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    pub fn join(self) -> std::thread::Result<R> {
+        self.inner
+            .join()
+            .map(|res| res.expect("thread is started after setup"))
+    }
+}
+
+impl ThreadBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Pins thread to core.
+    pub fn core(mut self, core: usize) -> Self {
+        self.core = Some(core);
+        self
+    }
+
+    /// `SCHED_FIFO` with priority (1..=99), needs CAP_SYS_NICE (or rtprio limit).
+    pub fn fifo_priority(mut self, priority: i32) -> Self {
+        self.fifo_priority = Some(priority);
+        self
+    }
+
+    pub fn timer_slack_ns(mut self, slack: u64) -> Self {
+        self.timer_slack_ns = Some(slack);
+        self
+    }
+
+    /// Minimal timer slack (1ns), default one is 50us.
+    pub fn no_timer_slack(self) -> Self {
+        self.timer_slack_ns(1)
+    }
+
+    /// Fails, if core is not isolated.
+    pub fn require_isolated(mut self, require: bool) -> Self {
+        self.require_isolated = require;
+        self
+    }
+
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Spawns thread, returns error if any setting can't be applied.
+    pub fn spawn<F, R>(self, f: F) -> Result<ThreadHandle<R>, GtsTransportError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.check_isolated()?;
+
+        let mut builder = std::thread::Builder::new();
+        if let Some(name) = &self.name {
+            builder = builder.name(name.clone());
+        }
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+
+        let (setup_tx, setup_rx) = mpsc::sync_channel(1);
+        let inner = builder.spawn(move || {
+            let setup = self.apply_to_current();
+            let ok = setup.is_ok();
+            let _ = setup_tx.send(setup);
+            ok.then(f)
+        })?;
+
+        match setup_rx.recv() {
+            Ok(Ok(())) => Ok(ThreadHandle { inner }),
+            Ok(Err(err)) => {
+                let _ = inner.join();
+                Err(err)
+            }
+            Err(_) => Err(GtsTransportError::CommonError(
+                "thread exited before setup".to_string(),
+            )),
+        }
+    }
+
+    /// Applies settings (except name and stack size) to current thread, e.g. to main one.
+    pub fn apply_to_current(&self) -> Result<(), GtsTransportError> {
+        self.check_isolated()?;
+        if let Some(core) = self.core {
+            set_affinity(core)?;
+        }
+        if let Some(slack) = self.timer_slack_ns {
+            if unsafe { libc::prctl(PR_SET_TIMERSLACK, slack as libc::c_ulong) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        if let Some(priority) = self.fifo_priority {
+            let param = sched_param {
+                sched_priority: priority,
+            };
+            // returns error number instead of errno.
+            let ret =
+                unsafe { libc::pthread_setschedparam(libc::pthread_self(), SCHED_FIFO, &param) };
+            if ret != 0 {
+                return Err(std::io::Error::from_raw_os_error(ret).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_isolated(&self) -> Result<(), GtsTransportError> {
+        match self.core {
+            Some(core) if self.require_isolated && !isolated_cpus()?.contains(&core) => Err(
+                GtsTransportError::CommonError(format!("core {core} is not isolated")),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn set_affinity(core: usize) -> Result<(), GtsTransportError> {
+    if core >= CPU_SETSIZE as usize {
+        return Err(GtsTransportError::LogicError(format!("bad core {core}")));
+    }
+    // SAFETY: cpu_set_t is plain bitmask, core is checked against its size.
+    unsafe {
+        let mut set = std::mem::zeroed::<cpu_set_t>();
+        CPU_ZERO(&mut set);
+        CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Isolated cores (`isolcpus=` kernel option), empty if there are no ones.
+pub fn isolated_cpus() -> Result<Vec<usize>, GtsTransportError> {
+    parse_cpu_list(&std::fs::read_to_string(SYSFS_ISOLATED)?)
+}
+
+/// Parses kernel cpu list, e.g. "2-5,7".
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>, GtsTransportError> {
+    let bad_list = || GtsTransportError::LogicError(format!("bad cpu list {list}"));
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: usize = first.parse().map_err(|_| bad_list())?;
+        let last: usize = last.parse().map_err(|_| bad_list())?;
+        if first > last {
+            return Err(bad_list());
+        }
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("\n").unwrap(), Vec::<usize>::new());
+        assert_eq!(parse_cpu_list("3\n").unwrap(), vec![3]);
+        assert_eq!(parse_cpu_list("2-5,7").unwrap(), vec![2, 3, 4, 5, 7]);
+        assert!(parse_cpu_list("5-2").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sched_setaffinity is not supported by miri.
+    fn test_spawn() {
+        let core = core_affinity::get_core_ids().unwrap()[0].id;
+        let handle = ThreadBuilder::new()
+            .name("test_spawn")
+            .core(core)
+            .no_timer_slack()
+            .spawn(|| unsafe { libc::prctl(libc::PR_GET_TIMERSLACK) })
+            .unwrap();
+        assert_eq!(handle.join().unwrap(), 1);
+
+        let called = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let called_clone = called.clone();
+        let res = ThreadBuilder::new()
+            .core(CPU_SETSIZE as usize)
+            .spawn(move || called_clone.store(true, std::sync::atomic::Ordering::Relaxed));
+        assert!(matches!(res, Err(GtsTransportError::LogicError(_))));
+        assert!(!called.load(std::sync::atomic::Ordering::Relaxed));
+
+        // priority out of 1..=99 is rejected even with CAP_SYS_NICE.
+        let res = ThreadBuilder::new().fifo_priority(1000).spawn(|| ());
+        assert!(matches!(res, Err(GtsTransportError::StdIoError(_))));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // sysfs is not supported by miri.
+    fn test_require_isolated() {
+        let isolated = isolated_cpus().unwrap();
+        let not_isolated = (0..).find(|core| !isolated.contains(core)).unwrap();
+        let res = ThreadBuilder::new()
+            .core(not_isolated)
+            .require_isolated(true)
+            .spawn(|| ());
+        assert!(matches!(res, Err(GtsTransportError::CommonError(_))));
+    }
+}
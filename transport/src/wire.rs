@@ -0,0 +1,117 @@
+//! Helpers to move Copy values through byte streams (network, files).
+//! Values are copied as is (native layout and endianness), so both sides must be
+//! built with the same definition of T, checked by [`fingerprint`].
+//! Written types must be [`NoUninit`]: padding bytes are uninitialized, reading them is UB
+//! and would leak memory contents to peer.
+//! Read types must be [`CheckedBitPattern`]: bytes come from peer or file and are not trusted,
+//! e.g. invalid bool or enum discriminant is rejected instead of UB.
+
+use crate::error::GtsTransportError;
+use bytemuck::{CheckedBitPattern, NoUninit};
+
+/// FNV-1a 64 hash.
+pub const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Type fingerprint: hash of type name, size and alignment.
+/// It's not a schema check, but catches most of mismatches between peers.
+pub fn fingerprint<T>() -> u64 {
+    let mut hash = fnv1a(std::any::type_name::<T>().as_bytes());
+    for val in [std::mem::size_of::<T>(), std::mem::align_of::<T>()] {
+        hash ^= fnv1a(&(val as u64).to_le_bytes());
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Copies bytes of value to the beginning of dst.
+///
+/// # Panics
+/// if dst is shorter than `size_of::<T>()`.
+pub fn write_value<T: NoUninit>(value: &T, dst: &mut [u8]) {
+    let bytes = bytemuck::bytes_of(value);
+    dst[..bytes.len()].copy_from_slice(bytes);
+}
+
+/// Reads value from the beginning of src,
+/// returns DecodeError if bytes are not valid value of T.
+///
+/// # Panics
+/// if src is shorter than `size_of::<T>()`.
+pub fn read_value<T: CheckedBitPattern>(src: &[u8]) -> Result<T, GtsTransportError> {
+    bytemuck::checked::try_pod_read_unaligned(&src[..std::mem::size_of::<T>()]).map_err(|err| {
+        GtsTransportError::DecodeError(format!("{} of {}", err, std::any::type_name::<T>()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct TestData {
+        timestamp: u64,
+        flag: bool,
+        _pad: [u8; 7],
+    }
+
+    // SAFETY: no padding, bool is NoUninit.
+    unsafe impl NoUninit for TestData {}
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct TestDataBits {
+        _timestamp: u64,
+        flag: u8,
+        _pad: [u8; 7],
+    }
+
+    // SAFETY: the same layout as TestData, any bytes are valid.
+    unsafe impl bytemuck::Zeroable for TestDataBits {}
+    unsafe impl bytemuck::AnyBitPattern for TestDataBits {}
+
+    // SAFETY: Bits has the same layout, flag is checked.
+    unsafe impl CheckedBitPattern for TestData {
+        type Bits = TestDataBits;
+
+        fn is_valid_bit_pattern(bits: &TestDataBits) -> bool {
+            bits.flag <= 1
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut buf = [0u8; 64];
+        let val = TestData {
+            timestamp: 123,
+            flag: true,
+            _pad: [0; 7],
+        };
+        write_value(&val, &mut buf[1..]);
+        assert_eq!(read_value::<TestData>(&buf[1..]).unwrap(), val);
+
+        // invalid bool.
+        buf[9] = 2;
+        assert!(matches!(
+            read_value::<TestData>(&buf[1..]),
+            Err(GtsTransportError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint::<TestData>(), fingerprint::<TestData>());
+        assert_ne!(fingerprint::<TestData>(), fingerprint::<u64>());
+        assert_ne!(fingerprint::<[u8; 3]>(), fingerprint::<[u8; 4]>());
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+    }
+}
@@ -0,0 +1,76 @@
+//! Model checking of lock free primitives orderings.
+//!
+//! ```bash
+//! RUSTFLAGS="--cfg loom" cargo test -p gts-transport --test loom --release
+//! ```
+#![cfg(loom)]
+
+use gts_transport::error::GtsTransportError;
+use gts_transport::membackend::memchunk::MemChunkHolder;
+use gts_transport::sync::lfringspsc::spsc_ring_pair;
+use gts_transport::sync::lfspmc::spmc_pair;
+
+#[derive(Copy, Clone, Debug)]
+struct Pair {
+    first: u64,
+    second: u64,
+}
+
+// SAFETY: no padding.
+unsafe impl bytemuck::NoUninit for Pair {}
+
+#[test]
+fn spsc_ring_wrap_around() {
+    loom::model(|| {
+        // 3 slots ring keeps 2 values, so 4 values wraps it.
+        let (mut tx, mut rx) = spsc_ring_pair::<3, u64, _>(MemChunkHolder::default());
+
+        let writer = loom::thread::spawn(move || {
+            for i in 1..=4 {
+                while let Err(err) = tx.send(&i) {
+                    assert!(matches!(err, GtsTransportError::WouldBlock));
+                    loom::thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 1;
+        while expected <= 4 {
+            match rx.try_recv() {
+                Ok(val) => {
+                    assert_eq!(*val, expected);
+                    expected += 1;
+                }
+                Err(_) => loom::thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+    });
+}
+
+/// Payload is copied by loom atomic words (see `racycopy::RacyCell`), so interleaved
+/// copies produce torn values, which must be rejected by seqlock recheck.
+#[test]
+fn spmc_no_torn_reads() {
+    loom::model(|| {
+        let (mut tx, mut rx) = spmc_pair::<Pair, _>(MemChunkHolder::default());
+
+        let writer = loom::thread::spawn(move || {
+            for i in 1..=2 {
+                tx.send(&Pair {
+                    first: i,
+                    second: i,
+                })
+                .unwrap();
+            }
+        });
+
+        for _ in 0..2 {
+            match rx.try_recv() {
+                Ok(val) => assert_eq!(val.first, val.second),
+                Err(_) => loom::thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+    });
+}